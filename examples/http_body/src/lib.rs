@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use proxy_wasm::body::*;
 use proxy_wasm::traits::*;
 use proxy_wasm::types::*;
 
@@ -30,40 +31,51 @@ impl RootContext for HttpBodyRoot {
     }
 
     fn create_http_context(&self, _: u32) -> Option<Box<dyn HttpContext>> {
-        Some(Box::new(HttpBody))
+        Some(Box::new(HttpBody {
+            response_body: BodyFilter::response(Redact),
+        }))
     }
 }
 
-struct HttpBody;
+struct Redact;
+
+impl BodyProcessor for Redact {
+    fn mode(&self) -> BodyMode {
+        // Buffer the complete body at the host side, up to 1 MiB.
+        BodyMode::Buffer { max_size: 1 << 20 }
+    }
+
+    fn on_body(&mut self, body: &[u8]) -> Option<Bytes> {
+        // Replace the message body if it contains the text "secret".
+        let body_str = String::from_utf8_lossy(body);
+        if body_str.contains("secret") {
+            let new_body = format!("Original message body ({} bytes) redacted.\n", body.len());
+            Some(new_body.into_bytes())
+        } else {
+            None
+        }
+    }
+}
+
+struct HttpBody {
+    response_body: BodyFilter<Redact>,
+}
 
 impl Context for HttpBody {}
 
 impl HttpContext for HttpBody {
-    fn on_http_response_headers(&mut self, _: usize, _: bool) -> Action {
+    fn on_http_response_headers(&mut self, _: usize, end_of_stream: bool) -> Action {
         // If there is a Content-Length header and we change the length of
-        // the body later, then clients will break. So remove it.
-        // We must do this here, because once we exit this function we
-        // can no longer modify the response headers.
-        self.set_http_response_header("content-length", None);
-        Action::Continue
+        // the body later, then clients will break. So hold the headers until
+        // the body is complete, at which point Content-Length is updated.
+        self.response_body.on_headers(end_of_stream)
     }
 
     fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
-        if !end_of_stream {
-            // Wait -- we'll be called again when the complete body is buffered
-            // at the host side.
-            return Action::Pause;
-        }
+        self.response_body.on_body(body_size, end_of_stream)
+    }
 
-        // Replace the message body if it contains the text "secret".
-        // Since we returned "Pause" previuously, this will return the whole body.
-        if let Some(body_bytes) = self.get_http_response_body(0, body_size) {
-            let body_str = String::from_utf8(body_bytes).unwrap();
-            if body_str.contains("secret") {
-                let new_body = format!("Original message body ({body_size} bytes) redacted.\n");
-                self.set_http_response_body(0, body_size, &new_body.into_bytes());
-            }
-        }
-        Action::Continue
+    fn on_http_response_trailers(&mut self, _: usize) -> Action {
+        self.response_body.on_trailers()
     }
 }
@@ -12,52 +12,50 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use proxy_wasm::config::*;
 use proxy_wasm::traits::*;
 use proxy_wasm::types::*;
+use serde::Deserialize;
+use std::rc::Rc;
 
 proxy_wasm::main! {{
     proxy_wasm::set_log_level(LogLevel::Trace);
-    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
-        Box::new(HttpConfigHeaderRoot {
-            header_content: String::new(),
-        })
-    });
+    proxy_wasm::set_root_context(|_| HttpConfigHeaderRoot.into_root_context());
 }}
 
+#[derive(Deserialize)]
+struct Config {
+    header_name: String,
+    header_value: String,
+}
+
 struct HttpConfigHeader {
-    header_content: String,
+    config: Rc<Config>,
 }
 
 impl Context for HttpConfigHeader {}
 
 impl HttpContext for HttpConfigHeader {
     fn on_http_response_headers(&mut self, _: usize, _: bool) -> Action {
-        self.add_http_response_header("custom-header", self.header_content.as_str());
+        self.add_http_response_header(&self.config.header_name, &self.config.header_value);
         Action::Continue
     }
 }
 
-struct HttpConfigHeaderRoot {
-    header_content: String,
-}
+struct HttpConfigHeaderRoot;
 
 impl Context for HttpConfigHeaderRoot {}
 
-impl RootContext for HttpConfigHeaderRoot {
-    fn on_configure(&mut self, _: usize) -> bool {
-        if let Some(config_bytes) = self.get_plugin_configuration() {
-            self.header_content = String::from_utf8(config_bytes).unwrap()
-        }
-        true
-    }
+impl RootContext for HttpConfigHeaderRoot {}
 
-    fn create_http_context(&self, _: u32) -> Option<Box<dyn HttpContext>> {
-        Some(Box::new(HttpConfigHeader {
-            header_content: self.header_content.clone(),
-        }))
-    }
+impl ConfigurableRootContext for HttpConfigHeaderRoot {
+    type Config = Config;
 
-    fn get_type(&self) -> Option<ContextType> {
-        Some(ContextType::HttpContext)
+    fn create_configured_http_context(
+        &self,
+        _: u32,
+        config: Rc<Config>,
+    ) -> Option<Box<dyn HttpContext>> {
+        Some(Box::new(HttpConfigHeader { config }))
     }
 }
@@ -52,32 +52,22 @@ struct HttpHeaders {
 }
 
 impl Context for HttpHeaders {
-    fn on_http_call_response(
-        &mut self,
-        token_id: u32,
-        num_headers: usize,
-        body_size: usize,
-        _: usize,
-    ) {
-        if num_headers == 0 {
-            panic!("Error in dispatch call, got 0 num_headers");
-        }
-        info!("got response for call {}", token_id);
-        let resp_headers = self.get_http_call_response_headers();
-
-        let mut status_code = 0;
-        for (name, value) in resp_headers.iter() {
-            if name == ":status" {
-                status_code = value.parse().unwrap_or(0);
-            }
+    fn on_http_call_result(&mut self, token_id: u32, response: HttpCallResponse) {
+        let status_code = match response.status_code() {
+            Some(status_code) => status_code,
+            None => panic!("Error in dispatch call, got no response headers"),
+        };
+        info!("got response for call {}: {}", token_id, status_code);
+        for (name, value) in response.headers() {
             info!(
                 "#{} http call response {}: {}",
-                self.context_id, name, value
+                self.context_id,
+                name,
+                String::from_utf8_lossy(value)
             )
         }
 
-        let response_body = self.get_http_call_response_body(0, body_size);
-        if let Some(body) = &response_body {
+        if let Some(body) = response.body() {
             info!("Response body: {:?}", String::from_utf8_lossy(body));
         }
         self.resume_http_request();
@@ -99,11 +89,9 @@ impl HttpContext for HttpHeaders {
             .collect();
         
         // This will get the upstream cluster name where the request will be sent
-        match self.get_property(vec!["cluster_name"]) {
-            Some(cluster_name_bytes) => {
-                if let Ok(cluster_name) = String::from_utf8(cluster_name_bytes) {
-                    log::info!("Request will be sent to upstream cluster: {}", cluster_name);
-                }
+        match proxy_wasm::properties::cluster_name() {
+            Ok(Some(cluster_name)) => {
+                log::info!("Request will be sent to upstream cluster: {}", cluster_name);
             }
             _ => log::error!("Failed to get upstream cluster name"),
         }
@@ -119,21 +107,14 @@ impl HttpContext for HttpHeaders {
             }
 
             Some(path) if path == "/timeout" => {
-                self.dispatch_http_call(
-                    "clustera",
-                    vec![
-                        (":method", "GET"),
-                        (":path", "/delay/5"),
-                        (":authority", "localhost:10000"),
-                    ],
-                    None,
-                    vec![],
-                    Duration::from_secs(1),
-                )
-                .unwrap_or_else(|err| {
-                    info!("#{} HTTP call failed: {:?}", self.context_id, err);
-                    0
-                });
+                let request = HttpCallRequest::new("clustera")
+                    .method("GET")
+                    .path("/delay/5")
+                    .authority("localhost:10000")
+                    .timeout(Duration::from_secs(1));
+                if let Err(err) = self.dispatch_http_call_request(request) {
+                    info!("#{} HTTP call failed: {}", self.context_id, err);
+                }
                 Action::Pause // don't complete original request, make a new call.
             }
 
@@ -154,16 +135,17 @@ impl HttpContext for HttpHeaders {
     }
 
     fn on_http_response_headers(&mut self, _: usize, _: bool) -> Action {
-        let mut status_code: u32 = 0;
+        let status_code = proxy_wasm::properties::response_code()
+            .ok()
+            .flatten()
+            .unwrap_or(0);
         let mut upstream = String::from("defaultcluster");
         for (name, value) in &self.get_http_response_headers() {
             info!(
                 "#{} response headers <- {}: {}",
                 self.context_id, name, value
             );
-            if name == ":status" {
-                status_code = value.parse().unwrap_or(0);
-            } else if name == ":freeform" {
+            if name == ":freeform" {
                 upstream = value.to_string();
             }
         }
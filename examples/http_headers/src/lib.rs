@@ -14,17 +14,36 @@
 
 use log::info;
 use log::error;
+use proxy_wasm::router::{Route, Router};
 use proxy_wasm::traits::*;
 use proxy_wasm::types::*;
+use std::rc::Rc;
 
 mod proxy_calls;
 
 proxy_wasm::main! {{
     proxy_wasm::set_log_level(LogLevel::Trace);
-    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> { Box::new(HttpHeadersRoot) });
+    proxy_wasm::set_root_context(|_| -> Box<dyn RootContext> {
+        Box::new(HttpHeadersRoot {
+            router: Rc::new(router()),
+        })
+    });
 }}
 
-struct HttpHeadersRoot;
+fn router() -> Router<HttpHeaders> {
+    Router::new().add(Route::any().exact("/hello"), |http_headers: &mut HttpHeaders, _| {
+        http_headers.send_http_response(
+            200,
+            vec![("Hello", "World"), ("Powered-By", "proxy-wasm")],
+            Some(b"Hello, World!\n"),
+        );
+        Action::Pause
+    })
+}
+
+struct HttpHeadersRoot {
+    router: Rc<Router<HttpHeaders>>,
+}
 
 impl Context for HttpHeadersRoot {}
 
@@ -34,12 +53,16 @@ impl RootContext for HttpHeadersRoot {
     }
 
     fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
-        Some(Box::new(HttpHeaders { context_id }))
+        Some(Box::new(HttpHeaders {
+            context_id,
+            router: Rc::clone(&self.router),
+        }))
     }
 }
 
 struct HttpHeaders {
     context_id: u32,
+    router: Rc<Router<HttpHeaders>>,
 }
 
 impl Context for HttpHeaders {}
@@ -96,17 +119,8 @@ impl HttpContext for HttpHeaders {
 
 
 
-        match self.get_http_request_header(":path") {
-            Some(path) if path == "/hello" => {
-                self.send_http_response(
-                    200,
-                    vec![("Hello", "World"), ("Powered-By", "proxy-wasm")],
-                    Some(b"Hello, World!\n"),
-                );
-                Action::Pause
-            }
-            _ => Action::Continue,
-        }
+        let router = Rc::clone(&self.router);
+        router.route(self)
     }
 
     fn on_http_response_headers(&mut self, _: usize, _: bool) -> Action {
@@ -66,11 +66,11 @@ impl HttpContext for HttpHeaders {
         // }
         info!("in response flow");
         self.set_http_response_header("lowercaseh1", Some("v1"));
-        self.set_http_response_header("mixeDCasEH2", Some("v2"));
-        self.set_http_response_header("UPPERCASEH3", Some("v3"));
-        self.set_http_response_header("Access-Control-Expose-Headers", Some("intuit_consent_resource,content-length,intuit_*,x-b3-parentspanid,test-header,intuit_consent_purpose,origin,x-b3-sampled,accept,authorization,intuit-*,tracestate,foo*,x-b3-traceid,x-b3-spanid,traceparent,x-requested-with,x-csrf-token,content-type,location"));
-        self.set_http_response_header("Access-Control-Allow-Credentials", Some("true"));
-        self.set_http_response_header("Access-Control-Allow-Origin", Some("https://qa2.unit1.turbotaxonline.intuit.com"));
+        self.set_http_response_header_with_case("mixeDCasEH2", Some("v2"));
+        self.set_http_response_header_with_case("UPPERCASEH3", Some("v3"));
+        self.set_http_response_header_with_case("Access-Control-Expose-Headers", Some("intuit_consent_resource,content-length,intuit_*,x-b3-parentspanid,test-header,intuit_consent_purpose,origin,x-b3-sampled,accept,authorization,intuit-*,tracestate,foo*,x-b3-traceid,x-b3-spanid,traceparent,x-requested-with,x-csrf-token,content-type,location"));
+        self.set_http_response_header_with_case("Access-Control-Allow-Credentials", Some("true"));
+        self.set_http_response_header_with_case("Access-Control-Allow-Origin", Some("https://qa2.unit1.turbotaxonline.intuit.com"));
         // self.send_http_response(503, vec![], Some(b"Internal proxy error.\n"));
         Action::Continue
     }
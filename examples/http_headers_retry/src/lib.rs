@@ -60,23 +60,23 @@ struct HttpHeaders {
 }
 
 impl Context for HttpHeaders {
-    fn on_http_call_response(&mut self, token_id: u32, _: usize, body_size: usize, _: usize) {
+    fn on_http_call_result(&mut self, token_id: u32, response: HttpCallResponse) {
         info!("got response for call {}", token_id);
-        let resp_headers = self.get_http_call_response_headers();
-
-        let mut status_code = 0;
+        let status_code = response.status_code().unwrap_or(0);
+        let resp_headers: Vec<(String, String)> = response
+            .headers()
+            .iter()
+            .map(|(name, value)| (name.clone(), String::from_utf8_lossy(value).into_owned()))
+            .collect();
         for (name, value) in resp_headers.iter() {
-            if name == ":status" {
-                status_code = value.parse().unwrap_or(0);
-            }
             info!(
                 "#{} http call response {}: {}",
                 self.context_id, name, value
             )
         }
 
-        let response_body = self.get_http_call_response_body(0, body_size);
-        if let Some(body) = &response_body {
+        let response_body = response.body();
+        if let Some(body) = response_body {
             info!("Response body: {:?}", String::from_utf8_lossy(body));
         }
 
@@ -88,12 +88,12 @@ impl Context for HttpHeaders {
             // final clusterb call
             info!("sending cluster b response back");
             self.send_http_response(
-                status_code as u32,
+                status_code,
                 resp_headers
                     .iter()
                     .map(|(k, v)| (k.as_str(), v.as_str()))
                     .collect(),
-                Some(response_body.as_deref().unwrap_or(&[])),
+                Some(response_body.unwrap_or(&[])),
             );
         }
 
@@ -213,16 +213,17 @@ impl HttpContext for HttpHeaders {
     }
 
     fn on_http_response_headers(&mut self, _: usize, _: bool) -> Action {
-        let mut status_code: u32 = 0;
+        let status_code = proxy_wasm::properties::response_code()
+            .ok()
+            .flatten()
+            .unwrap_or(0);
         let mut upstream = String::from("defaultcluster");
         for (name, value) in &self.get_http_response_headers() {
             info!(
                 "#{} response headers <- {}: {}",
                 self.context_id, name, value
             );
-            if name == ":status" {
-                status_code = value.parse().unwrap_or(0);
-            } else if name == ":freeform" {
+            if name == ":freeform" {
                 upstream = value.to_string();
             }
         }
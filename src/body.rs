@@ -0,0 +1,592 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! HTTP body processing.
+//!
+//! A [`BodyFilter`] drives a [`BodyProcessor`] from the body callbacks of an
+//! `HttpContext`, keeping track of the offset of each chunk, buffering the
+//! body when needed, and writing back the bytes returned by the processor.
+//!
+//! ```no_run
+//! use proxy_wasm::body::*;
+//! use proxy_wasm::traits::*;
+//! use proxy_wasm::types::*;
+//!
+//! struct Redact;
+//!
+//! impl BodyProcessor for Redact {
+//!     fn mode(&self) -> BodyMode {
+//!         BodyMode::Buffer { max_size: 1 << 20 }
+//!     }
+//!
+//!     fn on_body(&mut self, body: &[u8]) -> Option<Bytes> {
+//!         let body = String::from_utf8_lossy(body);
+//!         if body.contains("secret") {
+//!             Some(b"Redacted.\n".to_vec())
+//!         } else {
+//!             None
+//!         }
+//!     }
+//! }
+//!
+//! struct Filter {
+//!     response_body: BodyFilter<Redact>,
+//! }
+//!
+//! impl Context for Filter {}
+//!
+//! impl HttpContext for Filter {
+//!     fn on_http_response_headers(&mut self, _: usize, end_of_stream: bool) -> Action {
+//!         self.response_body.on_headers(end_of_stream)
+//!     }
+//!
+//!     fn on_http_response_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+//!         self.response_body.on_body(body_size, end_of_stream)
+//!     }
+//!
+//!     fn on_http_response_trailers(&mut self, _: usize) -> Action {
+//!         self.response_body.on_trailers()
+//!     }
+//! }
+//! ```
+
+use crate::encoding::ContentEncoding;
+use crate::hostcalls;
+use crate::types::*;
+
+/// How a [`BodyProcessor`] receives the body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BodyMode {
+    /// Buffers the body in the host, and passes it to
+    /// [`on_body`](BodyProcessor::on_body) once complete. Bodies larger than
+    /// `max_size` are rejected with a local response, i.e. 413 for requests
+    /// and 500 for responses.
+    Buffer { max_size: usize },
+    /// Passes each chunk to [`on_chunk`](BodyProcessor::on_chunk) as it
+    /// arrives, without holding it.
+    Stream,
+    /// Passes each chunk to [`on_chunk`](BodyProcessor::on_chunk) as it
+    /// arrives, and replaces it with the returned bytes.
+    Transform,
+}
+
+/// What to do with a compressed body that was replaced by the processor,
+/// when content decoding is enabled with
+/// [`with_content_decoding`](BodyFilter::with_content_decoding).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reencoding {
+    /// Compresses the new body with the original content coding.
+    Recompress,
+    /// Sends the new body uncompressed, and removes `content-encoding`.
+    StripEncoding,
+}
+
+pub trait BodyProcessor {
+    fn mode(&self) -> BodyMode;
+
+    /// Called with the complete body in `BodyMode::Buffer`. Returning `Some`
+    /// replaces the body.
+    fn on_body(&mut self, _body: &[u8]) -> Option<Bytes> {
+        None
+    }
+
+    /// Called with each chunk in `BodyMode::Stream` and `BodyMode::Transform`,
+    /// where `offset` is the position of the chunk in the original body.
+    /// Returning `Some` replaces the chunk in `BodyMode::Transform`, and is
+    /// ignored in `BodyMode::Stream`.
+    fn on_chunk(&mut self, _chunk: &[u8], _offset: usize, _end_of_stream: bool) -> Option<Bytes> {
+        None
+    }
+}
+
+/// Drives a [`BodyProcessor`] from `on_http_request_body` or
+/// `on_http_response_body`.
+///
+/// In `BodyMode::Stream` and `BodyMode::Transform`, chunks are expected to be
+/// forwarded as they arrive, i.e. no other filter in the same callback pauses
+/// the body.
+pub struct BodyFilter<P> {
+    processor: P,
+    mode: BodyMode,
+    buffer_type: BufferType,
+    seen: usize,
+    state: State,
+    decoding: Option<Reencoding>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Processing,
+    Done,
+    Rejected,
+}
+
+impl<P: BodyProcessor> BodyFilter<P> {
+    pub fn request(processor: P) -> BodyFilter<P> {
+        BodyFilter::new(BufferType::HttpRequestBody, processor)
+    }
+
+    pub fn response(processor: P) -> BodyFilter<P> {
+        BodyFilter::new(BufferType::HttpResponseBody, processor)
+    }
+
+    fn new(buffer_type: BufferType, processor: P) -> BodyFilter<P> {
+        BodyFilter {
+            mode: processor.mode(),
+            processor,
+            buffer_type,
+            seen: 0,
+            state: State::Processing,
+            decoding: None,
+        }
+    }
+
+    /// Decompresses the body according to `content-encoding` before passing
+    /// it to the processor, in `BodyMode::Buffer`. Codings are enabled by the
+    /// `gzip`, `deflate` and `brotli` features, and bodies with other codings
+    /// are forwarded unchanged, without calling the processor. The limit of
+    /// the mode applies to the decompressed body as well.
+    ///
+    /// Changing the headers requires them to be held with
+    /// [`on_headers`](Self::on_headers).
+    pub fn with_content_decoding(mut self, reencoding: Reencoding) -> BodyFilter<P> {
+        self.decoding = Some(reencoding);
+        self
+    }
+
+    pub fn processor(&self) -> &P {
+        &self.processor
+    }
+
+    pub fn processor_mut(&mut self) -> &mut P {
+        &mut self.processor
+    }
+
+    /// Returns the number of body bytes received so far.
+    pub fn seen(&self) -> usize {
+        self.seen
+    }
+
+    /// Prepares the headers for a body that might change length. Call it from
+    /// `on_http_request_headers` or `on_http_response_headers`, and return the
+    /// action it returns.
+    ///
+    /// In `BodyMode::Buffer`, the headers are held until the body is complete,
+    /// so that `content-length` can be updated if the body is replaced. In
+    /// `BodyMode::Transform`, `content-length` is removed.
+    pub fn on_headers(&mut self, end_of_stream: bool) -> Action {
+        self.try_on_headers(end_of_stream).unwrap()
+    }
+
+    pub fn try_on_headers(&mut self, end_of_stream: bool) -> Result<Action, Error> {
+        match self.mode {
+            BodyMode::Buffer { .. } if !end_of_stream => Ok(Action::Pause),
+            BodyMode::Transform => {
+                hostcalls::try_set_map_value(self.map_type(), "content-length", None)?;
+                Ok(Action::Continue)
+            }
+            _ => Ok(Action::Continue),
+        }
+    }
+
+    /// Processes the body, with the arguments passed to the body callback,
+    /// and returns the action for the callback to return.
+    pub fn on_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+        self.try_on_body(body_size, end_of_stream).unwrap()
+    }
+
+    pub fn try_on_body(&mut self, body_size: usize, end_of_stream: bool) -> Result<Action, Error> {
+        match self.state {
+            State::Processing => {}
+            State::Done => return Ok(Action::Continue),
+            State::Rejected => return Ok(Action::Pause),
+        }
+        match self.mode {
+            BodyMode::Buffer { max_size } => {
+                // The host accumulates the body while it's paused, so
+                // `body_size` is the size of the whole body received so far.
+                self.seen = body_size;
+                if body_size > max_size {
+                    return self.reject();
+                }
+                if !end_of_stream {
+                    return Ok(Action::Pause);
+                }
+                self.finish_buffered()
+            }
+            BodyMode::Stream | BodyMode::Transform => {
+                let offset = self.seen;
+                self.seen += body_size;
+                let chunk = self.get(body_size)?;
+                let replacement = self.processor.on_chunk(&chunk, offset, end_of_stream);
+                if let (BodyMode::Transform, Some(replacement)) = (self.mode, replacement) {
+                    self.set(body_size, &replacement)?;
+                }
+                if end_of_stream {
+                    self.state = State::Done;
+                }
+                Ok(Action::Continue)
+            }
+        }
+    }
+
+    /// Completes a buffered body that ended with trailers, in which case the
+    /// body callback isn't called with `end_of_stream`. Call it from
+    /// `on_http_request_trailers` or `on_http_response_trailers`.
+    pub fn on_trailers(&mut self) -> Action {
+        self.try_on_trailers().unwrap()
+    }
+
+    pub fn try_on_trailers(&mut self) -> Result<Action, Error> {
+        match (self.state, self.mode) {
+            (State::Processing, BodyMode::Buffer { .. }) => self.finish_buffered(),
+            (State::Rejected, _) => Ok(Action::Pause),
+            _ => {
+                self.state = State::Done;
+                Ok(Action::Continue)
+            }
+        }
+    }
+
+    fn finish_buffered(&mut self) -> Result<Action, Error> {
+        self.state = State::Done;
+        let mut body = self.get(self.seen)?;
+        let map_type = self.map_type();
+        let encoding = match self.content_encoding()? {
+            Some(encoding) => encoding,
+            None => return Ok(Action::Continue),
+        };
+        if encoding != ContentEncoding::Identity {
+            let max_size = match self.mode {
+                BodyMode::Buffer { max_size } => max_size,
+                _ => usize::MAX,
+            };
+            body = match encoding.decode(&body, max_size) {
+                Ok(body) => body,
+                Err(error) => {
+                    self.warn(&format!(
+                        "forwarding {} body unchanged: {}",
+                        encoding.as_str(),
+                        error
+                    ));
+                    return Ok(Action::Continue);
+                }
+            };
+        }
+        let mut replacement = match self.processor.on_body(&body) {
+            Some(replacement) => replacement,
+            None => return Ok(Action::Continue),
+        };
+        if encoding != ContentEncoding::Identity {
+            match self.decoding {
+                Some(Reencoding::Recompress) => replacement = encoding.encode(&replacement)?,
+                _ => hostcalls::try_set_map_value(map_type, "content-encoding", None)?,
+            }
+        }
+        self.set(self.seen, &replacement)?;
+        // Only effective if the headers were held by `on_headers`.
+        if hostcalls::try_get_map_value(map_type, "content-length")?.is_some() {
+            let content_length = replacement.len().to_string();
+            hostcalls::try_set_map_value(map_type, "content-length", Some(&content_length))?;
+        }
+        Ok(Action::Continue)
+    }
+
+    // Returns the coding to decode the body with, or `None` if it isn't
+    // supported and the body should be forwarded unchanged.
+    fn content_encoding(&self) -> Result<Option<ContentEncoding>, Error> {
+        if self.decoding.is_none() {
+            return Ok(Some(ContentEncoding::Identity));
+        }
+        let value = match hostcalls::try_get_map_value(self.map_type(), "content-encoding")? {
+            Some(value) => value,
+            None => return Ok(Some(ContentEncoding::Identity)),
+        };
+        let encoding = ContentEncoding::from_header(&value);
+        if encoding.is_none() {
+            self.warn(&format!(
+                "forwarding body with content-encoding {:?} unchanged",
+                value
+            ));
+        }
+        Ok(encoding)
+    }
+
+    fn warn(&self, message: &str) {
+        let _ = hostcalls::try_log(LogLevel::Warn, message);
+    }
+
+    fn reject(&mut self) -> Result<Action, Error> {
+        self.state = State::Rejected;
+        let status_code = match self.buffer_type {
+            BufferType::HttpRequestBody => 413,
+            _ => 500,
+        };
+        hostcalls::try_send_http_response(status_code, vec![], None)?;
+        Ok(Action::Pause)
+    }
+
+    fn map_type(&self) -> MapType {
+        match self.buffer_type {
+            BufferType::HttpRequestBody => MapType::HttpRequestHeaders,
+            _ => MapType::HttpResponseHeaders,
+        }
+    }
+
+    fn get(&self, size: usize) -> Result<Bytes, Error> {
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        Ok(hostcalls::try_get_buffer(self.buffer_type, 0, size)?.unwrap_or_default())
+    }
+
+    fn set(&self, size: usize, value: &[u8]) -> Result<(), Error> {
+        hostcalls::try_set_buffer(self.buffer_type, 0, size, value)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::MockHost;
+    use crate::traits::*;
+    use std::cell::{Cell, RefCell};
+
+    thread_local! {
+        static MODE: Cell<BodyMode> = const { Cell::new(BodyMode::Stream) };
+        static EVENTS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn record(event: String) {
+        EVENTS.with(|events| events.borrow_mut().push(event));
+    }
+
+    fn events() -> Vec<String> {
+        EVENTS.with(|events| events.borrow_mut().drain(..).collect())
+    }
+
+    // Wraps buffered bodies in angle brackets, and upper-cases chunks.
+    struct Processor;
+
+    impl BodyProcessor for Processor {
+        fn mode(&self) -> BodyMode {
+            MODE.with(|mode| mode.get())
+        }
+
+        fn on_body(&mut self, body: &[u8]) -> Option<Bytes> {
+            Some(format!("<{}>", String::from_utf8_lossy(body)).into_bytes())
+        }
+
+        fn on_chunk(&mut self, chunk: &[u8], offset: usize, _: bool) -> Option<Bytes> {
+            record(format!("chunk at {}", offset));
+            Some(chunk.to_ascii_uppercase())
+        }
+    }
+
+    // Records the body forwarded by each callback that continues.
+    struct Filter(BodyFilter<Processor>);
+
+    impl Filter {
+        fn forward(&self, action: Action) -> Action {
+            if action == Action::Continue {
+                let body = hostcalls::get_buffer(BufferType::HttpRequestBody, 0, usize::MAX);
+                let body = body.unwrap().unwrap_or_default();
+                record(format!("forwarded {}", String::from_utf8_lossy(&body)));
+            }
+            action
+        }
+    }
+
+    impl Context for Filter {}
+
+    impl HttpContext for Filter {
+        fn on_http_request_headers(&mut self, _: usize, end_of_stream: bool) -> Action {
+            self.0.on_headers(end_of_stream)
+        }
+
+        fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+            let action = self.0.on_body(body_size, end_of_stream);
+            self.forward(action)
+        }
+
+        fn on_http_request_trailers(&mut self, _: usize) -> Action {
+            let action = self.0.on_trailers();
+            self.forward(action)
+        }
+    }
+
+    struct Root;
+
+    impl Context for Root {}
+
+    impl RootContext for Root {
+        fn create_http_context(&self, _: u32) -> Option<Box<dyn HttpContext>> {
+            Some(Box::new(Filter(BodyFilter::request(Processor))))
+        }
+
+        fn get_type(&self) -> Option<ContextType> {
+            Some(ContextType::HttpContext)
+        }
+    }
+
+    fn setup(mode: BodyMode) -> (MockHost, u32) {
+        let host = MockHost::new();
+        MODE.with(|current| current.set(mode));
+        crate::set_root_context(|_| Box::new(Root));
+        let root_id = host.create_root_context();
+        let context_id = host.create_http_context(root_id);
+        (host, context_id)
+    }
+
+    fn content_length(host: &MockHost, context_id: u32) -> Option<String> {
+        host.map_value(context_id, MapType::HttpRequestHeaders, "content-length")
+    }
+
+    #[test]
+    fn buffer_over_max_size() {
+        let (host, context_id) = setup(BodyMode::Buffer { max_size: 4 });
+        let headers = vec![(":path", "/"), ("content-length", "6")];
+        assert_eq!(
+            host.on_http_request_headers(context_id, headers, false),
+            Action::Pause
+        );
+        assert_eq!(
+            host.on_http_request_body(context_id, b"abc", false),
+            Action::Pause
+        );
+        assert!(host.local_response(context_id).is_none());
+        assert_eq!(
+            host.on_http_request_body(context_id, b"def", false),
+            Action::Pause
+        );
+        assert_eq!(host.local_response(context_id).unwrap().status_code, 413);
+
+        // The body is dropped after the local response.
+        assert_eq!(
+            host.on_http_request_body(context_id, b"g", true),
+            Action::Pause
+        );
+        assert_eq!(
+            host.on_http_request_trailers(context_id, vec![]),
+            Action::Pause
+        );
+        assert!(events().is_empty());
+    }
+
+    #[test]
+    fn buffer_updates_content_length() {
+        let (host, context_id) = setup(BodyMode::Buffer { max_size: 4 });
+        let headers = vec![(":path", "/"), ("content-length", "4")];
+        assert_eq!(
+            host.on_http_request_headers(context_id, headers, false),
+            Action::Pause
+        );
+        assert_eq!(
+            host.on_http_request_body(context_id, b"ab", false),
+            Action::Pause
+        );
+        assert_eq!(
+            host.on_http_request_body(context_id, b"cd", true),
+            Action::Continue
+        );
+        assert_eq!(events(), ["forwarded <abcd>"]);
+        assert_eq!(content_length(&host, context_id).as_deref(), Some("6"));
+
+        // Without `content-length`, none is added.
+        let (host, context_id) = setup(BodyMode::Buffer { max_size: 4 });
+        let headers = vec![(":path", "/")];
+        assert_eq!(
+            host.on_http_request_headers(context_id, headers, false),
+            Action::Pause
+        );
+        assert_eq!(
+            host.on_http_request_body(context_id, b"ab", true),
+            Action::Continue
+        );
+        assert_eq!(events(), ["forwarded <ab>"]);
+        assert_eq!(content_length(&host, context_id), None);
+    }
+
+    #[test]
+    fn buffer_completed_by_trailers() {
+        let (host, context_id) = setup(BodyMode::Buffer { max_size: 4 });
+        let headers = vec![(":path", "/"), ("content-length", "4")];
+        assert_eq!(
+            host.on_http_request_headers(context_id, headers, false),
+            Action::Pause
+        );
+        assert_eq!(
+            host.on_http_request_body(context_id, b"ab", false),
+            Action::Pause
+        );
+        assert_eq!(
+            host.on_http_request_body(context_id, b"cd", false),
+            Action::Pause
+        );
+        assert!(events().is_empty());
+        let trailers = vec![("grpc-status", "0")];
+        assert_eq!(
+            host.on_http_request_trailers(context_id, trailers),
+            Action::Continue
+        );
+        assert_eq!(events(), ["forwarded <abcd>"]);
+        assert_eq!(content_length(&host, context_id).as_deref(), Some("6"));
+    }
+
+    #[test]
+    fn transform_replaces_chunks() {
+        let (host, context_id) = setup(BodyMode::Transform);
+        let headers = vec![(":path", "/"), ("content-length", "4")];
+        assert_eq!(
+            host.on_http_request_headers(context_id, headers, false),
+            Action::Continue
+        );
+        assert_eq!(content_length(&host, context_id), None);
+        assert_eq!(
+            host.on_http_request_body(context_id, b"ab", false),
+            Action::Continue
+        );
+        assert_eq!(
+            host.on_http_request_body(context_id, b"cd", true),
+            Action::Continue
+        );
+        assert_eq!(
+            events(),
+            ["chunk at 0", "forwarded AB", "chunk at 2", "forwarded CD"]
+        );
+    }
+
+    #[test]
+    fn stream_forwards_chunks_unchanged() {
+        let (host, context_id) = setup(BodyMode::Stream);
+        let headers = vec![(":path", "/"), ("content-length", "4")];
+        assert_eq!(
+            host.on_http_request_headers(context_id, headers, false),
+            Action::Continue
+        );
+        assert_eq!(content_length(&host, context_id).as_deref(), Some("4"));
+        assert_eq!(
+            host.on_http_request_body(context_id, b"ab", false),
+            Action::Continue
+        );
+        assert_eq!(
+            host.on_http_request_body(context_id, b"cd", true),
+            Action::Continue
+        );
+        assert_eq!(
+            events(),
+            ["chunk at 0", "forwarded ab", "chunk at 2", "forwarded cd"]
+        );
+    }
+}
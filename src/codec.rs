@@ -0,0 +1,90 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Codecs used to store typed values in shared data and shared queues.
+
+use crate::types::*;
+
+pub trait Codec<T> {
+    fn encode(&self, value: &T) -> Result<Bytes, Error>;
+    fn decode(&self, bytes: &[u8]) -> Result<T, Error>;
+}
+
+/// Passes bytes through unchanged.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Raw;
+
+impl Codec<Bytes> for Raw {
+    fn encode(&self, value: &Bytes) -> Result<Bytes, Error> {
+        Ok(value.clone())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Bytes, Error> {
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Encodes strings as UTF-8.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Text;
+
+impl Codec<String> for Text {
+    fn encode(&self, value: &String) -> Result<Bytes, Error> {
+        Ok(value.as_bytes().to_vec())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<String, Error> {
+        String::from_utf8(bytes.to_vec()).map_err(|err| Error::Codec {
+            reason: err.to_string(),
+        })
+    }
+}
+
+/// Encodes values as JSON.
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for Json {
+    fn encode(&self, value: &T) -> Result<Bytes, Error> {
+        serde_json::to_vec(value).map_err(|err| Error::Codec {
+            reason: err.to_string(),
+        })
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, Error> {
+        serde_json::from_slice(bytes).map_err(|err| Error::Codec {
+            reason: err.to_string(),
+        })
+    }
+}
+
+/// Encodes protobuf messages.
+#[cfg(feature = "prost")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Protobuf;
+
+#[cfg(feature = "prost")]
+impl<T: prost::Message + Default> Codec<T> for Protobuf {
+    fn encode(&self, value: &T) -> Result<Bytes, Error> {
+        Ok(value.encode_to_vec())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, Error> {
+        T::decode(bytes).map_err(|err| Error::Codec {
+            reason: err.to_string(),
+        })
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Typed plugin configuration.
+//!
+//! [`ConfigurableRootContext`] deserializes the plugin configuration into a
+//! user type when the host calls `proxy_on_configure`, and hands the parsed
+//! configuration to every `HttpContext` created by the root. Deserialization
+//! errors are logged with the path of the offending field, and the plugin
+//! fails to configure.
+//!
+//! ```no_run
+//! use proxy_wasm::config::*;
+//! use proxy_wasm::traits::*;
+//! use proxy_wasm::types::*;
+//! use serde::Deserialize;
+//! use std::rc::Rc;
+//!
+//! #[derive(Deserialize)]
+//! struct Config {
+//!     header_name: String,
+//!     header_value: String,
+//! }
+//!
+//! struct Root;
+//!
+//! impl Context for Root {}
+//! impl RootContext for Root {}
+//!
+//! impl ConfigurableRootContext for Root {
+//!     type Config = Config;
+//!
+//!     fn create_configured_http_context(
+//!         &self,
+//!         _context_id: u32,
+//!         config: Rc<Config>,
+//!     ) -> Option<Box<dyn HttpContext>> {
+//!         Some(Box::new(Filter { config }))
+//!     }
+//! }
+//!
+//! struct Filter {
+//!     config: Rc<Config>,
+//! }
+//!
+//! impl Context for Filter {}
+//!
+//! impl HttpContext for Filter {
+//!     fn on_http_response_headers(&mut self, _: usize, _: bool) -> Action {
+//!         self.add_http_response_header(&self.config.header_name, &self.config.header_value);
+//!         Action::Continue
+//!     }
+//! }
+//!
+//! proxy_wasm::main! {{
+//!     proxy_wasm::set_root_context(|_| Root.into_root_context());
+//! }}
+//! ```
+
+use crate::hostcalls;
+use crate::traits::*;
+use crate::types::*;
+use serde::de::DeserializeOwned;
+use std::rc::Rc;
+
+/// Serialization format of the plugin configuration.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConfigFormat {
+    #[cfg(feature = "json")]
+    #[default]
+    Json,
+    #[cfg(feature = "yaml")]
+    #[cfg_attr(not(feature = "json"), default)]
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Deserializes `bytes`, returning a message that includes the path of
+    /// the offending field on failure. An empty configuration is treated as
+    /// an empty object, so that configuration types with all fields
+    /// defaulted can be used without any configuration.
+    pub fn parse<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        let bytes = if bytes.iter().all(u8::is_ascii_whitespace) {
+            &b"{}"[..]
+        } else {
+            bytes
+        };
+        match self {
+            #[cfg(feature = "json")]
+            ConfigFormat::Json => {
+                let mut de = serde_json::Deserializer::from_slice(bytes);
+                let config = serde_path_to_error::deserialize(&mut de).map_err(describe)?;
+                de.end().map_err(|err| err.to_string())?;
+                Ok(config)
+            }
+            #[cfg(feature = "yaml")]
+            // serde_yaml_ng already reports the path of the offending field.
+            ConfigFormat::Yaml => serde_yaml_ng::from_slice(bytes).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+// Formats a deserialization error, prefixed with the path of the offending
+// field unless the error is at the root of the configuration.
+#[cfg(feature = "json")]
+fn describe<E: std::fmt::Display>(err: serde_path_to_error::Error<E>) -> String {
+    match err.path().to_string().as_str() {
+        "." => err.inner().to_string(),
+        path => format!("{}: {}", path, err.inner()),
+    }
+}
+
+pub trait ConfigurableRootContext: RootContext + Sized + 'static {
+    type Config: DeserializeOwned + 'static;
+
+    fn config_format(&self) -> ConfigFormat {
+        ConfigFormat::default()
+    }
+
+    /// Called with the newly parsed configuration, before it replaces the
+    /// previous one. Returning `false` rejects the configuration.
+    fn on_config(&mut self, _config: &Rc<Self::Config>) -> bool {
+        true
+    }
+
+    fn create_configured_http_context(
+        &self,
+        _context_id: u32,
+        _config: Rc<Self::Config>,
+    ) -> Option<Box<dyn HttpContext>> {
+        None
+    }
+
+    /// Wraps this context into a `RootContext` that loads the configuration
+    /// in `on_configure`. The wrapped context's own `on_configure` and
+    /// `create_http_context` are not called; all other callbacks are
+    /// forwarded to it.
+    fn into_root_context(self) -> Box<dyn RootContext> {
+        Box::new(ConfiguredRoot {
+            inner: self,
+            config: None,
+        })
+    }
+}
+
+// Root context returned by `into_root_context`. It owns the last configuration
+// that was accepted, and shares it with every HTTP context it creates.
+struct ConfiguredRoot<R: ConfigurableRootContext> {
+    inner: R,
+    config: Option<Rc<R::Config>>,
+}
+
+impl<R: ConfigurableRootContext> Context for ConfiguredRoot<R> {
+    fn on_http_call_response(
+        &mut self,
+        token_id: u32,
+        num_headers: usize,
+        body_size: usize,
+        num_trailers: usize,
+    ) {
+        self.inner
+            .on_http_call_response(token_id, num_headers, body_size, num_trailers)
+    }
+
+    fn on_http_call_result(&mut self, token_id: u32, response: HttpCallResponse) {
+        self.inner.on_http_call_result(token_id, response)
+    }
+
+    fn on_grpc_call_response(&mut self, token_id: u32, status_code: u32, response_size: usize) {
+        self.inner
+            .on_grpc_call_response(token_id, status_code, response_size)
+    }
+
+    fn on_grpc_stream_initial_metadata(&mut self, token_id: u32, num_elements: u32) {
+        self.inner
+            .on_grpc_stream_initial_metadata(token_id, num_elements)
+    }
+
+    fn on_grpc_stream_message(&mut self, token_id: u32, message_size: usize) {
+        self.inner.on_grpc_stream_message(token_id, message_size)
+    }
+
+    fn on_grpc_stream_trailing_metadata(&mut self, token_id: u32, num_elements: u32) {
+        self.inner
+            .on_grpc_stream_trailing_metadata(token_id, num_elements)
+    }
+
+    fn on_grpc_stream_close(&mut self, token_id: u32, status_code: u32) {
+        self.inner.on_grpc_stream_close(token_id, status_code)
+    }
+
+    fn on_callout_cancelled(&mut self, token_id: u32, reason: CancelReason) {
+        self.inner.on_callout_cancelled(token_id, reason)
+    }
+
+    fn on_timer(&mut self, timer_id: u32) {
+        self.inner.on_timer(timer_id)
+    }
+
+    fn on_done(&mut self) -> bool {
+        self.inner.on_done()
+    }
+}
+
+impl<R: ConfigurableRootContext> RootContext for ConfiguredRoot<R> {
+    fn on_vm_start(&mut self, vm_configuration_size: usize) -> bool {
+        self.inner.on_vm_start(vm_configuration_size)
+    }
+
+    fn on_configure(&mut self, _plugin_configuration_size: usize) -> bool {
+        let bytes = match self.try_get_plugin_configuration() {
+            Ok(bytes) => bytes.unwrap_or_default(),
+            Err(err) => {
+                let message = format!("failed to read plugin configuration: {}", err);
+                let _ = hostcalls::try_log(LogLevel::Error, &message);
+                return false;
+            }
+        };
+        let config = match self.inner.config_format().parse(&bytes) {
+            Ok(config) => Rc::new(config),
+            Err(err) => {
+                let message = format!("invalid plugin configuration: {}", err);
+                let _ = hostcalls::try_log(LogLevel::Error, &message);
+                return false;
+            }
+        };
+        if !self.inner.on_config(&config) {
+            return false;
+        }
+        self.config = Some(config);
+        true
+    }
+
+    fn on_tick(&mut self) {
+        self.inner.on_tick()
+    }
+
+    fn on_queue_ready(&mut self, queue_id: u32) {
+        self.inner.on_queue_ready(queue_id)
+    }
+
+    fn on_foreign_function(&mut self, function_id: u32, data_size: usize) {
+        self.inner.on_foreign_function(function_id, data_size)
+    }
+
+    fn on_log(&mut self) {
+        self.inner.on_log()
+    }
+
+    fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+        let config = self.config.clone()?;
+        self.inner
+            .create_configured_http_context(context_id, config)
+    }
+
+    fn create_stream_context(&self, context_id: u32) -> Option<Box<dyn StreamContext>> {
+        self.inner.create_stream_context(context_id)
+    }
+
+    fn get_type(&self) -> Option<ContextType> {
+        self.inner.get_type().or(Some(ContextType::HttpContext))
+    }
+}
@@ -12,12 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "async")]
+use crate::executor::Executor;
+use crate::grpc::StreamCallbacks;
 use crate::hostcalls;
 use crate::traits::*;
 use crate::types::*;
-use hashbrown::HashMap;
-use log::{trace, info};
+use hashbrown::{HashMap, HashSet};
+use log::{info, trace, warn};
 use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(panic = "unwind")]
+use std::panic::{self, AssertUnwindSafe};
+use std::time::{Duration, SystemTime};
 
 thread_local! {
 static DISPATCHER: Dispatcher = Dispatcher::new();
@@ -35,18 +44,156 @@ pub(crate) fn set_http_context(callback: NewHttpContext) {
     DISPATCHER.with(|dispatcher| dispatcher.set_http_context(callback));
 }
 
-pub(crate) fn register_callout(token_id: u32) {
-    DISPATCHER.with(|dispatcher| dispatcher.register_callout(token_id));
+pub(crate) fn register_callout(token_id: u32, timeout: Duration) {
+    DISPATCHER.with(|dispatcher| dispatcher.register_callout(token_id, timeout));
 }
 
-pub(crate) fn register_grpc_callout(token_id: u32) {
-    DISPATCHER.with(|dispatcher| dispatcher.register_grpc_callout(token_id));
+pub(crate) fn register_typed_callout(token_id: u32) {
+    DISPATCHER.with(|dispatcher| dispatcher.register_typed_callout(token_id));
+}
+
+pub(crate) fn register_grpc_callout(token_id: u32, timeout: Duration) {
+    DISPATCHER.with(|dispatcher| dispatcher.register_grpc_callout(token_id, timeout));
 }
 
 pub(crate) fn register_grpc_stream(token_id: u32) {
     DISPATCHER.with(|dispatcher| dispatcher.register_grpc_stream(token_id));
 }
 
+pub(crate) fn register_grpc_stream_handler(token_id: u32, handler: Box<dyn StreamCallbacks>) {
+    DISPATCHER.with(|dispatcher| dispatcher.register_grpc_stream_handler(token_id, handler));
+}
+
+pub(crate) fn pending_callouts() -> Vec<u32> {
+    DISPATCHER.with(|dispatcher| dispatcher.pending_callouts(dispatcher.active_id.get()))
+}
+
+pub(crate) fn forget_callout(token_id: u32) -> bool {
+    DISPATCHER.with(|dispatcher| dispatcher.forget_callout(token_id))
+}
+
+pub(crate) fn set_tick_period(period: Duration) -> Result<(), Error> {
+    DISPATCHER.with(|dispatcher| dispatcher.set_tick_period(period))
+}
+
+pub(crate) fn schedule_timer(delay: Duration, interval: Option<Duration>) -> Result<u32, Error> {
+    DISPATCHER.with(|dispatcher| dispatcher.schedule_timer(delay, interval))
+}
+
+pub(crate) fn cancel_timer(timer_id: u32) -> bool {
+    DISPATCHER.with(|dispatcher| dispatcher.cancel_timer(timer_id))
+}
+
+pub(crate) fn set_panic_policy(policy: PanicPolicy) {
+    DISPATCHER.with(|dispatcher| dispatcher.set_panic_policy(policy));
+}
+
+pub(crate) fn set_utf8_policy(policy: Utf8Policy) {
+    DISPATCHER.with(|dispatcher| dispatcher.utf8_policy.set(policy));
+}
+
+pub(crate) fn utf8_policy() -> Utf8Policy {
+    DISPATCHER
+        .try_with(|dispatcher| dispatcher.utf8_policy.get())
+        .unwrap_or_default()
+}
+
+pub(crate) fn active_context_id() -> Option<u32> {
+    DISPATCHER
+        .try_with(|dispatcher| dispatcher.active_id.get())
+        .ok()
+}
+
+pub(crate) fn on_panic() {
+    let _ = DISPATCHER.try_with(|dispatcher| dispatcher.on_panic());
+}
+
+#[cfg(feature = "async")]
+pub(crate) fn spawn(task: std::pin::Pin<Box<dyn Future<Output = ()>>>) {
+    DISPATCHER.with(|dispatcher| dispatcher.spawn(task));
+}
+
+#[cfg(feature = "async")]
+pub(crate) fn wake(task_id: u32) {
+    let _ = DISPATCHER.try_with(|dispatcher| dispatcher.executor.wake(task_id));
+}
+
+#[cfg(feature = "async")]
+pub(crate) fn with_executor<T>(f: impl FnOnce(&Executor) -> T) -> T {
+    DISPATCHER.with(|dispatcher| f(&dispatcher.executor))
+}
+
+#[cfg(feature = "testing")]
+pub(crate) fn reset() {
+    DISPATCHER.with(|dispatcher| dispatcher.reset());
+}
+
+// Callouts that the host didn't complete within their timeout (plus some
+// grace period) are cancelled on the next tick of any root context. Roots
+// with such callouts tick at least once per grace period, so that they are
+// cancelled even if the plugin didn't set a tick period.
+const CALLOUT_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+struct Callout {
+    context_id: u32,
+    deadline: Option<SystemTime>,
+}
+
+impl Callout {
+    fn new(context_id: u32, timeout: Duration) -> Callout {
+        let deadline = if timeout.as_millis() == 0 {
+            None
+        } else {
+            hostcalls::try_get_current_time()
+                .ok()
+                .map(|now| now + timeout + CALLOUT_GRACE_PERIOD)
+        };
+        Callout {
+            context_id,
+            deadline,
+        }
+    }
+}
+
+// Timers are multiplexed onto the tick of the root context of the context
+// that scheduled them. The tick period is the GCD of the periods of all
+// active timers, of the callout grace period while callouts with a deadline
+// are pending, and of the period requested with `set_tick_period`, and
+// `RootContext::on_tick` is only called when the latter elapses.
+//
+// Periods that share no factor would bring the GCD down to a millisecond,
+// while timers fire within half a tick of their deadline anyway, so the tick
+// period is never shorter than `MIN_TICK_PERIOD`, unless a shorter period
+// was requested.
+const MIN_TICK_PERIOD: Duration = Duration::from_millis(50);
+
+struct Timer {
+    context_id: u32,
+    root_id: u32,
+    deadline: SystemTime,
+    interval: Option<Duration>,
+    period: Duration,
+}
+
+#[derive(Default)]
+struct Scheduler {
+    user_period: Option<Duration>,
+    next_user_tick: Option<SystemTime>,
+    tick_period: Duration,
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn tick_millis(period: Duration) -> u64 {
+    u64::try_from(period.as_millis()).unwrap_or(u64::MAX).max(1)
+}
+
 struct NoopRoot;
 
 impl Context for NoopRoot {}
@@ -60,9 +207,21 @@ struct Dispatcher {
     new_http_stream: Cell<Option<NewHttpContext>>,
     http_streams: RefCell<HashMap<u32, Box<dyn HttpContext>>>,
     active_id: Cell<u32>,
-    callouts: RefCell<HashMap<u32, u32>>,
-    grpc_callouts: RefCell<HashMap<u32, u32>>,
+    callouts: RefCell<HashMap<u32, Callout>>,
+    typed_callouts: RefCell<HashSet<u32>>,
+    grpc_callouts: RefCell<HashMap<u32, Callout>>,
     grpc_streams: RefCell<HashMap<u32, u32>>,
+    grpc_stream_handlers: RefCell<HashMap<u32, Box<dyn StreamCallbacks>>>,
+    panic_policy: RefCell<PanicPolicy>,
+    utf8_policy: Cell<Utf8Policy>,
+    http_callback: Cell<Option<u32>>,
+    poisoned: RefCell<HashSet<u32>>,
+    parents: RefCell<HashMap<u32, u32>>,
+    timers: RefCell<HashMap<u32, Timer>>,
+    next_timer_id: Cell<u32>,
+    schedulers: RefCell<HashMap<u32, Scheduler>>,
+    #[cfg(feature = "async")]
+    executor: Executor,
 }
 
 impl Dispatcher {
@@ -76,11 +235,49 @@ impl Dispatcher {
             http_streams: RefCell::new(HashMap::new()),
             active_id: Cell::new(0),
             callouts: RefCell::new(HashMap::new()),
+            typed_callouts: RefCell::new(HashSet::new()),
             grpc_callouts: RefCell::new(HashMap::new()),
             grpc_streams: RefCell::new(HashMap::new()),
+            grpc_stream_handlers: RefCell::new(HashMap::new()),
+            panic_policy: RefCell::new(PanicPolicy::default()),
+            utf8_policy: Cell::new(Utf8Policy::default()),
+            http_callback: Cell::new(None),
+            poisoned: RefCell::new(HashSet::new()),
+            parents: RefCell::new(HashMap::new()),
+            timers: RefCell::new(HashMap::new()),
+            next_timer_id: Cell::new(1),
+            schedulers: RefCell::new(HashMap::new()),
+            #[cfg(feature = "async")]
+            executor: Executor::new(),
         }
     }
 
+    #[cfg(feature = "testing")]
+    fn reset(&self) {
+        self.new_root.set(None);
+        self.roots.borrow_mut().clear();
+        self.new_stream.set(None);
+        self.streams.borrow_mut().clear();
+        self.new_http_stream.set(None);
+        self.http_streams.borrow_mut().clear();
+        self.active_id.set(0);
+        self.callouts.borrow_mut().clear();
+        self.typed_callouts.borrow_mut().clear();
+        self.grpc_callouts.borrow_mut().clear();
+        self.grpc_streams.borrow_mut().clear();
+        self.grpc_stream_handlers.borrow_mut().clear();
+        self.panic_policy.replace(PanicPolicy::default());
+        self.utf8_policy.set(Utf8Policy::default());
+        self.http_callback.set(None);
+        self.poisoned.borrow_mut().clear();
+        self.parents.borrow_mut().clear();
+        self.timers.borrow_mut().clear();
+        self.next_timer_id.set(1);
+        self.schedulers.borrow_mut().clear();
+        #[cfg(feature = "async")]
+        self.executor.reset();
+    }
+
     fn set_root_context(&self, callback: NewRootContext) {
         self.new_root.set(Some(callback));
     }
@@ -93,15 +290,220 @@ impl Dispatcher {
         self.new_http_stream.set(Some(callback));
     }
 
-    fn register_callout(&self, token_id: u32) {
+    fn set_panic_policy(&self, policy: PanicPolicy) {
+        self.panic_policy.replace(policy);
+    }
+
+    fn is_poisoned(&self, context_id: u32) -> bool {
+        self.poisoned.borrow().contains(&context_id)
+    }
+
+    // Called from the panic hook, before unwinding (or aborting), while the
+    // panicking callback still holds its context.
+    fn on_panic(&self) {
+        let context_id = match self.http_callback.get() {
+            Some(context_id) => context_id,
+            None => return,
+        };
+        if let PanicPolicy::Isolate {
+            status_code,
+            headers,
+            body,
+        } = &*self.panic_policy.borrow()
+        {
+            self.poisoned.borrow_mut().insert(context_id);
+            let headers = headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect();
+            if let Err(error) =
+                hostcalls::try_send_http_response(*status_code, headers, body.as_deref())
+            {
+                warn!("#{}: failed to send local response: {}", context_id, error);
+            }
+        }
+    }
+
+    fn contain<T>(&self, context_id: u32, default: T, callback: impl FnOnce() -> T) -> T {
+        let previous = self.http_callback.replace(Some(context_id));
+        let result = self.catch(callback);
+        self.http_callback.set(previous);
+        result.unwrap_or_else(|| {
+            self.poisoned.borrow_mut().insert(context_id);
+            default
+        })
+    }
+
+    #[cfg(panic = "unwind")]
+    fn catch<T>(&self, callback: impl FnOnce() -> T) -> Option<T> {
+        if *self.panic_policy.borrow() == PanicPolicy::Abort {
+            return Some(callback());
+        }
+        panic::catch_unwind(AssertUnwindSafe(callback)).ok()
+    }
+
+    #[cfg(not(panic = "unwind"))]
+    fn catch<T>(&self, callback: impl FnOnce() -> T) -> Option<T> {
+        Some(callback())
+    }
+
+    fn root_of(&self, context_id: u32) -> u32 {
+        match self.parents.borrow().get(&context_id) {
+            Some(root_id) => *root_id,
+            None => context_id,
+        }
+    }
+
+    fn set_tick_period(&self, period: Duration) -> Result<(), Error> {
+        let root_id = self.root_of(self.active_id.get());
+        {
+            let mut schedulers = self.schedulers.borrow_mut();
+            let scheduler = schedulers.entry(root_id).or_default();
+            scheduler.user_period = if period.as_millis() == 0 {
+                None
+            } else {
+                Some(period)
+            };
+            scheduler.next_user_tick = None;
+        }
+        self.update_tick_period(root_id)
+    }
+
+    fn schedule_timer(&self, delay: Duration, interval: Option<Duration>) -> Result<u32, Error> {
+        let context_id = self.active_id.get();
+        let root_id = self.root_of(context_id);
+        let now = hostcalls::try_get_current_time()?;
+        let timer_id = self.next_timer_id.get();
+        self.next_timer_id.set(timer_id.checked_add(1).unwrap_or(1));
+        let timer = Timer {
+            context_id,
+            root_id,
+            deadline: now + delay,
+            interval,
+            period: interval.unwrap_or(delay),
+        };
+        self.timers.borrow_mut().insert(timer_id, timer);
+        if let Err(error) = self.update_tick_period(root_id) {
+            self.timers.borrow_mut().remove(&timer_id);
+            return Err(error);
+        }
+        Ok(timer_id)
+    }
+
+    fn cancel_timer(&self, timer_id: u32) -> bool {
+        let timer = match self.timers.borrow_mut().remove(&timer_id) {
+            Some(timer) => timer,
+            None => return false,
+        };
+        if let Err(error) = self.update_tick_period(timer.root_id) {
+            warn!(
+                "#{}: failed to update tick period: {}",
+                timer.root_id, error
+            );
+        }
+        true
+    }
+
+    fn update_tick_period(&self, root_id: u32) -> Result<(), Error> {
+        let mut schedulers = self.schedulers.borrow_mut();
+        let scheduler = schedulers.entry(root_id).or_default();
+        let mut periods: Vec<u64> = self
+            .timers
+            .borrow()
+            .values()
+            .filter(|timer| timer.root_id == root_id)
+            .map(|timer| tick_millis(timer.period))
+            .collect();
+        periods.extend(scheduler.user_period.map(tick_millis));
+        if self.has_callout_deadlines(root_id) {
+            periods.push(tick_millis(CALLOUT_GRACE_PERIOD));
+        }
+        let millis = match periods.iter().min() {
+            Some(shortest) => {
+                let min_millis = tick_millis(MIN_TICK_PERIOD).min(*shortest);
+                periods.iter().fold(0, |a, b| gcd(a, *b)).max(min_millis)
+            }
+            None => 0,
+        };
+        let period = Duration::from_millis(millis);
+        if period == scheduler.tick_period {
+            return Ok(());
+        }
+        // The tick period is set on the root context, which might not be
+        // the effective context when scheduling from a stream context.
+        let active_id = self.active_id.get();
+        if active_id != root_id {
+            hostcalls::try_set_effective_context(root_id)?;
+        }
+        let result = hostcalls::try_set_host_tick_period(period);
+        if active_id != root_id {
+            // The active context might have been deleted already.
+            let _ = hostcalls::try_set_effective_context(active_id);
+        }
+        result?;
+        scheduler.tick_period = period;
+        Ok(())
+    }
+
+    // Spawned tasks are polled right away, and the spawning context is
+    // restored afterwards, since other woken tasks might have run as well.
+    #[cfg(feature = "async")]
+    fn spawn(&self, task: std::pin::Pin<Box<dyn Future<Output = ()>>>) {
+        self.executor.spawn(self.active_id.get(), task);
+        self.run_tasks_and_restore();
+    }
+
+    #[cfg(feature = "async")]
+    fn run_tasks_and_restore(&self) {
+        let active_id = self.active_id.get();
+        self.run_tasks();
+        if self.active_id.get() != active_id {
+            self.active_id.set(active_id);
+            hostcalls::set_effective_context(active_id).unwrap();
+        }
+    }
+
+    // Polls all woken tasks, each one within its own context.
+    #[cfg(feature = "async")]
+    fn run_tasks(&self) {
+        while let Some((task_id, context_id)) = self.executor.next_ready() {
+            if context_id != self.active_id.get() {
+                self.active_id.set(context_id);
+                hostcalls::set_effective_context(context_id).unwrap();
+            }
+            self.executor.poll(task_id);
+        }
+    }
+
+    // Completes async callouts. The effective context is unknown at this
+    // point, so it's set again before polling any task.
+    #[cfg(feature = "async")]
+    fn run_callout_tasks(&self, completed: bool) -> bool {
+        if completed {
+            self.active_id.set(0);
+            self.run_tasks();
+        }
+        completed
+    }
+
+    fn register_callout(&self, token_id: u32, timeout: Duration) {
+        let callout = Callout::new(self.active_id.get(), timeout);
+        let has_deadline = callout.deadline.is_some();
         if self
             .callouts
             .borrow_mut()
-            .insert(token_id, self.active_id.get())
+            .insert(token_id, callout)
             .is_some()
         {
             panic!("duplicate token_id")
         }
+        if has_deadline {
+            self.schedule_sweep();
+        }
+    }
+
+    fn register_typed_callout(&self, token_id: u32) {
+        self.typed_callouts.borrow_mut().insert(token_id);
     }
 
     fn register_grpc_stream(&self, token_id: u32) {
@@ -115,15 +517,190 @@ impl Dispatcher {
         }
     }
 
-    fn register_grpc_callout(&self, token_id: u32) {
+    fn register_grpc_stream_handler(&self, token_id: u32, handler: Box<dyn StreamCallbacks>) {
+        self.grpc_stream_handlers
+            .borrow_mut()
+            .insert(token_id, handler);
+    }
+
+    fn register_grpc_callout(&self, token_id: u32, timeout: Duration) {
+        let callout = Callout::new(self.active_id.get(), timeout);
+        let has_deadline = callout.deadline.is_some();
         if self
             .grpc_callouts
             .borrow_mut()
-            .insert(token_id, self.active_id.get())
+            .insert(token_id, callout)
             .is_some()
         {
             panic!("duplicate token_id")
         }
+        if has_deadline {
+            self.schedule_sweep();
+        }
+    }
+
+    // Makes sure that the root of the active context ticks while it has
+    // callouts with a deadline.
+    fn schedule_sweep(&self) {
+        let root_id = self.root_of(self.active_id.get());
+        if let Err(error) = self.update_tick_period(root_id) {
+            warn!("#{}: failed to update tick period: {}", root_id, error);
+        }
+    }
+
+    fn has_callout_deadlines(&self, root_id: u32) -> bool {
+        self.callouts
+            .borrow()
+            .values()
+            .chain(self.grpc_callouts.borrow().values())
+            .any(|callout| {
+                callout.deadline.is_some() && self.root_of(callout.context_id) == root_id
+            })
+    }
+
+    fn pending_callouts(&self, context_id: u32) -> Vec<u32> {
+        let mut token_ids: Vec<u32> = self
+            .callouts
+            .borrow()
+            .iter()
+            .chain(self.grpc_callouts.borrow().iter())
+            .filter(|(_, callout)| callout.context_id == context_id)
+            .map(|(token_id, _)| *token_id)
+            .chain(
+                self.grpc_streams
+                    .borrow()
+                    .iter()
+                    .filter(|(_, id)| **id == context_id)
+                    .map(|(token_id, _)| *token_id),
+            )
+            .collect();
+        token_ids.sort_unstable();
+        token_ids
+    }
+
+    // Removes a callout cancelled by the plugin, so that a late response is
+    // dropped. Pending futures fail with `CancelReason::Cancelled`.
+    fn forget_callout(&self, token_id: u32) -> bool {
+        let found = self.callouts.borrow_mut().remove(&token_id).is_some()
+            | self.grpc_callouts.borrow_mut().remove(&token_id).is_some()
+            | self.grpc_streams.borrow_mut().remove(&token_id).is_some();
+        self.typed_callouts.borrow_mut().remove(&token_id);
+        self.grpc_stream_handlers.borrow_mut().remove(&token_id);
+        #[cfg(feature = "async")]
+        if self.executor.cancel(token_id, CancelReason::Cancelled) {
+            self.run_tasks_and_restore();
+        }
+        found
+    }
+
+    // Cancels all callouts of a context that is being deleted, and callouts
+    // that weren't completed by the host in time.
+    fn cancel_callouts(&self, context_id: Option<u32>, now: Option<SystemTime>) -> Vec<(u32, u32)> {
+        let expired = |callout: &Callout| match (context_id, now, callout.deadline) {
+            (Some(context_id), _, _) => callout.context_id == context_id,
+            (None, Some(now), Some(deadline)) => deadline < now,
+            _ => false,
+        };
+        let mut cancelled = Vec::new();
+        self.callouts.borrow_mut().retain(|token_id, callout| {
+            if expired(callout) {
+                cancelled.push((*token_id, callout.context_id));
+                return false;
+            }
+            true
+        });
+        for (token_id, _) in &cancelled {
+            self.typed_callouts.borrow_mut().remove(token_id);
+        }
+        let mut grpc_cancelled = Vec::new();
+        self.grpc_callouts.borrow_mut().retain(|token_id, callout| {
+            if expired(callout) {
+                grpc_cancelled.push((*token_id, callout.context_id));
+                return false;
+            }
+            true
+        });
+        if let Some(context_id) = context_id {
+            self.grpc_streams.borrow_mut().retain(|token_id, id| {
+                if *id == context_id {
+                    grpc_cancelled.push((*token_id, *id));
+                    self.grpc_stream_handlers.borrow_mut().remove(token_id);
+                    return false;
+                }
+                true
+            });
+        }
+        for (token_id, _) in &grpc_cancelled {
+            if let Err(error) = hostcalls::try_cancel_grpc_call(*token_id) {
+                trace!("failed to cancel gRPC callout {}: {}", token_id, error);
+            }
+        }
+        cancelled.extend(grpc_cancelled);
+        cancelled.sort_unstable();
+        cancelled
+    }
+
+    fn sweep_callouts(&self) {
+        let has_deadlines = self
+            .callouts
+            .borrow()
+            .values()
+            .chain(self.grpc_callouts.borrow().values())
+            .any(|callout| callout.deadline.is_some());
+        if !has_deadlines {
+            return;
+        }
+        let now = match hostcalls::try_get_current_time() {
+            Ok(now) => now,
+            Err(_) => return,
+        };
+        for (token_id, context_id) in self.cancel_callouts(None, Some(now)) {
+            warn!("#{}: callout {} timed out", context_id, token_id);
+            #[cfg(feature = "async")]
+            if self.run_callout_tasks(self.executor.cancel(token_id, CancelReason::TimedOut)) {
+                continue;
+            }
+            self.on_callout_cancelled(context_id, token_id, CancelReason::TimedOut);
+        }
+    }
+
+    // Called from `proxy_on_delete` and from ticks, when the host might not
+    // accept the context as the effective context anymore, in which case the
+    // callback is skipped.
+    fn on_callout_cancelled(&self, context_id: u32, token_id: u32, reason: CancelReason) {
+        if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
+            self.active_id.set(context_id);
+            if self.is_poisoned(context_id) || !self.try_set_effective_context(context_id) {
+                return;
+            }
+            self.contain(context_id, (), || {
+                http_stream.on_callout_cancelled(token_id, reason)
+            })
+        } else if let Some(stream) = self.streams.borrow_mut().get_mut(&context_id) {
+            self.active_id.set(context_id);
+            if self.try_set_effective_context(context_id) {
+                stream.on_callout_cancelled(token_id, reason)
+            }
+        } else if let Some(root) = self.roots.borrow_mut().get_mut(&context_id) {
+            self.active_id.set(context_id);
+            if self.try_set_effective_context(context_id) {
+                root.on_callout_cancelled(token_id, reason)
+            }
+        }
+    }
+
+    fn try_set_effective_context(&self, context_id: u32) -> bool {
+        match hostcalls::try_set_effective_context(context_id) {
+            Ok(()) => true,
+            Err(error) => {
+                trace!(
+                    "#{}: failed to set effective context: {}",
+                    context_id,
+                    error
+                );
+                false
+            }
+        }
     }
 
     fn create_root_context(&self, context_id: u32) {
@@ -184,6 +761,11 @@ impl Dispatcher {
     }
 
     fn on_create_context(&self, context_id: u32, root_context_id: u32) {
+        if root_context_id != 0 {
+            self.parents
+                .borrow_mut()
+                .insert(context_id, root_context_id);
+        }
         if root_context_id == 0 {
             self.create_root_context(context_id);
         } else if self.new_http_stream.get().is_some() {
@@ -208,7 +790,10 @@ impl Dispatcher {
     fn on_done(&self, context_id: u32) -> bool {
         if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
-            http_stream.on_done()
+            if self.is_poisoned(context_id) {
+                return true;
+            }
+            self.contain(context_id, true, || http_stream.on_done())
         } else if let Some(stream) = self.streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
             stream.on_done()
@@ -223,7 +808,10 @@ impl Dispatcher {
     fn on_log(&self, context_id: u32) {
         if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
-            http_stream.on_log()
+            if self.is_poisoned(context_id) {
+                return;
+            }
+            self.contain(context_id, (), || http_stream.on_log())
         } else if let Some(stream) = self.streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
             stream.on_log()
@@ -237,6 +825,21 @@ impl Dispatcher {
 
     fn on_delete(&self, context_id: u32) {
         info!("on delete invoked for {}", context_id);
+        #[cfg(feature = "async")]
+        self.executor.drop_tasks(context_id);
+        for (token_id, _) in self.cancel_callouts(Some(context_id), None) {
+            #[cfg(feature = "async")]
+            if self.executor.cancel(token_id, CancelReason::ContextDeleted) {
+                continue;
+            }
+            self.on_callout_cancelled(context_id, token_id, CancelReason::ContextDeleted);
+        }
+        self.poisoned.borrow_mut().remove(&context_id);
+        self.parents.borrow_mut().remove(&context_id);
+        self.schedulers.borrow_mut().remove(&context_id);
+        self.timers
+            .borrow_mut()
+            .retain(|_, timer| timer.context_id != context_id);
         if !(self.http_streams.borrow_mut().remove(&context_id).is_some()
             || self.streams.borrow_mut().remove(&context_id).is_some()
             || self.roots.borrow_mut().remove(&context_id).is_some())
@@ -264,18 +867,150 @@ impl Dispatcher {
     }
 
     fn on_tick(&self, context_id: u32) {
+        if !self.roots.borrow().contains_key(&context_id) {
+            panic!("invalid context_id")
+        }
+        self.tick(context_id);
+        self.sweep_callouts();
+        // Restore the requested period after the last timer or callout is gone.
+        if let Err(error) = self.update_tick_period(context_id) {
+            warn!("#{}: failed to update tick period: {}", context_id, error);
+        }
+    }
+
+    fn tick(&self, context_id: u32) {
+        self.active_id.set(context_id);
+        let has_timers = self
+            .timers
+            .borrow()
+            .values()
+            .any(|timer| timer.root_id == context_id);
+        // Without timers, every tick is due, unless callout sweeps are
+        // multiplexed onto the requested period.
+        let multiplexed = match self.schedulers.borrow().get(&context_id) {
+            Some(scheduler) => match scheduler.user_period {
+                Some(period) => Duration::from_millis(tick_millis(period)) != scheduler.tick_period,
+                None => false,
+            },
+            None => false,
+        };
+        if !has_timers && !multiplexed {
+            let user_tick = match self.schedulers.borrow_mut().get_mut(&context_id) {
+                Some(scheduler) => {
+                    scheduler.next_user_tick = None;
+                    scheduler.user_period.is_some()
+                }
+                None => false,
+            };
+            if user_tick {
+                if let Some(root) = self.roots.borrow_mut().get_mut(&context_id) {
+                    root.on_tick()
+                }
+            }
+            return;
+        }
+
+        let now = hostcalls::get_current_time().unwrap();
+        // Ticks might be slightly early or late, so anything due within half
+        // a tick is considered due now.
+        let (user_tick, tolerance) = match self.schedulers.borrow_mut().get_mut(&context_id) {
+            Some(scheduler) => {
+                let tolerance = scheduler.tick_period / 2;
+                let user_tick = match (scheduler.user_period, scheduler.next_user_tick) {
+                    (Some(_), Some(next)) if next > now + tolerance => false,
+                    (Some(period), _) => {
+                        scheduler.next_user_tick = Some(now + period);
+                        true
+                    }
+                    (None, _) => false,
+                };
+                (user_tick, tolerance)
+            }
+            None => (false, Duration::ZERO),
+        };
+        if user_tick {
+            if let Some(root) = self.roots.borrow_mut().get_mut(&context_id) {
+                root.on_tick()
+            }
+        }
+
+        let mut due: Vec<(SystemTime, u32)> = self
+            .timers
+            .borrow()
+            .iter()
+            .filter(|(_, timer)| timer.root_id == context_id && timer.deadline <= now + tolerance)
+            .map(|(timer_id, timer)| (timer.deadline, *timer_id))
+            .collect();
+        due.sort();
+        for (_, timer_id) in due {
+            // Timers might be cancelled by the callbacks of earlier timers.
+            let timer_context_id = {
+                let mut timers = self.timers.borrow_mut();
+                let timer = match timers.get_mut(&timer_id) {
+                    Some(timer) => timer,
+                    None => continue,
+                };
+                let timer_context_id = timer.context_id;
+                match timer.interval {
+                    Some(interval) => {
+                        timer.deadline += interval;
+                        if timer.deadline <= now {
+                            timer.deadline = now + interval;
+                        }
+                    }
+                    None => {
+                        timers.remove(&timer_id);
+                    }
+                }
+                timer_context_id
+            };
+            self.on_timer(timer_context_id, timer_id);
+        }
+    }
+
+    // Timers of contexts that the host doesn't accept as the effective
+    // context anymore are dropped.
+    fn on_timer(&self, context_id: u32, timer_id: u32) {
+        if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
+            self.active_id.set(context_id);
+            if self.is_poisoned(context_id) {
+                return;
+            }
+            if !self.try_set_effective_context(context_id) {
+                self.timers.borrow_mut().remove(&timer_id);
+                return;
+            }
+            self.contain(context_id, (), || http_stream.on_timer(timer_id))
+        } else if let Some(stream) = self.streams.borrow_mut().get_mut(&context_id) {
+            self.active_id.set(context_id);
+            if !self.try_set_effective_context(context_id) {
+                self.timers.borrow_mut().remove(&timer_id);
+                return;
+            }
+            stream.on_timer(timer_id)
+        } else if let Some(root) = self.roots.borrow_mut().get_mut(&context_id) {
+            self.active_id.set(context_id);
+            if !self.try_set_effective_context(context_id) {
+                self.timers.borrow_mut().remove(&timer_id);
+                return;
+            }
+            root.on_timer(timer_id)
+        }
+    }
+
+    fn on_queue_ready(&self, context_id: u32, queue_id: u32) {
         if let Some(root) = self.roots.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
-            root.on_tick()
+            root.on_queue_ready(queue_id)
         } else {
             panic!("invalid context_id")
         }
     }
 
-    fn on_queue_ready(&self, context_id: u32, queue_id: u32) {
+    fn on_foreign_function(&self, context_id: u32, function_id: u32, data_size: usize) {
         if let Some(root) = self.roots.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
-            root.on_queue_ready(queue_id)
+            root.on_foreign_function(function_id, data_size)
         } else {
             panic!("invalid context_id")
         }
@@ -334,7 +1069,12 @@ impl Dispatcher {
     ) -> Action {
         if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
-            http_stream.on_http_request_headers(num_headers, end_of_stream)
+            if self.is_poisoned(context_id) {
+                return Action::Continue;
+            }
+            self.contain(context_id, Action::Pause, || {
+                http_stream.on_http_request_headers(num_headers, end_of_stream)
+            })
         } else {
             panic!("invalid context_id")
         }
@@ -348,7 +1088,12 @@ impl Dispatcher {
     ) -> Action {
         if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
-            http_stream.on_http_request_body(body_size, end_of_stream)
+            if self.is_poisoned(context_id) {
+                return Action::Continue;
+            }
+            self.contain(context_id, Action::Pause, || {
+                http_stream.on_http_request_body(body_size, end_of_stream)
+            })
         } else {
             panic!("invalid context_id")
         }
@@ -357,7 +1102,12 @@ impl Dispatcher {
     fn on_http_request_trailers(&self, context_id: u32, num_trailers: usize) -> Action {
         if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
-            http_stream.on_http_request_trailers(num_trailers)
+            if self.is_poisoned(context_id) {
+                return Action::Continue;
+            }
+            self.contain(context_id, Action::Pause, || {
+                http_stream.on_http_request_trailers(num_trailers)
+            })
         } else {
             panic!("invalid context_id")
         }
@@ -371,7 +1121,12 @@ impl Dispatcher {
     ) -> Action {
         if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
-            http_stream.on_http_response_headers(num_headers, end_of_stream)
+            if self.is_poisoned(context_id) {
+                return Action::Continue;
+            }
+            self.contain(context_id, Action::Pause, || {
+                http_stream.on_http_response_headers(num_headers, end_of_stream)
+            })
         } else {
             panic!("invalid context_id")
         }
@@ -385,7 +1140,12 @@ impl Dispatcher {
     ) -> Action {
         if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
-            http_stream.on_http_response_body(body_size, end_of_stream)
+            if self.is_poisoned(context_id) {
+                return Action::Continue;
+            }
+            self.contain(context_id, Action::Pause, || {
+                http_stream.on_http_response_body(body_size, end_of_stream)
+            })
         } else {
             panic!("invalid context_id")
         }
@@ -394,7 +1154,12 @@ impl Dispatcher {
     fn on_http_response_trailers(&self, context_id: u32, num_trailers: usize) -> Action {
         if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
-            http_stream.on_http_response_trailers(num_trailers)
+            if self.is_poisoned(context_id) {
+                return Action::Continue;
+            }
+            self.contain(context_id, Action::Pause, || {
+                http_stream.on_http_response_trailers(num_trailers)
+            })
         } else {
             panic!("invalid context_id")
         }
@@ -407,27 +1172,98 @@ impl Dispatcher {
         body_size: usize,
         num_trailers: usize,
     ) {
-        let context_id = self
-            .callouts
-            .borrow_mut()
-            .remove(&token_id)
-            .expect("invalid token_id");
+        let context_id = match self.callouts.borrow_mut().remove(&token_id) {
+            Some(callout) => callout.context_id,
+            None => {
+                // Cancelled, timed out, or its context was deleted.
+                trace!("on_http_call_response: unknown token_id {}", token_id);
+                return;
+            }
+        };
+        let typed = self.typed_callouts.borrow_mut().remove(&token_id);
+
+        #[cfg(feature = "async")]
+        if self.run_callout_tasks(self.executor.on_http_call_response(
+            token_id,
+            num_headers,
+            body_size,
+            num_trailers,
+        )) {
+            return;
+        }
 
         if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
+            if self.is_poisoned(context_id) {
+                return;
+            }
             hostcalls::set_effective_context(context_id).unwrap();
-            http_stream.on_http_call_response(token_id, num_headers, body_size, num_trailers)
+            self.contain(context_id, (), || {
+                deliver_http_call_response(
+                    &mut **http_stream,
+                    typed,
+                    token_id,
+                    num_headers,
+                    body_size,
+                    num_trailers,
+                )
+            })
         } else if let Some(stream) = self.streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
             hostcalls::set_effective_context(context_id).unwrap();
-            stream.on_http_call_response(token_id, num_headers, body_size, num_trailers)
+            deliver_http_call_response(
+                &mut **stream,
+                typed,
+                token_id,
+                num_headers,
+                body_size,
+                num_trailers,
+            )
         } else if let Some(root) = self.roots.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
             hostcalls::set_effective_context(context_id).unwrap();
-            root.on_http_call_response(token_id, num_headers, body_size, num_trailers)
+            deliver_http_call_response(
+                &mut **root,
+                typed,
+                token_id,
+                num_headers,
+                body_size,
+                num_trailers,
+            )
         }
     }
 
+    // Delivers an event to the handler of a stream opened with
+    // `GrpcClient::open_stream`, within the context that opened it. The
+    // handler is taken out of the map while it runs, since it might open or
+    // cancel streams, and is put back only if the stream is still open.
+    fn deliver_to_stream_handler(
+        &self,
+        token_id: u32,
+        context_id: u32,
+        event: impl FnOnce(&mut dyn StreamCallbacks),
+    ) -> bool {
+        let mut handler = match self.grpc_stream_handlers.borrow_mut().remove(&token_id) {
+            Some(handler) => handler,
+            None => return false,
+        };
+        self.active_id.set(context_id);
+        if !self.is_poisoned(context_id) {
+            hostcalls::set_effective_context(context_id).unwrap();
+            if self.http_streams.borrow().contains_key(&context_id) {
+                self.contain(context_id, (), || event(&mut *handler));
+            } else {
+                event(&mut *handler);
+            }
+        }
+        if self.grpc_streams.borrow().contains_key(&token_id) {
+            self.grpc_stream_handlers
+                .borrow_mut()
+                .insert(token_id, handler);
+        }
+        true
+    }
+
     fn on_grpc_receive_initial_metadata(&self, token_id: u32, headers: u32) {
         let context_id = match self.grpc_streams.borrow_mut().get(&token_id) {
             Some(id) => *id,
@@ -437,11 +1273,21 @@ impl Dispatcher {
                 return;
             }
         };
+        if self.deliver_to_stream_handler(token_id, context_id, |handler| {
+            handler.on_initial_metadata()
+        }) {
+            return;
+        }
 
         if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
+            if self.is_poisoned(context_id) {
+                return;
+            }
             hostcalls::set_effective_context(context_id).unwrap();
-            http_stream.on_grpc_stream_initial_metadata(token_id, headers);
+            self.contain(context_id, (), || {
+                http_stream.on_grpc_stream_initial_metadata(token_id, headers)
+            });
         } else if let Some(stream) = self.streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
             hostcalls::set_effective_context(context_id).unwrap();
@@ -454,12 +1300,31 @@ impl Dispatcher {
     }
 
     fn on_grpc_receive(&self, token_id: u32, response_size: usize) {
-        let context_id = self.grpc_callouts.borrow_mut().remove(&token_id);
+        #[cfg(feature = "async")]
+        if self.run_callout_tasks(self.executor.on_grpc_call_response(
+            token_id,
+            GrpcStatusCode::Ok as u32,
+            response_size,
+        )) {
+            self.grpc_callouts.borrow_mut().remove(&token_id);
+            return;
+        }
+
+        let context_id = self
+            .grpc_callouts
+            .borrow_mut()
+            .remove(&token_id)
+            .map(|callout| callout.context_id);
         if let Some(context_id) = context_id {
             if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
                 self.active_id.set(context_id);
+                if self.is_poisoned(context_id) {
+                    return;
+                }
                 hostcalls::set_effective_context(context_id).unwrap();
-                http_stream.on_grpc_call_response(token_id, 0, response_size);
+                self.contain(context_id, (), || {
+                    http_stream.on_grpc_call_response(token_id, 0, response_size)
+                });
             } else if let Some(stream) = self.streams.borrow_mut().get_mut(&context_id) {
                 self.active_id.set(context_id);
                 hostcalls::set_effective_context(context_id).unwrap();
@@ -472,10 +1337,20 @@ impl Dispatcher {
         } else {
             let context_id = self.grpc_streams.borrow().get(&token_id).cloned();
             if let Some(context_id) = context_id {
+                if self.deliver_to_stream_handler(token_id, context_id, |handler| {
+                    handler.on_message(response_size)
+                }) {
+                    return;
+                }
                 if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
                     self.active_id.set(context_id);
+                    if self.is_poisoned(context_id) {
+                        return;
+                    }
                     hostcalls::set_effective_context(context_id).unwrap();
-                    http_stream.on_grpc_stream_message(token_id, response_size);
+                    self.contain(context_id, (), || {
+                        http_stream.on_grpc_stream_message(token_id, response_size)
+                    });
                 } else if let Some(stream) = self.streams.borrow_mut().get_mut(&context_id) {
                     self.active_id.set(context_id);
                     hostcalls::set_effective_context(context_id).unwrap();
@@ -501,11 +1376,21 @@ impl Dispatcher {
                 return;
             }
         };
+        if self.deliver_to_stream_handler(token_id, context_id, |handler| {
+            handler.on_trailing_metadata()
+        }) {
+            return;
+        }
 
         if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
+            if self.is_poisoned(context_id) {
+                return;
+            }
             hostcalls::set_effective_context(context_id).unwrap();
-            http_stream.on_grpc_stream_trailing_metadata(token_id, trailers);
+            self.contain(context_id, (), || {
+                http_stream.on_grpc_stream_trailing_metadata(token_id, trailers)
+            });
         } else if let Some(stream) = self.streams.borrow_mut().get_mut(&context_id) {
             self.active_id.set(context_id);
             hostcalls::set_effective_context(context_id).unwrap();
@@ -518,12 +1403,30 @@ impl Dispatcher {
     }
 
     fn on_grpc_close(&self, token_id: u32, status_code: u32) {
-        let context_id = self.grpc_callouts.borrow_mut().remove(&token_id);
+        #[cfg(feature = "async")]
+        if self.run_callout_tasks(
+            self.executor
+                .on_grpc_call_response(token_id, status_code, 0),
+        ) {
+            self.grpc_callouts.borrow_mut().remove(&token_id);
+            return;
+        }
+
+        let context_id = self
+            .grpc_callouts
+            .borrow_mut()
+            .remove(&token_id)
+            .map(|callout| callout.context_id);
         if let Some(context_id) = context_id {
             if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
                 self.active_id.set(context_id);
+                if self.is_poisoned(context_id) {
+                    return;
+                }
                 hostcalls::set_effective_context(context_id).unwrap();
-                http_stream.on_grpc_call_response(token_id, status_code, 0);
+                self.contain(context_id, (), || {
+                    http_stream.on_grpc_call_response(token_id, status_code, 0)
+                });
             } else if let Some(stream) = self.streams.borrow_mut().get_mut(&context_id) {
                 self.active_id.set(context_id);
                 hostcalls::set_effective_context(context_id).unwrap();
@@ -536,10 +1439,20 @@ impl Dispatcher {
         } else {
             let context_id = self.grpc_streams.borrow_mut().remove(&token_id);
             if let Some(context_id) = context_id {
+                if self.deliver_to_stream_handler(token_id, context_id, |handler| {
+                    handler.on_close(status_code)
+                }) {
+                    return;
+                }
                 if let Some(http_stream) = self.http_streams.borrow_mut().get_mut(&context_id) {
                     self.active_id.set(context_id);
+                    if self.is_poisoned(context_id) {
+                        return;
+                    }
                     hostcalls::set_effective_context(context_id).unwrap();
-                    http_stream.on_grpc_stream_close(token_id, status_code)
+                    self.contain(context_id, (), || {
+                        http_stream.on_grpc_stream_close(token_id, status_code)
+                    })
                 } else if let Some(stream) = self.streams.borrow_mut().get_mut(&context_id) {
                     self.active_id.set(context_id);
                     hostcalls::set_effective_context(context_id).unwrap();
@@ -557,6 +1470,23 @@ impl Dispatcher {
     }
 }
 
+fn deliver_http_call_response<C: Context + ?Sized>(
+    context: &mut C,
+    typed: bool,
+    token_id: u32,
+    num_headers: usize,
+    body_size: usize,
+    num_trailers: usize,
+) {
+    if typed {
+        let response =
+            hostcalls::get_http_call_response(num_headers, body_size, num_trailers).unwrap();
+        context.on_http_call_result(token_id, response)
+    } else {
+        context.on_http_call_response(token_id, num_headers, body_size, num_trailers)
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn proxy_on_context_create(context_id: u32, root_context_id: u32) {
     DISPATCHER.with(|dispatcher| dispatcher.on_create_context(context_id, root_context_id))
@@ -597,6 +1527,11 @@ pub extern "C" fn proxy_on_queue_ready(context_id: u32, queue_id: u32) {
     DISPATCHER.with(|dispatcher| dispatcher.on_queue_ready(context_id, queue_id))
 }
 
+#[no_mangle]
+pub extern "C" fn proxy_on_foreign_function(context_id: u32, function_id: u32, data_size: usize) {
+    DISPATCHER.with(|dispatcher| dispatcher.on_foreign_function(context_id, function_id, data_size))
+}
+
 #[no_mangle]
 pub extern "C" fn proxy_on_new_connection(context_id: u32) -> Action {
     DISPATCHER.with(|dispatcher| dispatcher.on_new_connection(context_id))
@@ -723,3 +1658,315 @@ pub extern "C" fn proxy_on_grpc_receive_trailing_metadata(
 pub extern "C" fn proxy_on_grpc_close(_context_id: u32, token_id: u32, status_code: u32) {
     DISPATCHER.with(|dispatcher| dispatcher.on_grpc_close(token_id, status_code))
 }
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::codec::Text;
+    use crate::grpc::{GrpcClient, GrpcStreamHandler};
+    use crate::testing::MockHost;
+
+    thread_local! {
+        static EVENTS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn record(event: String) {
+        EVENTS.with(|events| events.borrow_mut().push(event));
+    }
+
+    fn events() -> Vec<String> {
+        EVENTS.with(|events| events.borrow_mut().drain(..).collect())
+    }
+
+    struct Root;
+
+    impl Context for Root {}
+
+    impl RootContext for Root {
+        fn on_configure(&mut self, _: usize) -> bool {
+            if self.get_plugin_configuration().as_deref() == Some(b"tick") {
+                self.set_tick_period(Duration::from_secs(1));
+            }
+            true
+        }
+
+        fn on_tick(&mut self) {
+            record("tick".to_string());
+        }
+
+        fn create_http_context(&self, context_id: u32) -> Option<Box<dyn HttpContext>> {
+            Some(Box::new(Filter {
+                context_id,
+                timer_id: None,
+            }))
+        }
+
+        fn get_type(&self) -> Option<ContextType> {
+            Some(ContextType::HttpContext)
+        }
+    }
+
+    // Runs the scenario selected by the `:path` of the request.
+    struct Filter {
+        context_id: u32,
+        timer_id: Option<u32>,
+    }
+
+    impl Filter {
+        fn dispatch(&self) -> u32 {
+            let headers = vec![
+                (":method", "GET"),
+                (":path", "/"),
+                (":authority", "upstream"),
+            ];
+            self.dispatch_http_call("upstream", headers, None, vec![], Duration::from_secs(2))
+                .unwrap()
+        }
+    }
+
+    impl Context for Filter {
+        fn on_http_call_response(&mut self, token_id: u32, _: usize, _: usize, _: usize) {
+            record(format!("#{} response {}", self.context_id, token_id));
+        }
+
+        fn on_callout_cancelled(&mut self, token_id: u32, reason: CancelReason) {
+            record(format!(
+                "#{} cancelled {} {}",
+                self.context_id, token_id, reason
+            ));
+        }
+
+        fn on_timer(&mut self, _: u32) {
+            record(format!("#{} timer", self.context_id));
+        }
+    }
+
+    impl HttpContext for Filter {
+        fn on_http_request_headers(&mut self, _: usize, _: bool) -> Action {
+            match self.get_http_request_header(":path").as_deref() {
+                Some("/panic") => {
+                    // The panic hook is process-wide, so it isn't installed
+                    // by these tests. Do what it does before unwinding.
+                    on_panic();
+                    panic!("boom");
+                }
+                Some("/timer") => {
+                    self.timer_id = Some(self.schedule_after(Duration::from_millis(1500)));
+                }
+                Some("/callout") => {
+                    self.dispatch();
+                }
+                Some("/every") => {
+                    self.schedule_every(Duration::from_millis(333));
+                    self.dispatch();
+                }
+                Some("/cancel") => {
+                    let token_id = self.dispatch();
+                    assert!(self.cancel_http_call(token_id));
+                }
+                Some("/stream") => {
+                    let handler = StreamHandler {
+                        context_id: self.context_id,
+                    };
+                    GrpcClient::new("upstream")
+                        .open_stream::<String, _, _>("svc.Svc", "Watch", vec![], Text, handler)
+                        .unwrap();
+                }
+                _ => return Action::Continue,
+            }
+            Action::Pause
+        }
+
+        fn on_http_response_headers(&mut self, _: usize, _: bool) -> Action {
+            record(format!("#{} response headers", self.context_id));
+            if let Some(timer_id) = self.timer_id.take() {
+                assert!(self.cancel_timer(timer_id));
+            }
+            Action::Continue
+        }
+    }
+
+    struct StreamHandler {
+        context_id: u32,
+    }
+
+    impl GrpcStreamHandler for StreamHandler {
+        type Message = String;
+
+        fn on_message(&mut self, message: String) {
+            record(format!("#{} message {}", self.context_id, message));
+        }
+
+        fn on_close(&mut self, status_code: GrpcStatusCode, _: Option<String>) {
+            record(format!("#{} close {:?}", self.context_id, status_code));
+        }
+    }
+
+    fn setup(plugin_configuration: Option<&[u8]>) -> (MockHost, u32) {
+        let host = MockHost::new();
+        crate::set_root_context(|_| Box::new(Root));
+        let root_id = host.create_root_context();
+        host.on_configure(root_id, plugin_configuration);
+        events();
+        (host, root_id)
+    }
+
+    fn request(host: &MockHost, root_id: u32, path: &str) -> u32 {
+        let context_id = host.create_http_context(root_id);
+        host.on_http_request_headers(context_id, vec![(":path", path)], false);
+        context_id
+    }
+
+    #[test]
+    fn panic_is_contained() {
+        let (host, root_id) = setup(None);
+        set_panic_policy(PanicPolicy::Isolate {
+            status_code: 500,
+            headers: vec![],
+            body: Some(b"oops".to_vec()),
+        });
+        let poisoned = host.create_http_context(root_id);
+        let action = host.on_http_request_headers(poisoned, vec![(":path", "/panic")], false);
+        assert_eq!(action, Action::Pause);
+        let response = host.local_response(poisoned).unwrap();
+        assert_eq!(response.status_code, 500);
+        assert_eq!(response.body.as_deref(), Some(&b"oops"[..]));
+
+        let healthy = request(&host, root_id, "/");
+        host.on_http_response_headers(poisoned, vec![], false);
+        host.on_http_response_headers(healthy, vec![], false);
+        assert_eq!(events(), [format!("#{} response headers", healthy)]);
+    }
+
+    #[test]
+    fn timer() {
+        let (host, root_id) = setup(None);
+        let context_id = request(&host, root_id, "/timer");
+        assert_eq!(host.tick_period(root_id), Some(Duration::from_millis(1500)));
+
+        host.advance_time(Duration::from_millis(1500));
+        host.on_tick(root_id);
+        assert_eq!(events(), [format!("#{} timer", context_id)]);
+        assert_eq!(host.tick_period(root_id), Some(Duration::ZERO));
+
+        // No tick period was requested, so `on_tick` isn't called.
+        host.on_tick(root_id);
+        assert!(events().is_empty());
+    }
+
+    #[test]
+    fn timer_of_torn_down_context() {
+        let (host, root_id) = setup(None);
+        request(&host, root_id, "/every");
+        host.fail_hostcall("proxy_set_effective_context", Status::BadArgument);
+        host.advance_time(Duration::from_millis(350));
+        host.on_tick(root_id);
+        assert!(events().is_empty());
+
+        // The repeating timer was dropped; only the callout keeps ticking.
+        host.clear_hostcall_failure("proxy_set_effective_context");
+        host.advance_time(Duration::from_secs(1));
+        host.on_tick(root_id);
+        assert!(events().is_empty());
+        assert_eq!(host.tick_period(root_id), Some(CALLOUT_GRACE_PERIOD));
+    }
+
+    #[test]
+    fn cancelled_timer() {
+        let (host, root_id) = setup(Some(b"tick"));
+        let context_id = request(&host, root_id, "/timer");
+        assert_eq!(host.tick_period(root_id), Some(Duration::from_millis(500)));
+
+        host.on_http_response_headers(context_id, vec![], false);
+        assert_eq!(host.tick_period(root_id), Some(Duration::from_secs(1)));
+        events();
+
+        host.advance_time(Duration::from_secs(2));
+        host.on_tick(root_id);
+        assert_eq!(events(), ["tick"]);
+    }
+
+    #[test]
+    fn timer_with_coprime_periods() {
+        let (host, root_id) = setup(None);
+        let context_id = request(&host, root_id, "/every");
+        assert_eq!(host.tick_period(root_id), Some(MIN_TICK_PERIOD));
+
+        for _ in 0..20 {
+            host.advance_time(MIN_TICK_PERIOD);
+            host.on_tick(root_id);
+        }
+        let timer = format!("#{} timer", context_id);
+        assert_eq!(events(), [timer.clone(), timer.clone(), timer]);
+    }
+
+    #[test]
+    fn cancelled_callout() {
+        let (host, root_id) = setup(None);
+        request(&host, root_id, "/cancel");
+        let token_id = host.http_calls()[0].token_id;
+        host.on_http_call_response(token_id, vec![(":status", "200")], None, vec![]);
+        assert!(events().is_empty());
+    }
+
+    #[test]
+    fn callout_timeout() {
+        let (host, root_id) = setup(None);
+        let context_id = request(&host, root_id, "/callout");
+        let token_id = host.http_calls()[0].token_id;
+        assert_eq!(host.tick_period(root_id), Some(CALLOUT_GRACE_PERIOD));
+
+        host.advance_time(Duration::from_secs(1));
+        host.on_tick(root_id);
+        assert!(events().is_empty());
+
+        host.advance_time(Duration::from_secs(3));
+        host.on_tick(root_id);
+        let cancelled = format!("#{} cancelled {} timed out", context_id, token_id);
+        assert_eq!(events(), [cancelled]);
+        assert_eq!(host.tick_period(root_id), Some(Duration::ZERO));
+
+        host.on_http_call_response(token_id, vec![(":status", "200")], None, vec![]);
+        assert!(events().is_empty());
+    }
+
+    #[test]
+    fn callout_of_deleted_context() {
+        let (host, root_id) = setup(None);
+        let context_id = request(&host, root_id, "/callout");
+        let token_id = host.http_calls()[0].token_id;
+        host.on_delete(context_id);
+        let cancelled = format!("#{} cancelled {} context deleted", context_id, token_id);
+        assert_eq!(events(), [cancelled]);
+
+        // The callback is skipped if the context can't be made effective.
+        let context_id = request(&host, root_id, "/callout");
+        host.fail_hostcall("proxy_set_effective_context", Status::BadArgument);
+        host.on_delete(context_id);
+        assert!(events().is_empty());
+    }
+
+    #[test]
+    fn grpc_stream_handler() {
+        let (host, root_id) = setup(None);
+        let context_id = request(&host, root_id, "/stream");
+        let token_id = host.grpc_streams()[0].token_id;
+        host.on_grpc_receive(token_id, b"one");
+        host.on_grpc_close(token_id, GrpcStatusCode::NotFound as u32, None);
+        host.on_grpc_receive(token_id, b"late");
+        assert_eq!(
+            events(),
+            [
+                format!("#{} message one", context_id),
+                format!("#{} close NotFound", context_id),
+            ]
+        );
+
+        let context_id = request(&host, root_id, "/stream");
+        let token_id = host.grpc_streams()[1].token_id;
+        host.on_delete(context_id);
+        host.on_grpc_receive(token_id, b"late");
+        let cancelled = format!("#{} cancelled {} context deleted", context_id, token_id);
+        assert_eq!(events(), [cancelled]);
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! HTTP content codings.
+//!
+//! Each coding is enabled by the feature of the same name, i.e. `gzip`,
+//! `deflate` and `brotli`. All of them are implemented in pure Rust, so they
+//! can be used on the `wasm32` targets.
+
+use crate::types::*;
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+use std::io::{Read, Write};
+
+/// Content coding of an HTTP body, as indicated by `content-encoding`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ContentEncoding {
+    Identity,
+    #[cfg(feature = "gzip")]
+    Gzip,
+    #[cfg(feature = "deflate")]
+    Deflate,
+    #[cfg(feature = "brotli")]
+    Brotli,
+}
+
+impl ContentEncoding {
+    /// Parses the value of `content-encoding`. Returns `None` for codings
+    /// that aren't enabled, and for multiple codings.
+    pub fn from_header(value: &str) -> Option<ContentEncoding> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" => Some(ContentEncoding::Identity),
+            #[cfg(feature = "gzip")]
+            "gzip" | "x-gzip" => Some(ContentEncoding::Gzip),
+            #[cfg(feature = "deflate")]
+            "deflate" => Some(ContentEncoding::Deflate),
+            #[cfg(feature = "brotli")]
+            "br" => Some(ContentEncoding::Brotli),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Identity => "identity",
+            #[cfg(feature = "gzip")]
+            ContentEncoding::Gzip => "gzip",
+            #[cfg(feature = "deflate")]
+            ContentEncoding::Deflate => "deflate",
+            #[cfg(feature = "brotli")]
+            ContentEncoding::Brotli => "br",
+        }
+    }
+
+    /// Decompresses `body`. Fails if the decompressed body is larger than
+    /// `max_size`, to bound the memory used by compression bombs.
+    pub fn decode(&self, body: &[u8], max_size: usize) -> Result<Bytes, Error> {
+        let decoded = match self {
+            ContentEncoding::Identity => body.to_vec(),
+            #[cfg(feature = "gzip")]
+            ContentEncoding::Gzip => {
+                // A gzip body can consist of multiple members, which decode to
+                // their concatenation.
+                read_to_end(flate2::read::MultiGzDecoder::new(body), max_size)?
+            }
+            #[cfg(feature = "deflate")]
+            ContentEncoding::Deflate => {
+                // "deflate" is the zlib format, but some servers send raw
+                // deflate data instead.
+                read_to_end(flate2::read::ZlibDecoder::new(body), max_size)
+                    .or_else(|_| read_to_end(flate2::read::DeflateDecoder::new(body), max_size))?
+            }
+            #[cfg(feature = "brotli")]
+            ContentEncoding::Brotli => {
+                read_to_end(brotli::Decompressor::new(body, 4096), max_size)?
+            }
+        };
+        if decoded.len() > max_size {
+            return Err(codec_error(format!(
+                "decoded body exceeds {} bytes",
+                max_size
+            )));
+        }
+        Ok(decoded)
+    }
+
+    pub fn encode(&self, body: &[u8]) -> Result<Bytes, Error> {
+        match self {
+            ContentEncoding::Identity => Ok(body.to_vec()),
+            #[cfg(feature = "gzip")]
+            ContentEncoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body).map_err(io_error)?;
+                encoder.finish().map_err(io_error)
+            }
+            #[cfg(feature = "deflate")]
+            ContentEncoding::Deflate => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body).map_err(io_error)?;
+                encoder.finish().map_err(io_error)
+            }
+            #[cfg(feature = "brotli")]
+            ContentEncoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                encoder.write_all(body).map_err(io_error)?;
+                Ok(encoder.into_inner())
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+fn read_to_end(reader: impl Read, max_size: usize) -> Result<Bytes, Error> {
+    let mut decoded = Vec::new();
+    let limit = (max_size as u64).saturating_add(1);
+    reader
+        .take(limit)
+        .read_to_end(&mut decoded)
+        .map_err(io_error)?;
+    Ok(decoded)
+}
+
+#[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+fn io_error(error: std::io::Error) -> Error {
+    codec_error(error.to_string())
+}
+
+fn codec_error(reason: String) -> Error {
+    Error::Codec { reason }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_header() {
+        assert_eq!(
+            ContentEncoding::from_header(""),
+            Some(ContentEncoding::Identity)
+        );
+        assert_eq!(
+            ContentEncoding::from_header(" Identity "),
+            Some(ContentEncoding::Identity)
+        );
+        assert_eq!(ContentEncoding::from_header("gzip, br"), None);
+        assert_eq!(ContentEncoding::from_header("compress"), None);
+    }
+
+    #[test]
+    fn identity() {
+        let encoding = ContentEncoding::Identity;
+        assert_eq!(encoding.encode(b"body").unwrap(), b"body");
+        assert_eq!(encoding.decode(b"body", 4).unwrap(), b"body");
+        assert!(encoding.decode(b"body", 3).is_err());
+    }
+
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    fn encodings() -> Vec<ContentEncoding> {
+        vec![
+            #[cfg(feature = "gzip")]
+            ContentEncoding::Gzip,
+            #[cfg(feature = "deflate")]
+            ContentEncoding::Deflate,
+            #[cfg(feature = "brotli")]
+            ContentEncoding::Brotli,
+        ]
+    }
+
+    #[test]
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    fn round_trip() {
+        let body = b"Hello, World! ".repeat(100);
+        for encoding in encodings() {
+            assert_eq!(
+                ContentEncoding::from_header(encoding.as_str()),
+                Some(encoding)
+            );
+            let encoded = encoding.encode(&body).unwrap();
+            assert!(encoded.len() < body.len(), "{:?}", encoding);
+            assert_eq!(encoding.decode(&encoded, body.len()).unwrap(), body);
+            assert!(encoding.decode(&encoded, body.len() - 1).is_err());
+            assert_eq!(
+                encoding.decode(&encoding.encode(b"").unwrap(), 0).unwrap(),
+                b""
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    fn compression_bomb() {
+        let body = vec![0; 16 * 1024 * 1024];
+        for encoding in encodings() {
+            let encoded = encoding.encode(&body).unwrap();
+            assert!(encoded.len() < 64 * 1024, "{:?}", encoding);
+            assert!(encoding.decode(&encoded, 1024 * 1024).is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    fn invalid_body() {
+        for encoding in encodings() {
+            assert!(encoding.decode(b"not compressed", 1024).is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn multiple_gzip_members() {
+        let encoding = ContentEncoding::Gzip;
+        let mut encoded = encoding.encode(b"Hello, ").unwrap();
+        encoded.extend(encoding.encode(b"World!").unwrap());
+        assert_eq!(encoding.decode(&encoded, 13).unwrap(), b"Hello, World!");
+        assert!(encoding.decode(&encoded, 12).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "deflate")]
+    fn raw_deflate() {
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"Hello, World!").unwrap();
+        let encoded = encoder.finish().unwrap();
+        let decoded = ContentEncoding::Deflate.decode(&encoded, 13).unwrap();
+        assert_eq!(decoded, b"Hello, World!");
+    }
+}
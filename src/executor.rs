@@ -0,0 +1,505 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Async/await support for HTTP and gRPC callouts.
+//!
+//! Futures are driven by a single-threaded executor owned by the dispatcher.
+//! Callout futures are completed from `proxy_on_http_call_response`,
+//! `proxy_on_grpc_receive` and `proxy_on_grpc_close`, keyed by token id, and
+//! tasks are polled with the effective context of the context that spawned
+//! them. Tasks are dropped when their context is deleted.
+//!
+//! Spawned futures must be `'static`, so they cannot borrow the context that
+//! spawns them. Use [`AsyncContext::handle`] to get a [`ContextHandle`] for
+//! reading and modifying the stream from within the task:
+//!
+//! ```no_run
+//! use proxy_wasm::executor::*;
+//! use proxy_wasm::traits::*;
+//! use proxy_wasm::types::*;
+//! use std::time::Duration;
+//!
+//! struct Filter;
+//!
+//! impl Context for Filter {}
+//!
+//! impl HttpContext for Filter {
+//!     fn on_http_request_headers(&mut self, _: usize, _: bool) -> Action {
+//!         let ctx = self.handle();
+//!         self.spawn_request(async move {
+//!             let request = HttpCallRequest::new("auth")
+//!                 .method("GET")
+//!                 .path("/check")
+//!                 .authority("auth")
+//!                 .timeout(Duration::from_secs(1));
+//!             let response = ctx.http_call_request(request).await?;
+//!             if response.status_code() != Some(200) {
+//!                 ctx.send_http_response(403, vec![], None);
+//!                 return Ok(Action::Pause);
+//!             }
+//!             Ok(Action::Continue)
+//!         })
+//!     }
+//! }
+//! ```
+
+use crate::dispatcher;
+use crate::hostcalls;
+use crate::traits::*;
+use crate::types::*;
+use hashbrown::HashMap;
+use log::error;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::task::{Context as TaskContext, Poll, RawWaker, RawWakerVTable, Waker};
+use std::time::Duration;
+
+pub trait AsyncContext: Context {
+    fn handle(&self) -> ContextHandle {
+        ContextHandle {
+            context_id: dispatcher::active_context_id().unwrap_or(0),
+        }
+    }
+
+    fn http_call(
+        &self,
+        upstream: &str,
+        headers: Vec<(&str, &str)>,
+        body: Option<&[u8]>,
+        trailers: Vec<(&str, &str)>,
+        timeout: Duration,
+    ) -> HttpCallFuture {
+        match hostcalls::try_dispatch_http_call(upstream, headers, body, trailers, timeout) {
+            Ok(token_id) => HttpCallFuture(CallFuture::pending(|executor, slot| {
+                executor.http_calls.borrow_mut().insert(token_id, slot);
+            })),
+            Err(error) => HttpCallFuture(CallFuture::failed(error)),
+        }
+    }
+
+    fn http_call_request(&self, request: HttpCallRequest) -> HttpCallFuture {
+        match hostcalls::try_dispatch_http_call_request(&request) {
+            Ok(token_id) => HttpCallFuture(CallFuture::pending(|executor, slot| {
+                executor.http_calls.borrow_mut().insert(token_id, slot);
+            })),
+            Err(error) => HttpCallFuture(CallFuture::failed(error)),
+        }
+    }
+
+    fn grpc_call(
+        &self,
+        upstream_name: &str,
+        service_name: &str,
+        method_name: &str,
+        initial_metadata: Vec<(&str, &[u8])>,
+        message: Option<&[u8]>,
+        timeout: Duration,
+    ) -> GrpcCallFuture {
+        grpc_call(
+            upstream_name,
+            service_name,
+            method_name,
+            initial_metadata,
+            message,
+            timeout,
+        )
+    }
+
+    fn spawn_local<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        dispatcher::spawn(Box::pin(future));
+    }
+}
+
+impl<T: Context + ?Sized> AsyncContext for T {}
+
+pub(crate) fn grpc_call(
+    upstream_name: &str,
+    service_name: &str,
+    method_name: &str,
+    initial_metadata: Vec<(&str, &[u8])>,
+    message: Option<&[u8]>,
+    timeout: Duration,
+) -> GrpcCallFuture {
+    match hostcalls::try_dispatch_grpc_call(
+        upstream_name,
+        service_name,
+        method_name,
+        initial_metadata,
+        message,
+        timeout,
+    ) {
+        Ok(token_id) => GrpcCallFuture(CallFuture::pending(|executor, slot| {
+            executor.grpc_calls.borrow_mut().insert(token_id, slot);
+        })),
+        Err(error) => GrpcCallFuture(CallFuture::failed(error)),
+    }
+}
+
+pub trait AsyncHttpContext: HttpContext {
+    // Runs the future until it completes. If it completes right away, its
+    // action is returned as is. Otherwise, the request is paused and resumed
+    // once the future returns `Action::Continue`. Errors are logged and the
+    // request is reset.
+    fn spawn_request<F>(&self, future: F) -> Action
+    where
+        F: Future<Output = Result<Action, Error>> + 'static,
+    {
+        spawn_stream(StreamType::HttpRequest, future)
+    }
+
+    fn spawn_response<F>(&self, future: F) -> Action
+    where
+        F: Future<Output = Result<Action, Error>> + 'static,
+    {
+        spawn_stream(StreamType::HttpResponse, future)
+    }
+}
+
+impl<T: HttpContext + ?Sized> AsyncHttpContext for T {}
+
+// A handle to the context that can be moved into spawned tasks. Hostcalls made
+// through it apply to the context it was created from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ContextHandle {
+    context_id: u32,
+}
+
+impl ContextHandle {
+    pub fn context_id(&self) -> u32 {
+        self.context_id
+    }
+}
+
+impl Context for ContextHandle {}
+
+impl HttpContext for ContextHandle {}
+
+pub struct HttpCallFuture(CallFuture<HttpCallResponse>);
+
+impl Future for HttpCallFuture {
+    type Output = Result<HttpCallResponse, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+pub struct GrpcCallFuture(CallFuture<Option<Bytes>>);
+
+impl Future for GrpcCallFuture {
+    type Output = Result<Option<Bytes>, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+enum Outcome {
+    Polling,
+    Done(Result<Action, Error>),
+    Detached,
+}
+
+fn spawn_stream<F>(stream_type: StreamType, future: F) -> Action
+where
+    F: Future<Output = Result<Action, Error>> + 'static,
+{
+    let outcome = Rc::new(RefCell::new(Outcome::Polling));
+    let task_outcome = Rc::clone(&outcome);
+    dispatcher::spawn(Box::pin(async move {
+        let result = future.await;
+        let mut outcome = task_outcome.borrow_mut();
+        match *outcome {
+            Outcome::Detached => finish_stream(stream_type, result),
+            _ => *outcome = Outcome::Done(result),
+        }
+    }));
+    let result = match outcome.replace(Outcome::Detached) {
+        Outcome::Done(result) => result,
+        _ => return Action::Pause,
+    };
+    match result {
+        Ok(action) => action,
+        Err(error) => {
+            finish_stream(stream_type, Err(error));
+            Action::Pause
+        }
+    }
+}
+
+fn finish_stream(stream_type: StreamType, result: Result<Action, Error>) {
+    let result = match result {
+        Ok(Action::Continue) => match stream_type {
+            StreamType::HttpRequest => hostcalls::try_resume_http_request(),
+            StreamType::HttpResponse => hostcalls::try_resume_http_response(),
+            StreamType::Downstream => hostcalls::try_resume_downstream(),
+            StreamType::Upstream => hostcalls::try_resume_upstream(),
+        },
+        Ok(_) => Ok(()),
+        Err(error) => {
+            error!("{:?} task failed: {}", stream_type, error);
+            match stream_type {
+                StreamType::HttpRequest => hostcalls::try_reset_http_request(),
+                StreamType::HttpResponse => hostcalls::try_reset_http_response(),
+                StreamType::Downstream => hostcalls::try_close_downstream(),
+                StreamType::Upstream => hostcalls::try_close_upstream(),
+            }
+        }
+    };
+    if let Err(error) = result {
+        error!("failed to finish {:?}: {}", stream_type, error);
+    }
+}
+
+type Slot<T> = RefCell<SlotState<Result<T, Error>>>;
+
+struct SlotState<T> {
+    value: Option<T>,
+    waker: Option<Waker>,
+}
+
+struct CallFuture<T> {
+    failed: Option<Error>,
+    slot: Option<Rc<Slot<T>>>,
+}
+
+impl<T> CallFuture<T> {
+    fn pending(register: impl FnOnce(&Executor, Weak<Slot<T>>)) -> Self {
+        let slot = Rc::new(RefCell::new(SlotState {
+            value: None,
+            waker: None,
+        }));
+        dispatcher::with_executor(|executor| register(executor, Rc::downgrade(&slot)));
+        CallFuture {
+            failed: None,
+            slot: Some(slot),
+        }
+    }
+
+    fn failed(error: Error) -> Self {
+        CallFuture {
+            failed: Some(error),
+            slot: None,
+        }
+    }
+}
+
+impl<T> Future for CallFuture<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext) -> Poll<Self::Output> {
+        if let Some(error) = self.failed.take() {
+            return Poll::Ready(Err(error));
+        }
+        let slot = self.slot.as_ref().expect("polled after completion");
+        let mut state = slot.borrow_mut();
+        match state.value.take() {
+            Some(value) => {
+                drop(state);
+                self.slot = None;
+                Poll::Ready(value)
+            }
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+fn complete<T>(slot: Weak<Slot<T>>, value: impl FnOnce() -> Result<T, Error>) -> bool {
+    match slot.upgrade() {
+        Some(slot) => {
+            let value = value();
+            let waker = {
+                let mut state = slot.borrow_mut();
+                state.value = Some(value);
+                state.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+pub(crate) struct Executor {
+    next_task_id: Cell<u32>,
+    tasks: RefCell<HashMap<u32, (u32, Task)>>,
+    ready: RefCell<VecDeque<u32>>,
+    http_calls: RefCell<HashMap<u32, Weak<Slot<HttpCallResponse>>>>,
+    grpc_calls: RefCell<HashMap<u32, Weak<Slot<Option<Bytes>>>>>,
+}
+
+impl Executor {
+    pub(crate) fn new() -> Executor {
+        Executor {
+            next_task_id: Cell::new(1),
+            tasks: RefCell::new(HashMap::new()),
+            ready: RefCell::new(VecDeque::new()),
+            http_calls: RefCell::new(HashMap::new()),
+            grpc_calls: RefCell::new(HashMap::new()),
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    pub(crate) fn reset(&self) {
+        self.next_task_id.set(1);
+        self.tasks.borrow_mut().clear();
+        self.ready.borrow_mut().clear();
+        self.http_calls.borrow_mut().clear();
+        self.grpc_calls.borrow_mut().clear();
+    }
+
+    pub(crate) fn spawn(&self, context_id: u32, task: Task) {
+        let task_id = self.next_task_id.get();
+        self.next_task_id.set(task_id.wrapping_add(1));
+        self.tasks.borrow_mut().insert(task_id, (context_id, task));
+        self.wake(task_id);
+    }
+
+    pub(crate) fn wake(&self, task_id: u32) {
+        let mut ready = self.ready.borrow_mut();
+        if !ready.contains(&task_id) {
+            ready.push_back(task_id);
+        }
+    }
+
+    // Returns the next task ready to be polled, along with its context id.
+    pub(crate) fn next_ready(&self) -> Option<(u32, u32)> {
+        loop {
+            let task_id = self.ready.borrow_mut().pop_front()?;
+            if let Some((context_id, _)) = self.tasks.borrow().get(&task_id) {
+                return Some((task_id, *context_id));
+            }
+        }
+    }
+
+    pub(crate) fn poll(&self, task_id: u32) {
+        // The task is taken out of the map while it's polled, so that it can
+        // spawn new tasks.
+        let (context_id, mut task) = match self.tasks.borrow_mut().remove(&task_id) {
+            Some(task) => task,
+            None => return,
+        };
+        let waker = task_waker(task_id);
+        let mut cx = TaskContext::from_waker(&waker);
+        if task.as_mut().poll(&mut cx).is_pending() {
+            self.tasks.borrow_mut().insert(task_id, (context_id, task));
+        }
+    }
+
+    pub(crate) fn drop_tasks(&self, context_id: u32) {
+        let tasks: Vec<Task> = {
+            let mut tasks = self.tasks.borrow_mut();
+            let task_ids: Vec<u32> = tasks
+                .iter()
+                .filter(|(_, (id, _))| *id == context_id)
+                .map(|(task_id, _)| *task_id)
+                .collect();
+            task_ids
+                .iter()
+                .filter_map(|task_id| tasks.remove(task_id))
+                .map(|(_, task)| task)
+                .collect()
+        };
+        // Dropped outside of the borrow, since futures can hold callouts.
+        drop(tasks);
+    }
+
+    // Fails the future of an async callout. Returns true if the token
+    // belonged to an async callout.
+    pub(crate) fn cancel(&self, token_id: u32, reason: CancelReason) -> bool {
+        let error = Error::Cancelled { token_id, reason };
+        if let Some(slot) = self.http_calls.borrow_mut().remove(&token_id) {
+            complete(slot, || Err(error));
+            return true;
+        }
+        if let Some(slot) = self.grpc_calls.borrow_mut().remove(&token_id) {
+            complete(slot, || Err(error));
+            return true;
+        }
+        false
+    }
+
+    // Returns true if the token belonged to an async HTTP call.
+    pub(crate) fn on_http_call_response(
+        &self,
+        token_id: u32,
+        num_headers: usize,
+        body_size: usize,
+        num_trailers: usize,
+    ) -> bool {
+        let slot = match self.http_calls.borrow_mut().remove(&token_id) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        complete(slot, || {
+            hostcalls::try_get_http_call_response(num_headers, body_size, num_trailers)
+        });
+        true
+    }
+
+    // Returns true if the token belonged to an async gRPC call.
+    pub(crate) fn on_grpc_call_response(
+        &self,
+        token_id: u32,
+        status_code: u32,
+        response_size: usize,
+    ) -> bool {
+        let slot = match self.grpc_calls.borrow_mut().remove(&token_id) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        complete(slot, || {
+            if status_code != GrpcStatusCode::Ok as u32 {
+                let (_, message) = hostcalls::try_get_grpc_status()?;
+                Err(Error::Grpc {
+                    status_code,
+                    message,
+                })
+            } else if response_size == 0 {
+                Ok(None)
+            } else {
+                hostcalls::try_get_buffer(BufferType::GrpcReceiveBuffer, 0, response_size)
+            }
+        });
+        true
+    }
+}
+
+fn task_waker(task_id: u32) -> Waker {
+    unsafe { Waker::from_raw(raw_waker(task_id)) }
+}
+
+fn raw_waker(task_id: u32) -> RawWaker {
+    RawWaker::new(task_id as usize as *const (), &VTABLE)
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(
+    |data| raw_waker(data as usize as u32),
+    |data| dispatcher::wake(data as usize as u32),
+    |data| dispatcher::wake(data as usize as u32),
+    |_| {},
+);
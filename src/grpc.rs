@@ -0,0 +1,666 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! gRPC calls with typed messages.
+//!
+//! `GrpcClient::unary` (with the `prost` feature) encodes the request and
+//! returns a `UnaryCall`, which decodes the response once the host delivers
+//! it to `Context::on_grpc_call_response`. Calls that complete with a status
+//! other than `GrpcStatusCode::Ok` fail with `Error::Grpc`, carrying the
+//! message returned by the upstream.
+//!
+//! [`GrpcClient::open_stream`] opens a [`GrpcStream`], whose events are
+//! delivered to a [`GrpcStreamHandler`] instead of the `on_grpc_stream_*`
+//! callbacks of the context that opened it:
+//!
+//! ```no_run
+//! use proxy_wasm::codec::Text;
+//! use proxy_wasm::grpc::*;
+//! use proxy_wasm::types::*;
+//!
+//! struct Updates;
+//!
+//! impl GrpcStreamHandler for Updates {
+//!     type Message = String;
+//!
+//!     fn on_message(&mut self, update: String) {
+//!         // Apply update.
+//!     }
+//!
+//!     fn on_close(&mut self, status_code: GrpcStatusCode, message: Option<String>) {
+//!         // Reconnect.
+//!     }
+//! }
+//!
+//! let stream = GrpcClient::new("updates")
+//!     .open_stream("updates.Updates", "Watch", vec![], Text, Updates)
+//!     .unwrap();
+//! stream.send(&"all".to_string()).unwrap();
+//! stream.close_send().unwrap();
+//! ```
+
+use crate::codec::Codec;
+#[cfg(feature = "prost")]
+use crate::codec::Protobuf;
+use crate::dispatcher;
+use crate::hostcalls;
+use crate::types::*;
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+#[cfg(feature = "prost")]
+use std::time::Duration;
+
+/// How messages received on a gRPC stream are delimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// Each `on_grpc_receive` carries exactly one message, as delivered by
+    /// Envoy.
+    #[default]
+    Message,
+    /// Received data is a sequence of gRPC length-prefixed messages, which
+    /// might be split across or coalesced within `on_grpc_receive` calls.
+    LengthPrefixed,
+}
+
+// Same as the default limit of gRPC clients.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Client for the gRPC services behind a single upstream.
+#[derive(Clone, Debug)]
+pub struct GrpcClient {
+    upstream_name: String,
+    framing: Framing,
+    max_message_size: usize,
+}
+
+impl GrpcClient {
+    pub fn new(upstream_name: &str) -> GrpcClient {
+        GrpcClient {
+            upstream_name: upstream_name.to_string(),
+            framing: Framing::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Sets how messages received on streams opened by this client are
+    /// delimited.
+    pub fn with_framing(mut self, framing: Framing) -> GrpcClient {
+        self.framing = framing;
+        self
+    }
+
+    /// Sets the size of the largest message that can be received on streams
+    /// opened by this client, 4 MiB by default. Streams receiving larger
+    /// messages are cancelled, and closed with
+    /// `GrpcStatusCode::ResourceExhausted`.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> GrpcClient {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    pub fn upstream_name(&self) -> &str {
+        &self.upstream_name
+    }
+
+    /// Dispatches a unary call with the encoded `request`.
+    #[cfg(feature = "prost")]
+    pub fn unary<Req, Resp>(
+        &self,
+        service_name: &str,
+        method_name: &str,
+        request: &Req,
+        initial_metadata: Vec<(&str, &[u8])>,
+        timeout: Duration,
+    ) -> Result<UnaryCall<Resp>, Error>
+    where
+        Req: prost::Message + Default,
+        Resp: prost::Message + Default,
+    {
+        let message = Protobuf.encode(request)?;
+        let token_id = hostcalls::try_dispatch_grpc_call(
+            &self.upstream_name,
+            service_name,
+            method_name,
+            initial_metadata,
+            Some(&message),
+            timeout,
+        )?;
+        Ok(UnaryCall {
+            token_id,
+            response_type: PhantomData,
+        })
+    }
+
+    /// Same as [`unary`](Self::unary), but resolves to the decoded response
+    /// once the call completes. The call is dispatched immediately.
+    #[cfg(all(feature = "prost", feature = "async"))]
+    pub fn unary_async<Req, Resp>(
+        &self,
+        service_name: &str,
+        method_name: &str,
+        request: &Req,
+        initial_metadata: Vec<(&str, &[u8])>,
+        timeout: Duration,
+    ) -> impl std::future::Future<Output = Result<Resp, Error>>
+    where
+        Req: prost::Message + Default,
+        Resp: prost::Message + Default,
+    {
+        let call = Protobuf.encode(request).map(|message| {
+            crate::executor::grpc_call(
+                &self.upstream_name,
+                service_name,
+                method_name,
+                initial_metadata,
+                Some(&message),
+                timeout,
+            )
+        });
+        async move {
+            let message = call?.await?;
+            Protobuf.decode(message.as_deref().unwrap_or_default())
+        }
+    }
+
+    /// Opens a stream, whose messages are encoded and decoded with `codec`.
+    /// Events received on the stream are delivered to `handler`, within the
+    /// context that opened it.
+    pub fn open_stream<Req, C, H>(
+        &self,
+        service_name: &str,
+        method_name: &str,
+        initial_metadata: Vec<(&str, &[u8])>,
+        codec: C,
+        handler: H,
+    ) -> Result<GrpcStream<Req, C>, Error>
+    where
+        C: Codec<Req> + Codec<H::Message> + Clone + 'static,
+        H: GrpcStreamHandler + 'static,
+    {
+        let token_id = hostcalls::try_open_grpc_stream(
+            &self.upstream_name,
+            service_name,
+            method_name,
+            initial_metadata,
+        )?;
+        let receiver = StreamReceiver {
+            token_id,
+            handler,
+            codec: codec.clone(),
+            framing: self.framing,
+            max_message_size: self.max_message_size,
+            buffer: Vec::new(),
+        };
+        dispatcher::register_grpc_stream_handler(token_id, Box::new(receiver));
+        Ok(GrpcStream {
+            token_id,
+            codec,
+            message_type: PhantomData,
+        })
+    }
+}
+
+/// Pending unary call, returned by [`GrpcClient::unary`].
+///
+/// ```no_run
+/// use proxy_wasm::grpc::{GrpcClient, UnaryCall};
+/// use proxy_wasm::traits::*;
+/// use proxy_wasm::types::*;
+/// use std::time::Duration;
+///
+/// #[derive(Clone, PartialEq, prost::Message)]
+/// struct CheckRequest {
+///     #[prost(string, tag = "1")]
+///     path: String,
+/// }
+///
+/// #[derive(Clone, PartialEq, prost::Message)]
+/// struct CheckResponse {
+///     #[prost(bool, tag = "1")]
+///     allowed: bool,
+/// }
+///
+/// struct Filter {
+///     check: Option<UnaryCall<CheckResponse>>,
+/// }
+///
+/// impl HttpContext for Filter {
+///     fn on_http_request_headers(&mut self, _: usize, _: bool) -> Action {
+///         let request = CheckRequest {
+///             path: self.get_http_request_header(":path").unwrap_or_default(),
+///         };
+///         let client = GrpcClient::new("authz");
+///         let call = client
+///             .unary("authz.Authz", "Check", &request, vec![], Duration::from_secs(1))
+///             .unwrap();
+///         self.check = Some(call);
+///         Action::Pause
+///     }
+/// }
+///
+/// impl Context for Filter {
+///     fn on_grpc_call_response(&mut self, _: u32, status_code: u32, response_size: usize) {
+///         let call = self.check.take().unwrap();
+///         match call.response(status_code, response_size) {
+///             Ok(response) if response.allowed => self.resume_http_request(),
+///             _ => self.send_http_response(403, vec![], None),
+///         }
+///     }
+/// }
+/// ```
+#[cfg(feature = "prost")]
+pub struct UnaryCall<Resp> {
+    token_id: u32,
+    response_type: PhantomData<fn() -> Resp>,
+}
+
+#[cfg(feature = "prost")]
+impl<Resp: prost::Message + Default> UnaryCall<Resp> {
+    /// Returns the token passed to `Context::on_grpc_call_response`.
+    pub fn token_id(&self) -> u32 {
+        self.token_id
+    }
+
+    /// Decodes the response, using the arguments passed to
+    /// `Context::on_grpc_call_response`.
+    pub fn response(&self, status_code: u32, response_size: usize) -> Result<Resp, Error> {
+        if status_code != GrpcStatusCode::Ok as u32 {
+            let (_, message) = hostcalls::try_get_grpc_status()?;
+            return Err(Error::Grpc {
+                status_code,
+                message,
+            });
+        }
+        let message = match response_size {
+            0 => None,
+            _ => hostcalls::try_get_buffer(BufferType::GrpcReceiveBuffer, 0, response_size)?,
+        };
+        Protobuf.decode(message.as_deref().unwrap_or_default())
+    }
+}
+
+/// Sending half of a stream, returned by [`GrpcClient::open_stream`].
+pub struct GrpcStream<T, C: Codec<T>> {
+    token_id: u32,
+    codec: C,
+    message_type: PhantomData<fn(&T)>,
+}
+
+impl<T, C: Codec<T> + Clone> Clone for GrpcStream<T, C> {
+    fn clone(&self) -> Self {
+        GrpcStream {
+            token_id: self.token_id,
+            codec: self.codec.clone(),
+            message_type: PhantomData,
+        }
+    }
+}
+
+impl<T, C: Codec<T>> GrpcStream<T, C> {
+    pub fn token_id(&self) -> u32 {
+        self.token_id
+    }
+
+    pub fn send(&self, message: &T) -> Result<(), Error> {
+        let bytes = self.codec.encode(message)?;
+        hostcalls::try_send_grpc_stream_message(self.token_id, Some(&bytes), false)
+    }
+
+    /// Half-closes the stream. Messages can still be received until the
+    /// upstream closes it.
+    pub fn close_send(&self) -> Result<(), Error> {
+        hostcalls::try_close_grpc_stream(self.token_id)
+    }
+
+    /// Cancels the stream. The handler isn't called afterwards.
+    pub fn cancel(&self) -> Result<(), Error> {
+        hostcalls::try_cancel_grpc_stream(self.token_id)?;
+        dispatcher::forget_callout(self.token_id);
+        Ok(())
+    }
+}
+
+/// Receives the events of a stream opened with [`GrpcClient::open_stream`].
+pub trait GrpcStreamHandler {
+    type Message;
+
+    fn on_initial_metadata(&mut self, _metadata: Vec<(String, Bytes)>) {}
+
+    fn on_message(&mut self, message: Self::Message);
+
+    fn on_trailing_metadata(&mut self, _metadata: Vec<(String, Bytes)>) {}
+
+    fn on_close(&mut self, status_code: GrpcStatusCode, message: Option<String>);
+}
+
+// Type-erased stream handler, registered with the dispatcher.
+pub(crate) trait StreamCallbacks {
+    fn on_initial_metadata(&mut self);
+    fn on_message(&mut self, message_size: usize);
+    fn on_trailing_metadata(&mut self);
+    fn on_close(&mut self, status_code: u32);
+}
+
+struct StreamReceiver<H, C> {
+    token_id: u32,
+    handler: H,
+    codec: C,
+    framing: Framing,
+    max_message_size: usize,
+    buffer: Bytes,
+}
+
+impl<H, C> StreamReceiver<H, C>
+where
+    H: GrpcStreamHandler,
+    C: Codec<H::Message>,
+{
+    fn deliver(&mut self, bytes: &[u8]) {
+        match self.codec.decode(bytes) {
+            Ok(message) => self.handler.on_message(message),
+            Err(error) => self.warn(&format!("dropping message: {}", error)),
+        }
+    }
+
+    // Returns the length of the next message, once its prefix is buffered.
+    fn next_length(&self) -> Option<usize> {
+        let mut length = [0; 4];
+        length.copy_from_slice(self.buffer.get(1..5)?);
+        Some(usize::try_from(u32::from_be_bytes(length)).unwrap_or(usize::MAX))
+    }
+
+    // Splits the next complete message off the buffer.
+    fn next_frame(&mut self) -> Option<Result<Bytes, Error>> {
+        let end = self.next_length()?.checked_add(5)?;
+        if self.buffer.len() < end {
+            return None;
+        }
+        let compressed = self.buffer[0] != 0;
+        let frame = self.buffer[5..end].to_vec();
+        self.buffer.drain(..end);
+        if compressed {
+            return Some(Err(Error::Codec {
+                reason: "compressed messages are not supported".to_string(),
+            }));
+        }
+        Some(Ok(frame))
+    }
+
+    // Cancels the stream instead of buffering a message over the limit, and
+    // closes it the way gRPC clients do.
+    fn cancel_oversized(&mut self, message_size: usize) {
+        let message = format!(
+            "received message larger than max ({} vs. {})",
+            message_size, self.max_message_size
+        );
+        self.warn(&message);
+        self.buffer.clear();
+        if let Err(error) = hostcalls::try_cancel_grpc_stream(self.token_id) {
+            self.warn(&format!("failed to cancel: {}", error));
+        }
+        dispatcher::forget_callout(self.token_id);
+        self.handler
+            .on_close(GrpcStatusCode::ResourceExhausted, Some(message));
+    }
+
+    fn warn(&self, message: &str) {
+        let message = format!("gRPC stream {}: {}", self.token_id, message);
+        let _ = hostcalls::try_log(LogLevel::Warn, &message);
+    }
+}
+
+impl<H, C> StreamCallbacks for StreamReceiver<H, C>
+where
+    H: GrpcStreamHandler,
+    C: Codec<H::Message>,
+{
+    fn on_initial_metadata(&mut self) {
+        match hostcalls::try_get_map_bytes(MapType::GrpcReceiveInitialMetadata) {
+            Ok(metadata) => self.handler.on_initial_metadata(metadata),
+            Err(error) => self.warn(&format!("dropping initial metadata: {}", error)),
+        }
+    }
+
+    fn on_message(&mut self, message_size: usize) {
+        if self.framing == Framing::Message && message_size > self.max_message_size {
+            return self.cancel_oversized(message_size);
+        }
+        let bytes = match hostcalls::try_get_buffer(BufferType::GrpcReceiveBuffer, 0, message_size)
+        {
+            Ok(bytes) => bytes.unwrap_or_default(),
+            Err(error) => return self.warn(&format!("dropping message: {}", error)),
+        };
+        match self.framing {
+            Framing::Message => self.deliver(&bytes),
+            Framing::LengthPrefixed => {
+                self.buffer.extend_from_slice(&bytes);
+                loop {
+                    match self.next_length() {
+                        Some(length) if length > self.max_message_size => {
+                            return self.cancel_oversized(length)
+                        }
+                        _ => {}
+                    }
+                    match self.next_frame() {
+                        Some(Ok(frame)) => self.deliver(&frame),
+                        Some(Err(error)) => self.warn(&format!("dropping message: {}", error)),
+                        None => break,
+                    }
+                }
+            }
+        }
+    }
+
+    fn on_trailing_metadata(&mut self) {
+        match hostcalls::try_get_map_bytes(MapType::GrpcReceiveTrailingMetadata) {
+            Ok(metadata) => self.handler.on_trailing_metadata(metadata),
+            Err(error) => self.warn(&format!("dropping trailing metadata: {}", error)),
+        }
+    }
+
+    fn on_close(&mut self, status_code: u32) {
+        if !self.buffer.is_empty() {
+            let message = format!(
+                "closed with {} bytes of a partial message",
+                self.buffer.len()
+            );
+            self.warn(&message);
+        }
+        let message = match hostcalls::try_get_grpc_status() {
+            Ok((_, message)) => message,
+            Err(_) => None,
+        };
+        self.handler
+            .on_close(GrpcStatusCode::from_code(status_code), message);
+    }
+}
+
+/// Name of the trailer carrying a `google.rpc.Status` message with the
+/// details of an error.
+pub const STATUS_DETAILS_HEADER: &str = "grpc-status-details-bin";
+
+/// Detail of a gRPC error, encoded as a `google.protobuf.Any` message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ErrorDetail {
+    pub type_url: String,
+    pub value: Bytes,
+}
+
+impl ErrorDetail {
+    pub fn new(type_url: &str, value: Bytes) -> ErrorDetail {
+        ErrorDetail {
+            type_url: type_url.to_string(),
+            value,
+        }
+    }
+
+    /// Creates a detail from a protobuf message, e.g. a
+    /// `type.googleapis.com/google.rpc.BadRequest`.
+    #[cfg(feature = "prost")]
+    pub fn from_message<M: prost::Message>(type_url: &str, message: &M) -> ErrorDetail {
+        ErrorDetail::new(type_url, message.encode_to_vec())
+    }
+}
+
+/// Encodes a `google.rpc.Status` message as the value of the
+/// [`STATUS_DETAILS_HEADER`] trailer.
+pub fn encode_status_details(
+    status_code: GrpcStatusCode,
+    message: Option<&str>,
+    details: &[ErrorDetail],
+) -> String {
+    let mut status = Vec::new();
+    if status_code != GrpcStatusCode::Ok {
+        encode_varint(&mut status, 1, status_code as u64);
+    }
+    if let Some(message) = message.filter(|message| !message.is_empty()) {
+        encode_bytes(&mut status, 2, message.as_bytes());
+    }
+    for detail in details {
+        let mut any = Vec::new();
+        encode_bytes(&mut any, 1, detail.type_url.as_bytes());
+        if !detail.value.is_empty() {
+            encode_bytes(&mut any, 2, &detail.value);
+        }
+        encode_bytes(&mut status, 3, &any);
+    }
+    base64_encode(&status)
+}
+
+fn encode_varint(buffer: &mut Bytes, field: u32, value: u64) {
+    write_varint(buffer, u64::from(field << 3));
+    write_varint(buffer, value);
+}
+
+fn encode_bytes(buffer: &mut Bytes, field: u32, value: &[u8]) {
+    write_varint(buffer, u64::from(field << 3 | 2));
+    write_varint(buffer, value.len() as u64);
+    buffer.extend_from_slice(value);
+}
+
+fn write_varint(buffer: &mut Bytes, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+// Binary metadata is sent unpadded, as recommended by the gRPC spec.
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity((bytes.len() * 4 + 2) / 3);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | u32::from(*byte) << (16 - 8 * i)
+        });
+        for i in 0..=chunk.len() {
+            encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::codec::Text;
+    use crate::testing::MockHost;
+    use crate::traits::*;
+    use std::cell::RefCell;
+
+    thread_local! {
+        static EVENTS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn events() -> Vec<String> {
+        EVENTS.with(|events| events.borrow_mut().drain(..).collect())
+    }
+
+    struct Handler;
+
+    impl GrpcStreamHandler for Handler {
+        type Message = String;
+
+        fn on_message(&mut self, message: String) {
+            EVENTS.with(|events| events.borrow_mut().push(message));
+        }
+
+        fn on_close(&mut self, status_code: GrpcStatusCode, _: Option<String>) {
+            let event = format!("close {:?}", status_code);
+            EVENTS.with(|events| events.borrow_mut().push(event));
+        }
+    }
+
+    // Opens a length-prefixed stream, with messages of up to 8 bytes.
+    struct Root;
+
+    impl Context for Root {}
+
+    impl RootContext for Root {
+        fn on_vm_start(&mut self, _: usize) -> bool {
+            GrpcClient::new("upstream")
+                .with_framing(Framing::LengthPrefixed)
+                .with_max_message_size(8)
+                .open_stream::<String, _, _>("svc.Svc", "Watch", vec![], Text, Handler)
+                .unwrap();
+            true
+        }
+    }
+
+    fn frame(message: &[u8]) -> Bytes {
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(message);
+        frame
+    }
+
+    fn open_stream() -> (MockHost, u32) {
+        let host = MockHost::new();
+        crate::set_root_context(|_| Box::new(Root));
+        let root_id = host.create_root_context();
+        host.on_vm_start(root_id, None);
+        let token_id = host.grpc_streams()[0].token_id;
+        (host, token_id)
+    }
+
+    #[test]
+    fn length_prefixed_messages() {
+        let (host, token_id) = open_stream();
+        let mut data = frame(b"one");
+        data.extend(frame(b"two"));
+        data.extend(frame(b"three"));
+        host.on_grpc_receive(token_id, &data[..2]);
+        host.on_grpc_receive(token_id, &data[2..6]);
+        assert!(events().is_empty());
+        host.on_grpc_receive(token_id, &data[6..11]);
+        host.on_grpc_receive(token_id, &data[11..]);
+        assert_eq!(events(), ["one", "two", "three"]);
+    }
+
+    #[test]
+    fn oversized_message() {
+        let (host, token_id) = open_stream();
+        host.on_grpc_receive(token_id, &frame(b"one"));
+        host.on_grpc_receive(token_id, &frame(b"too large"));
+        host.on_grpc_receive(token_id, &frame(b"late"));
+        assert_eq!(events(), ["one", "close ResourceExhausted"]);
+        assert!(host.grpc_streams()[0].cancelled);
+
+        let (host, token_id) = open_stream();
+        host.on_grpc_receive(token_id, &[0, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(events(), ["close ResourceExhausted"]);
+        assert!(host.grpc_streams()[0].cancelled);
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Header case hints.
+//!
+//! Hosts like Envoy store header names in lowercase, and the Proxy-Wasm ABI
+//! has no way to set the case in which a name is sent. Instead, the original
+//! case of names set through this module is recorded in a companion header,
+//! [`HEADER_CASE_HEADER`], as a comma-separated list, e.g.
+//! `x-proxy-wasm-header-case: Access-Control-Allow-Origin`.
+//!
+//! Something downstream of the plugin has to apply the hints and remove the
+//! companion header, e.g. a proxy in front of legacy clients, using
+//! [`restore_case`]. Envoy's `preserve_case` header formatter only applies to
+//! names received from a peer, and ignores the hints.
+
+use crate::hostcalls;
+use crate::types::*;
+
+/// Name of the companion header listing the original case of header names.
+pub const HEADER_CASE_HEADER: &str = "x-proxy-wasm-header-case";
+
+/// Sets a header, and records `name` as its original case. Removing the
+/// header with `None` removes its hint as well.
+pub fn set_header_with_case(
+    map_type: MapType,
+    name: &str,
+    value: Option<&str>,
+) -> Result<(), Error> {
+    hostcalls::try_set_map_value(map_type, name, value)?;
+    update_hints(map_type, name, value.is_some())
+}
+
+/// Adds a header value, and records `name` as its original case.
+pub fn add_header_with_case(map_type: MapType, name: &str, value: &str) -> Result<(), Error> {
+    hostcalls::try_add_map_value(map_type, name, value)?;
+    update_hints(map_type, name, true)
+}
+
+/// Returns the original case recorded for `name`, if any.
+pub fn original_case(map_type: MapType, name: &str) -> Result<Option<String>, Error> {
+    let hints = hostcalls::try_get_map_value(map_type, HEADER_CASE_HEADER)?;
+    Ok(hints.and_then(|hints| find_hint(&hints, name).map(str::to_string)))
+}
+
+/// Renames the headers in `headers` to the case recorded in the companion
+/// header, and removes the companion header.
+pub fn restore_case<V: AsRef<[u8]>>(headers: Vec<(String, V)>) -> Vec<(String, V)> {
+    let hints: Vec<String> = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case(HEADER_CASE_HEADER))
+        .filter_map(|(_, value)| std::str::from_utf8(value.as_ref()).ok())
+        .flat_map(|hints| hints.split(',').map(|hint| hint.trim().to_string()))
+        .collect();
+    headers
+        .into_iter()
+        .filter(|(name, _)| !name.eq_ignore_ascii_case(HEADER_CASE_HEADER))
+        .map(
+            |(name, value)| match hints.iter().find(|hint| hint.eq_ignore_ascii_case(&name)) {
+                Some(hint) => (hint.clone(), value),
+                None => (name, value),
+            },
+        )
+        .collect()
+}
+
+fn update_hints(map_type: MapType, name: &str, present: bool) -> Result<(), Error> {
+    let hints = hostcalls::try_get_map_value(map_type, HEADER_CASE_HEADER)?.unwrap_or_default();
+    let mut hints: Vec<&str> = hints
+        .split(',')
+        .map(str::trim)
+        .filter(|hint| !hint.is_empty() && !hint.eq_ignore_ascii_case(name))
+        .collect();
+    // Lowercase names don't need a hint.
+    if present && name.bytes().any(|b| b.is_ascii_uppercase()) {
+        hints.push(name);
+    }
+    let hints = hints.join(",");
+    let value = Some(hints.as_str()).filter(|hints| !hints.is_empty());
+    hostcalls::try_set_map_value(map_type, HEADER_CASE_HEADER, value)
+}
+
+fn find_hint<'a>(hints: &'a str, name: &str) -> Option<&'a str> {
+    hints
+        .split(',')
+        .map(str::trim)
+        .find(|hint| hint.eq_ignore_ascii_case(name))
+}
@@ -22,10 +22,18 @@ extern "C" {
 }
 
 pub fn log(level: LogLevel, message: &str) -> Result<(), Status> {
+    expect_status(try_log(level, message), &[])
+}
+
+pub fn try_log(level: LogLevel, message: &str) -> Result<(), Error> {
     unsafe {
         match proxy_log(level, message.as_ptr(), message.len()) {
             Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_log",
+                status,
+                format!("level: {:?}", level),
+            )),
         }
     }
 }
@@ -35,11 +43,19 @@ extern "C" {
 }
 
 pub fn get_log_level() -> Result<LogLevel, Status> {
+    expect_status(try_get_log_level(), &[])
+}
+
+pub fn try_get_log_level() -> Result<LogLevel, Error> {
     let mut return_level: LogLevel = LogLevel::Trace;
     unsafe {
         match proxy_get_log_level(&mut return_level) {
             Status::Ok => Ok(return_level),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_get_log_level",
+                status,
+                String::new(),
+            )),
         }
     }
 }
@@ -49,11 +65,19 @@ extern "C" {
 }
 
 pub fn get_current_time() -> Result<SystemTime, Status> {
+    expect_status(try_get_current_time(), &[])
+}
+
+pub fn try_get_current_time() -> Result<SystemTime, Error> {
     let mut return_time: u64 = 0;
     unsafe {
         match proxy_get_current_time_nanoseconds(&mut return_time) {
             Status::Ok => Ok(UNIX_EPOCH + Duration::from_nanos(return_time)),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_get_current_time_nanoseconds",
+                status,
+                String::new(),
+            )),
         }
     }
 }
@@ -63,10 +87,24 @@ extern "C" {
 }
 
 pub fn set_tick_period(period: Duration) -> Result<(), Status> {
+    expect_status(try_set_tick_period(period), &[])
+}
+
+pub fn try_set_tick_period(period: Duration) -> Result<(), Error> {
+    dispatcher::set_tick_period(period)
+}
+
+// Sets the tick period on the host. The period requested by the plugin is
+// multiplexed with the periods of its timers by the dispatcher.
+pub(crate) fn try_set_host_tick_period(period: Duration) -> Result<(), Error> {
     unsafe {
         match proxy_set_tick_period_milliseconds(period.as_millis() as u32) {
             Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_set_tick_period_milliseconds",
+                status,
+                format!("period: {:?}", period),
+            )),
         }
     }
 }
@@ -86,6 +124,14 @@ pub fn get_buffer(
     start: usize,
     max_size: usize,
 ) -> Result<Option<Bytes>, Status> {
+    expect_status(try_get_buffer(buffer_type, start, max_size), &[])
+}
+
+pub fn try_get_buffer(
+    buffer_type: BufferType,
+    start: usize,
+    max_size: usize,
+) -> Result<Option<Bytes>, Error> {
     let mut return_data: *mut u8 = null_mut();
     let mut return_size: usize = 0;
     unsafe {
@@ -108,7 +154,14 @@ pub fn get_buffer(
                 }
             }
             Status::NotFound => Ok(None),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_get_buffer_bytes",
+                status,
+                format!(
+                    "buffer_type: {:?}, start: {:?}, max_size: {:?}",
+                    buffer_type, start, max_size
+                ),
+            )),
         }
     }
 }
@@ -129,10 +182,80 @@ pub fn set_buffer(
     size: usize,
     value: &[u8],
 ) -> Result<(), Status> {
+    expect_status(try_set_buffer(buffer_type, start, size, value), &[])
+}
+
+pub fn try_set_buffer(
+    buffer_type: BufferType,
+    start: usize,
+    size: usize,
+    value: &[u8],
+) -> Result<(), Error> {
     unsafe {
         match proxy_set_buffer_bytes(buffer_type, start, size, value.as_ptr(), value.len()) {
             Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_set_buffer_bytes",
+                status,
+                format!(
+                    "buffer_type: {:?}, start: {:?}, size: {:?}",
+                    buffer_type, start, size
+                ),
+            )),
+        }
+    }
+}
+
+extern "C" {
+    fn proxy_get_buffer_status(
+        buffer_type: BufferType,
+        return_buffer_size: *mut usize,
+        return_flags: *mut u32,
+    ) -> Status;
+}
+
+pub fn get_buffer_status(buffer_type: BufferType) -> Result<Option<(usize, BufferFlags)>, Status> {
+    expect_status(try_get_buffer_status(buffer_type), &[])
+}
+
+pub fn try_get_buffer_status(
+    buffer_type: BufferType,
+) -> Result<Option<(usize, BufferFlags)>, Error> {
+    let mut return_size: usize = 0;
+    let mut return_flags: u32 = 0;
+    unsafe {
+        match proxy_get_buffer_status(buffer_type, &mut return_size, &mut return_flags) {
+            Status::Ok => Ok(Some((return_size, BufferFlags::from_bits(return_flags)))),
+            Status::NotFound => Ok(None),
+            status => Err(Error::hostcall(
+                "proxy_get_buffer_status",
+                status,
+                format!("buffer_type: {:?}", buffer_type),
+            )),
+        }
+    }
+}
+
+extern "C" {
+    fn proxy_get_header_map_size(map_type: MapType, return_map_size: *mut usize) -> Status;
+}
+
+/// Returns the size of the map in bytes, as reported by the host, i.e. the
+/// total size of names and values in Envoy.
+pub fn get_map_size(map_type: MapType) -> Result<usize, Status> {
+    expect_status(try_get_map_size(map_type), &[])
+}
+
+pub fn try_get_map_size(map_type: MapType) -> Result<usize, Error> {
+    let mut return_size: usize = 0;
+    unsafe {
+        match proxy_get_header_map_size(map_type, &mut return_size) {
+            Status::Ok => Ok(return_size),
+            status => Err(Error::hostcall(
+                "proxy_get_header_map_size",
+                status,
+                format!("map_type: {:?}", map_type),
+            )),
         }
     }
 }
@@ -146,37 +269,82 @@ extern "C" {
 }
 
 pub fn get_map(map_type: MapType) -> Result<Vec<(String, String)>, Status> {
+    expect_status(try_get_map(map_type), &[])
+}
+
+pub fn try_get_map(map_type: MapType) -> Result<Vec<(String, String)>, Error> {
     unsafe {
         let mut return_data: *mut u8 = null_mut();
         let mut return_size: usize = 0;
         match proxy_get_header_map_pairs(map_type, &mut return_data, &mut return_size) {
             Status::Ok => {
                 if !return_data.is_null() {
-                    let serialized_map = std::slice::from_raw_parts(return_data, return_size);
-                    Ok(utils::deserialize_map(serialized_map))
+                    let serialized_map = Vec::from_raw_parts(return_data, return_size, return_size);
+                    utils::deserialize_map(&serialized_map, dispatcher::utf8_policy())
                 } else {
                     Ok(Vec::new())
                 }
             }
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_get_header_map_pairs",
+                status,
+                format!("map_type: {:?}", map_type),
+            )),
         }
     }
 }
 
 pub fn get_map_bytes(map_type: MapType) -> Result<Vec<(String, Bytes)>, Status> {
+    expect_status(try_get_map_bytes(map_type), &[])
+}
+
+pub fn try_get_map_bytes(map_type: MapType) -> Result<Vec<(String, Bytes)>, Error> {
     unsafe {
         let mut return_data: *mut u8 = null_mut();
         let mut return_size: usize = 0;
         match proxy_get_header_map_pairs(map_type, &mut return_data, &mut return_size) {
             Status::Ok => {
                 if !return_data.is_null() {
-                    let serialized_map = std::slice::from_raw_parts(return_data, return_size);
-                    Ok(utils::deserialize_map_bytes(serialized_map))
+                    let serialized_map = Vec::from_raw_parts(return_data, return_size, return_size);
+                    utils::deserialize_map_bytes(&serialized_map, dispatcher::utf8_policy())
                 } else {
                     Ok(Vec::new())
                 }
             }
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_get_header_map_pairs",
+                status,
+                format!("map_type: {:?}", map_type),
+            )),
+        }
+    }
+}
+
+pub fn get_header_map(map_type: MapType) -> Result<HeaderMap, Status> {
+    expect_status(try_get_header_map(map_type), &[])
+}
+
+pub fn try_get_header_map(map_type: MapType) -> Result<HeaderMap, Error> {
+    unsafe {
+        let mut return_data: *mut u8 = null_mut();
+        let mut return_size: usize = 0;
+        match proxy_get_header_map_pairs(map_type, &mut return_data, &mut return_size) {
+            Status::Ok => {
+                if !return_data.is_null() {
+                    HeaderMap::from_bytes(Vec::from_raw_parts(
+                        return_data,
+                        return_size,
+                        return_size,
+                    ))
+                } else {
+                    Ok(HeaderMap::default())
+                }
+            }
+            status => Err(Error::hostcall(
+                "proxy_get_header_map_pairs",
+                status,
+                format!("map_type: {:?}", map_type),
+            )),
         }
     }
 }
@@ -190,21 +358,37 @@ extern "C" {
 }
 
 pub fn set_map(map_type: MapType, map: Vec<(&str, &str)>) -> Result<(), Status> {
+    expect_status(try_set_map(map_type, map), &[])
+}
+
+pub fn try_set_map(map_type: MapType, map: Vec<(&str, &str)>) -> Result<(), Error> {
     let serialized_map = utils::serialize_map(map);
     unsafe {
         match proxy_set_header_map_pairs(map_type, serialized_map.as_ptr(), serialized_map.len()) {
             Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_set_header_map_pairs",
+                status,
+                format!("map_type: {:?}", map_type),
+            )),
         }
     }
 }
 
 pub fn set_map_bytes(map_type: MapType, map: Vec<(&str, &[u8])>) -> Result<(), Status> {
+    expect_status(try_set_map_bytes(map_type, map), &[])
+}
+
+pub fn try_set_map_bytes(map_type: MapType, map: Vec<(&str, &[u8])>) -> Result<(), Error> {
     let serialized_map = utils::serialize_map_bytes(map);
     unsafe {
         match proxy_set_header_map_pairs(map_type, serialized_map.as_ptr(), serialized_map.len()) {
             Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_set_header_map_pairs",
+                status,
+                format!("map_type: {:?}", map_type),
+            )),
         }
     }
 }
@@ -220,6 +404,10 @@ extern "C" {
 }
 
 pub fn get_map_value(map_type: MapType, key: &str) -> Result<Option<String>, Status> {
+    expect_status(try_get_map_value(map_type, key), &[])
+}
+
+pub fn try_get_map_value(map_type: MapType, key: &str) -> Result<Option<String>, Error> {
     let mut return_data: *mut u8 = null_mut();
     let mut return_size: usize = 0;
     unsafe {
@@ -232,25 +420,30 @@ pub fn get_map_value(map_type: MapType, key: &str) -> Result<Option<String>, Sta
         ) {
             Status::Ok => {
                 if !return_data.is_null() {
-                    Ok(Some(
-                        String::from_utf8(Vec::from_raw_parts(
-                            return_data,
-                            return_size,
-                            return_size,
-                        ))
-                        .unwrap(),
-                    ))
+                    utils::decode_string(
+                        Vec::from_raw_parts(return_data, return_size, return_size),
+                        dispatcher::utf8_policy(),
+                        || format!("value of {:?}", key),
+                    )
                 } else {
                     Ok(None)
                 }
             }
             Status::NotFound => Ok(None),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_get_header_map_value",
+                status,
+                format!("map_type: {:?}, key: {:?}", map_type, key),
+            )),
         }
     }
 }
 
 pub fn get_map_value_bytes(map_type: MapType, key: &str) -> Result<Option<Bytes>, Status> {
+    expect_status(try_get_map_value_bytes(map_type, key), &[])
+}
+
+pub fn try_get_map_value_bytes(map_type: MapType, key: &str) -> Result<Option<Bytes>, Error> {
     let mut return_data: *mut u8 = null_mut();
     let mut return_size: usize = 0;
     unsafe {
@@ -273,7 +466,11 @@ pub fn get_map_value_bytes(map_type: MapType, key: &str) -> Result<Option<Bytes>
                 }
             }
             Status::NotFound => Ok(None),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_get_header_map_value",
+                status,
+                format!("map_type: {:?}, key: {:?}", map_type, key),
+            )),
         }
     }
 }
@@ -297,6 +494,10 @@ extern "C" {
 }
 
 pub fn set_map_value(map_type: MapType, key: &str, value: Option<&str>) -> Result<(), Status> {
+    expect_status(try_set_map_value(map_type, key, value), &[])
+}
+
+pub fn try_set_map_value(map_type: MapType, key: &str, value: Option<&str>) -> Result<(), Error> {
     unsafe {
         if let Some(value) = value {
             match proxy_replace_header_map_value(
@@ -307,12 +508,20 @@ pub fn set_map_value(map_type: MapType, key: &str, value: Option<&str>) -> Resul
                 value.len(),
             ) {
                 Status::Ok => Ok(()),
-                status => panic!("unexpected status: {}", status as u32),
+                status => Err(Error::hostcall(
+                    "proxy_replace_header_map_value",
+                    status,
+                    format!("map_type: {:?}, key: {:?}", map_type, key),
+                )),
             }
         } else {
             match proxy_remove_header_map_value(map_type, key.as_ptr(), key.len()) {
                 Status::Ok => Ok(()),
-                status => panic!("unexpected status: {}", status as u32),
+                status => Err(Error::hostcall(
+                    "proxy_remove_header_map_value",
+                    status,
+                    format!("map_type: {:?}, key: {:?}", map_type, key),
+                )),
             }
         }
     }
@@ -323,6 +532,14 @@ pub fn set_map_value_bytes(
     key: &str,
     value: Option<&[u8]>,
 ) -> Result<(), Status> {
+    expect_status(try_set_map_value_bytes(map_type, key, value), &[])
+}
+
+pub fn try_set_map_value_bytes(
+    map_type: MapType,
+    key: &str,
+    value: Option<&[u8]>,
+) -> Result<(), Error> {
     unsafe {
         if let Some(value) = value {
             match proxy_replace_header_map_value(
@@ -333,12 +550,20 @@ pub fn set_map_value_bytes(
                 value.len(),
             ) {
                 Status::Ok => Ok(()),
-                status => panic!("unexpected status: {}", status as u32),
+                status => Err(Error::hostcall(
+                    "proxy_replace_header_map_value",
+                    status,
+                    format!("map_type: {:?}, key: {:?}", map_type, key),
+                )),
             }
         } else {
             match proxy_remove_header_map_value(map_type, key.as_ptr(), key.len()) {
                 Status::Ok => Ok(()),
-                status => panic!("unexpected status: {}", status as u32),
+                status => Err(Error::hostcall(
+                    "proxy_remove_header_map_value",
+                    status,
+                    format!("map_type: {:?}, key: {:?}", map_type, key),
+                )),
             }
         }
     }
@@ -355,6 +580,10 @@ extern "C" {
 }
 
 pub fn add_map_value(map_type: MapType, key: &str, value: &str) -> Result<(), Status> {
+    expect_status(try_add_map_value(map_type, key, value), &[])
+}
+
+pub fn try_add_map_value(map_type: MapType, key: &str, value: &str) -> Result<(), Error> {
     unsafe {
         match proxy_add_header_map_value(
             map_type,
@@ -364,12 +593,20 @@ pub fn add_map_value(map_type: MapType, key: &str, value: &str) -> Result<(), St
             value.len(),
         ) {
             Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_add_header_map_value",
+                status,
+                format!("map_type: {:?}, key: {:?}", map_type, key),
+            )),
         }
     }
 }
 
 pub fn add_map_value_bytes(map_type: MapType, key: &str, value: &[u8]) -> Result<(), Status> {
+    expect_status(try_add_map_value_bytes(map_type, key, value), &[])
+}
+
+pub fn try_add_map_value_bytes(map_type: MapType, key: &str, value: &[u8]) -> Result<(), Error> {
     unsafe {
         match proxy_add_header_map_value(
             map_type,
@@ -379,7 +616,11 @@ pub fn add_map_value_bytes(map_type: MapType, key: &str, value: &[u8]) -> Result
             value.len(),
         ) {
             Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_add_header_map_value",
+                status,
+                format!("map_type: {:?}, key: {:?}", map_type, key),
+            )),
         }
     }
 }
@@ -394,7 +635,14 @@ extern "C" {
 }
 
 pub fn get_property(path: Vec<&str>) -> Result<Option<Bytes>, Status> {
-    let serialized_path = utils::serialize_property_path(path);
+    expect_status(
+        try_get_property(path),
+        &[Status::SerializationFailure, Status::InternalFailure],
+    )
+}
+
+pub fn try_get_property(path: Vec<&str>) -> Result<Option<Bytes>, Error> {
+    let serialized_path = utils::serialize_property_path(&path);
     let mut return_data: *mut u8 = null_mut();
     let mut return_size: usize = 0;
     unsafe {
@@ -416,13 +664,28 @@ pub fn get_property(path: Vec<&str>) -> Result<Option<Bytes>, Status> {
                 }
             }
             Status::NotFound => Ok(None),
-            Status::SerializationFailure => Err(Status::SerializationFailure),
-            Status::InternalFailure => Err(Status::InternalFailure),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_get_property",
+                status,
+                format!("path: {:?}", path),
+            )),
         }
     }
 }
 
+pub fn get_property_string(path: Vec<&str>) -> Result<Option<String>, Status> {
+    expect_status(try_get_property_string(path), &[])
+}
+
+pub fn try_get_property_string(path: Vec<&str>) -> Result<Option<String>, Error> {
+    match try_get_property(path.clone())? {
+        Some(value) => utils::decode_string(value, dispatcher::utf8_policy(), || {
+            format!("property {:?}", path)
+        }),
+        None => Ok(None),
+    }
+}
+
 extern "C" {
     fn proxy_set_property(
         path_data: *const u8,
@@ -433,7 +696,11 @@ extern "C" {
 }
 
 pub fn set_property(path: Vec<&str>, value: Option<&[u8]>) -> Result<(), Status> {
-    let serialized_path = utils::serialize_property_path(path);
+    expect_status(try_set_property(path, value), &[])
+}
+
+pub fn try_set_property(path: Vec<&str>, value: Option<&[u8]>) -> Result<(), Error> {
+    let serialized_path = utils::serialize_property_path(&path);
     unsafe {
         match proxy_set_property(
             serialized_path.as_ptr(),
@@ -442,7 +709,11 @@ pub fn set_property(path: Vec<&str>, value: Option<&[u8]>) -> Result<(), Status>
             value.map_or(0, |value| value.len()),
         ) {
             Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_set_property",
+                status,
+                format!("path: {:?}", path),
+            )),
         }
     }
 }
@@ -458,6 +729,10 @@ extern "C" {
 }
 
 pub fn get_shared_data(key: &str) -> Result<(Option<Bytes>, Option<u32>), Status> {
+    expect_status(try_get_shared_data(key), &[])
+}
+
+pub fn try_get_shared_data(key: &str) -> Result<(Option<Bytes>, Option<u32>), Error> {
     let mut return_data: *mut u8 = null_mut();
     let mut return_size: usize = 0;
     let mut return_cas: u32 = 0;
@@ -484,7 +759,11 @@ pub fn get_shared_data(key: &str) -> Result<(Option<Bytes>, Option<u32>), Status
                 }
             }
             Status::NotFound => Ok((None, None)),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_get_shared_data",
+                status,
+                format!("key: {:?}", key),
+            )),
         }
     }
 }
@@ -500,6 +779,10 @@ extern "C" {
 }
 
 pub fn set_shared_data(key: &str, value: Option<&[u8]>, cas: Option<u32>) -> Result<(), Status> {
+    expect_status(try_set_shared_data(key, value, cas), &[Status::CasMismatch])
+}
+
+pub fn try_set_shared_data(key: &str, value: Option<&[u8]>, cas: Option<u32>) -> Result<(), Error> {
     unsafe {
         match proxy_set_shared_data(
             key.as_ptr(),
@@ -509,8 +792,11 @@ pub fn set_shared_data(key: &str, value: Option<&[u8]>, cas: Option<u32>) -> Res
             cas.unwrap_or(0),
         ) {
             Status::Ok => Ok(()),
-            Status::CasMismatch => Err(Status::CasMismatch),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_set_shared_data",
+                status,
+                format!("key: {:?}, cas: {:?}", key, cas),
+            )),
         }
     }
 }
@@ -524,11 +810,19 @@ extern "C" {
 }
 
 pub fn register_shared_queue(name: &str) -> Result<u32, Status> {
+    expect_status(try_register_shared_queue(name), &[])
+}
+
+pub fn try_register_shared_queue(name: &str) -> Result<u32, Error> {
     unsafe {
         let mut return_id: u32 = 0;
         match proxy_register_shared_queue(name.as_ptr(), name.len(), &mut return_id) {
             Status::Ok => Ok(return_id),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_register_shared_queue",
+                status,
+                format!("name: {:?}", name),
+            )),
         }
     }
 }
@@ -544,6 +838,10 @@ extern "C" {
 }
 
 pub fn resolve_shared_queue(vm_id: &str, name: &str) -> Result<Option<u32>, Status> {
+    expect_status(try_resolve_shared_queue(vm_id, name), &[])
+}
+
+pub fn try_resolve_shared_queue(vm_id: &str, name: &str) -> Result<Option<u32>, Error> {
     let mut return_id: u32 = 0;
     unsafe {
         match proxy_resolve_shared_queue(
@@ -555,7 +853,11 @@ pub fn resolve_shared_queue(vm_id: &str, name: &str) -> Result<Option<u32>, Stat
         ) {
             Status::Ok => Ok(Some(return_id)),
             Status::NotFound => Ok(None),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_resolve_shared_queue",
+                status,
+                format!("vm_id: {:?}, name: {:?}", vm_id, name),
+            )),
         }
     }
 }
@@ -569,6 +871,10 @@ extern "C" {
 }
 
 pub fn dequeue_shared_queue(queue_id: u32) -> Result<Option<Bytes>, Status> {
+    expect_status(try_dequeue_shared_queue(queue_id), &[Status::NotFound])
+}
+
+pub fn try_dequeue_shared_queue(queue_id: u32) -> Result<Option<Bytes>, Error> {
     let mut return_data: *mut u8 = null_mut();
     let mut return_size: usize = 0;
     unsafe {
@@ -585,8 +891,11 @@ pub fn dequeue_shared_queue(queue_id: u32) -> Result<Option<Bytes>, Status> {
                 }
             }
             Status::Empty => Ok(None),
-            Status::NotFound => Err(Status::NotFound),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_dequeue_shared_queue",
+                status,
+                format!("queue_id: {:?}", queue_id),
+            )),
         }
     }
 }
@@ -600,6 +909,13 @@ extern "C" {
 }
 
 pub fn enqueue_shared_queue(queue_id: u32, value: Option<&[u8]>) -> Result<(), Status> {
+    expect_status(
+        try_enqueue_shared_queue(queue_id, value),
+        &[Status::NotFound],
+    )
+}
+
+pub fn try_enqueue_shared_queue(queue_id: u32, value: Option<&[u8]>) -> Result<(), Error> {
     unsafe {
         match proxy_enqueue_shared_queue(
             queue_id,
@@ -607,8 +923,11 @@ pub fn enqueue_shared_queue(queue_id: u32, value: Option<&[u8]>) -> Result<(), S
             value.map_or(0, |value| value.len()),
         ) {
             Status::Ok => Ok(()),
-            Status::NotFound => Err(Status::NotFound),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_enqueue_shared_queue",
+                status,
+                format!("queue_id: {:?}", queue_id),
+            )),
         }
     }
 }
@@ -617,79 +936,98 @@ extern "C" {
     fn proxy_continue_stream(stream_type: StreamType) -> Status;
 }
 
-pub fn resume_downstream() -> Result<(), Status> {
+fn try_continue_stream(stream_type: StreamType) -> Result<(), Error> {
     unsafe {
-        match proxy_continue_stream(StreamType::Downstream) {
+        match proxy_continue_stream(stream_type) {
             Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_continue_stream",
+                status,
+                format!("stream_type: {:?}", stream_type),
+            )),
         }
     }
 }
 
+pub fn resume_downstream() -> Result<(), Status> {
+    expect_status(try_resume_downstream(), &[])
+}
+
+pub fn try_resume_downstream() -> Result<(), Error> {
+    try_continue_stream(StreamType::Downstream)
+}
+
 pub fn resume_upstream() -> Result<(), Status> {
-    unsafe {
-        match proxy_continue_stream(StreamType::Upstream) {
-            Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
-        }
-    }
+    expect_status(try_resume_upstream(), &[])
+}
+
+pub fn try_resume_upstream() -> Result<(), Error> {
+    try_continue_stream(StreamType::Upstream)
 }
 
 pub fn resume_http_request() -> Result<(), Status> {
-    unsafe {
-        match proxy_continue_stream(StreamType::HttpRequest) {
-            Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
-        }
-    }
+    expect_status(try_resume_http_request(), &[])
+}
+
+pub fn try_resume_http_request() -> Result<(), Error> {
+    try_continue_stream(StreamType::HttpRequest)
 }
 
 pub fn resume_http_response() -> Result<(), Status> {
-    unsafe {
-        match proxy_continue_stream(StreamType::HttpResponse) {
-            Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
-        }
-    }
+    expect_status(try_resume_http_response(), &[])
+}
+
+pub fn try_resume_http_response() -> Result<(), Error> {
+    try_continue_stream(StreamType::HttpResponse)
 }
 
 extern "C" {
     fn proxy_close_stream(stream_type: StreamType) -> Status;
 }
 
-pub fn close_downstream() -> Result<(), Status> {
+fn try_close_stream(stream_type: StreamType) -> Result<(), Error> {
     unsafe {
-        match proxy_close_stream(StreamType::Downstream) {
+        match proxy_close_stream(stream_type) {
             Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_close_stream",
+                status,
+                format!("stream_type: {:?}", stream_type),
+            )),
         }
     }
 }
+
+pub fn close_downstream() -> Result<(), Status> {
+    expect_status(try_close_downstream(), &[])
+}
+
+pub fn try_close_downstream() -> Result<(), Error> {
+    try_close_stream(StreamType::Downstream)
+}
+
 pub fn close_upstream() -> Result<(), Status> {
-    unsafe {
-        match proxy_close_stream(StreamType::Upstream) {
-            Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
-        }
-    }
+    expect_status(try_close_upstream(), &[])
+}
+
+pub fn try_close_upstream() -> Result<(), Error> {
+    try_close_stream(StreamType::Upstream)
 }
 
 pub fn reset_http_request() -> Result<(), Status> {
-    unsafe {
-        match proxy_close_stream(StreamType::HttpRequest) {
-            Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
-        }
-    }
+    expect_status(try_reset_http_request(), &[])
+}
+
+pub fn try_reset_http_request() -> Result<(), Error> {
+    try_close_stream(StreamType::HttpRequest)
 }
 
 pub fn reset_http_response() -> Result<(), Status> {
-    unsafe {
-        match proxy_close_stream(StreamType::HttpResponse) {
-            Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
-        }
-    }
+    expect_status(try_reset_http_response(), &[])
+}
+
+pub fn try_reset_http_response() -> Result<(), Error> {
+    try_close_stream(StreamType::HttpResponse)
 }
 
 extern "C" {
@@ -710,22 +1048,40 @@ pub fn send_http_response(
     headers: Vec<(&str, &str)>,
     body: Option<&[u8]>,
 ) -> Result<(), Status> {
-    let serialized_headers = utils::serialize_map(headers);
-    unsafe {
-        match proxy_send_local_response(
-            status_code,
-            null(),
-            0,
-            body.map_or(null(), |body| body.as_ptr()),
-            body.map_or(0, |body| body.len()),
-            serialized_headers.as_ptr(),
-            serialized_headers.len(),
-            -1,
-        ) {
-            Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
-        }
-    }
+    expect_status(try_send_http_response(status_code, headers, body), &[])
+}
+
+pub fn try_send_http_response(
+    status_code: u32,
+    headers: Vec<(&str, &str)>,
+    body: Option<&[u8]>,
+) -> Result<(), Error> {
+    try_send_http_response_with_code_details(status_code, None, headers, body)
+}
+
+pub fn send_http_response_with_code_details(
+    status_code: u32,
+    status_code_details: Option<&str>,
+    headers: Vec<(&str, &str)>,
+    body: Option<&[u8]>,
+) -> Result<(), Status> {
+    expect_status(
+        try_send_http_response_with_code_details(status_code, status_code_details, headers, body),
+        &[],
+    )
+}
+
+pub fn try_send_http_response_with_code_details(
+    status_code: u32,
+    status_code_details: Option<&str>,
+    headers: Vec<(&str, &str)>,
+    body: Option<&[u8]>,
+) -> Result<(), Error> {
+    let headers = headers
+        .into_iter()
+        .map(|(name, value)| (name, value.as_bytes()))
+        .collect();
+    try_send_local_response(status_code, status_code_details, headers, body, None)
 }
 
 pub fn send_grpc_response(
@@ -733,20 +1089,98 @@ pub fn send_grpc_response(
     grpc_status_message: Option<&str>,
     custom_metadata: Vec<(&str, &[u8])>,
 ) -> Result<(), Status> {
-    let serialized_custom_metadata = utils::serialize_map_bytes(custom_metadata);
+    expect_status(
+        try_send_grpc_response(grpc_status, grpc_status_message, custom_metadata),
+        &[],
+    )
+}
+
+pub fn try_send_grpc_response(
+    grpc_status: GrpcStatusCode,
+    grpc_status_message: Option<&str>,
+    custom_metadata: Vec<(&str, &[u8])>,
+) -> Result<(), Error> {
+    try_send_grpc_response_with_code_details(
+        grpc_status,
+        grpc_status_message,
+        None,
+        custom_metadata,
+    )
+}
+
+pub fn send_grpc_response_with_code_details(
+    grpc_status: GrpcStatusCode,
+    grpc_status_message: Option<&str>,
+    status_code_details: Option<&str>,
+    custom_metadata: Vec<(&str, &[u8])>,
+) -> Result<(), Status> {
+    expect_status(
+        try_send_grpc_response_with_code_details(
+            grpc_status,
+            grpc_status_message,
+            status_code_details,
+            custom_metadata,
+        ),
+        &[],
+    )
+}
+
+pub fn try_send_grpc_response_with_code_details(
+    grpc_status: GrpcStatusCode,
+    grpc_status_message: Option<&str>,
+    status_code_details: Option<&str>,
+    custom_metadata: Vec<(&str, &[u8])>,
+) -> Result<(), Error> {
+    try_send_local_response(
+        200,
+        status_code_details,
+        custom_metadata,
+        grpc_status_message.map(str::as_bytes),
+        Some(grpc_status),
+    )
+}
+
+pub fn send_local_response(
+    status_code: u32,
+    status_code_details: Option<&str>,
+    headers: Vec<(&str, &[u8])>,
+    body: Option<&[u8]>,
+    grpc_status: Option<GrpcStatusCode>,
+) -> Result<(), Status> {
+    expect_status(
+        try_send_local_response(status_code, status_code_details, headers, body, grpc_status),
+        &[],
+    )
+}
+
+pub fn try_send_local_response(
+    status_code: u32,
+    status_code_details: Option<&str>,
+    headers: Vec<(&str, &[u8])>,
+    body: Option<&[u8]>,
+    grpc_status: Option<GrpcStatusCode>,
+) -> Result<(), Error> {
+    let serialized_headers = utils::serialize_map_bytes(headers);
     unsafe {
         match proxy_send_local_response(
-            200,
-            null(),
-            0,
-            grpc_status_message.map_or(null(), |grpc_status_message| grpc_status_message.as_ptr()),
-            grpc_status_message.map_or(0, |grpc_status_message| grpc_status_message.len()),
-            serialized_custom_metadata.as_ptr(),
-            serialized_custom_metadata.len(),
-            grpc_status as i32,
+            status_code,
+            status_code_details.map_or(null(), |details| details.as_ptr()),
+            status_code_details.map_or(0, |details| details.len()),
+            body.map_or(null(), |body| body.as_ptr()),
+            body.map_or(0, |body| body.len()),
+            serialized_headers.as_ptr(),
+            serialized_headers.len(),
+            grpc_status.map_or(-1, |grpc_status| grpc_status as i32),
         ) {
             Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_send_local_response",
+                status,
+                format!(
+                    "status_code: {:?}, grpc_status: {:?}",
+                    status_code, grpc_status
+                ),
+            )),
         }
     }
 }
@@ -773,6 +1207,19 @@ pub fn dispatch_http_call(
     trailers: Vec<(&str, &str)>,
     timeout: Duration,
 ) -> Result<u32, Status> {
+    expect_status(
+        try_dispatch_http_call(upstream, headers, body, trailers, timeout),
+        &[Status::BadArgument, Status::InternalFailure],
+    )
+}
+
+pub fn try_dispatch_http_call(
+    upstream: &str,
+    headers: Vec<(&str, &str)>,
+    body: Option<&[u8]>,
+    trailers: Vec<(&str, &str)>,
+    timeout: Duration,
+) -> Result<u32, Error> {
     let serialized_headers = utils::serialize_map(headers);
     let serialized_trailers = utils::serialize_map(trailers);
     let mut return_token: u32 = 0;
@@ -790,16 +1237,65 @@ pub fn dispatch_http_call(
             &mut return_token,
         ) {
             Status::Ok => {
-                dispatcher::register_callout(return_token);
+                dispatcher::register_callout(return_token, timeout);
                 Ok(return_token)
             }
-            Status::BadArgument => Err(Status::BadArgument),
-            Status::InternalFailure => Err(Status::InternalFailure),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_http_call",
+                status,
+                format!("upstream: {:?}, timeout: {:?}", upstream, timeout),
+            )),
         }
     }
 }
 
+// The response is delivered to `Context::on_http_call_result`.
+pub fn try_dispatch_http_call_request(request: &HttpCallRequest) -> Result<u32, Error> {
+    let token_id = try_dispatch_http_call(
+        request.get_upstream(),
+        request.get_headers()?,
+        request.get_body(),
+        request.get_trailers(),
+        request.get_timeout(),
+    )?;
+    dispatcher::register_typed_callout(token_id);
+    Ok(token_id)
+}
+
+pub fn get_http_call_response(
+    num_headers: usize,
+    body_size: usize,
+    num_trailers: usize,
+) -> Result<HttpCallResponse, Status> {
+    expect_status(
+        try_get_http_call_response(num_headers, body_size, num_trailers),
+        &[],
+    )
+}
+
+pub fn try_get_http_call_response(
+    num_headers: usize,
+    body_size: usize,
+    num_trailers: usize,
+) -> Result<HttpCallResponse, Error> {
+    let headers = if num_headers == 0 {
+        Vec::new()
+    } else {
+        try_get_map_bytes(MapType::HttpCallResponseHeaders)?
+    };
+    let body = if body_size == 0 {
+        None
+    } else {
+        try_get_buffer(BufferType::HttpCallResponseBody, 0, body_size)?
+    };
+    let trailers = if num_trailers == 0 {
+        Vec::new()
+    } else {
+        try_get_map_bytes(MapType::HttpCallResponseTrailers)?
+    };
+    Ok(HttpCallResponse::new(headers, body, trailers))
+}
+
 extern "C" {
     fn proxy_grpc_call(
         upstream_data: *const u8,
@@ -825,6 +1321,27 @@ pub fn dispatch_grpc_call(
     message: Option<&[u8]>,
     timeout: Duration,
 ) -> Result<u32, Status> {
+    expect_status(
+        try_dispatch_grpc_call(
+            upstream_name,
+            service_name,
+            method_name,
+            initial_metadata,
+            message,
+            timeout,
+        ),
+        &[Status::ParseFailure, Status::InternalFailure],
+    )
+}
+
+pub fn try_dispatch_grpc_call(
+    upstream_name: &str,
+    service_name: &str,
+    method_name: &str,
+    initial_metadata: Vec<(&str, &[u8])>,
+    message: Option<&[u8]>,
+    timeout: Duration,
+) -> Result<u32, Error> {
     let mut return_callout_id = 0;
     let serialized_initial_metadata = utils::serialize_map_bytes(initial_metadata);
     unsafe {
@@ -843,12 +1360,17 @@ pub fn dispatch_grpc_call(
             &mut return_callout_id,
         ) {
             Status::Ok => {
-                dispatcher::register_grpc_callout(return_callout_id);
+                dispatcher::register_grpc_callout(return_callout_id, timeout);
                 Ok(return_callout_id)
             }
-            Status::ParseFailure => Err(Status::ParseFailure),
-            Status::InternalFailure => Err(Status::InternalFailure),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_grpc_call",
+                status,
+                format!(
+                    "upstream_name: {:?}, service_name: {:?}, method_name: {:?}, timeout: {:?}",
+                    upstream_name, service_name, method_name, timeout
+                ),
+            )),
         }
     }
 }
@@ -873,6 +1395,18 @@ pub fn open_grpc_stream(
     method_name: &str,
     initial_metadata: Vec<(&str, &[u8])>,
 ) -> Result<u32, Status> {
+    expect_status(
+        try_open_grpc_stream(upstream_name, service_name, method_name, initial_metadata),
+        &[Status::ParseFailure, Status::InternalFailure],
+    )
+}
+
+pub fn try_open_grpc_stream(
+    upstream_name: &str,
+    service_name: &str,
+    method_name: &str,
+    initial_metadata: Vec<(&str, &[u8])>,
+) -> Result<u32, Error> {
     let mut return_stream_id = 0;
     let serialized_initial_metadata = utils::serialize_map_bytes(initial_metadata);
     unsafe {
@@ -891,9 +1425,14 @@ pub fn open_grpc_stream(
                 dispatcher::register_grpc_stream(return_stream_id);
                 Ok(return_stream_id)
             }
-            Status::ParseFailure => Err(Status::ParseFailure),
-            Status::InternalFailure => Err(Status::InternalFailure),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_grpc_stream",
+                status,
+                format!(
+                    "upstream_name: {:?}, service_name: {:?}, method_name: {:?}",
+                    upstream_name, service_name, method_name
+                ),
+            )),
         }
     }
 }
@@ -912,6 +1451,17 @@ pub fn send_grpc_stream_message(
     message: Option<&[u8]>,
     end_stream: bool,
 ) -> Result<(), Status> {
+    expect_status(
+        try_send_grpc_stream_message(token, message, end_stream),
+        &[Status::BadArgument, Status::NotFound],
+    )
+}
+
+pub fn try_send_grpc_stream_message(
+    token: u32,
+    message: Option<&[u8]>,
+    end_stream: bool,
+) -> Result<(), Error> {
     unsafe {
         match proxy_grpc_send(
             token,
@@ -920,9 +1470,11 @@ pub fn send_grpc_stream_message(
             end_stream,
         ) {
             Status::Ok => Ok(()),
-            Status::BadArgument => Err(Status::BadArgument),
-            Status::NotFound => Err(Status::NotFound),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_grpc_send",
+                status,
+                format!("token: {:?}, end_stream: {:?}", token, end_stream),
+            )),
         }
     }
 }
@@ -932,21 +1484,35 @@ extern "C" {
 }
 
 pub fn cancel_grpc_call(token_id: u32) -> Result<(), Status> {
+    expect_status(try_cancel_grpc_call(token_id), &[Status::NotFound])
+}
+
+pub fn try_cancel_grpc_call(token_id: u32) -> Result<(), Error> {
     unsafe {
         match proxy_grpc_cancel(token_id) {
             Status::Ok => Ok(()),
-            Status::NotFound => Err(Status::NotFound),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_grpc_cancel",
+                status,
+                format!("token_id: {:?}", token_id),
+            )),
         }
     }
 }
 
 pub fn cancel_grpc_stream(token_id: u32) -> Result<(), Status> {
+    expect_status(try_cancel_grpc_stream(token_id), &[Status::NotFound])
+}
+
+pub fn try_cancel_grpc_stream(token_id: u32) -> Result<(), Error> {
     unsafe {
         match proxy_grpc_cancel(token_id) {
             Status::Ok => Ok(()),
-            Status::NotFound => Err(Status::NotFound),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_grpc_cancel",
+                status,
+                format!("token_id: {:?}", token_id),
+            )),
         }
     }
 }
@@ -956,11 +1522,18 @@ extern "C" {
 }
 
 pub fn close_grpc_stream(token_id: u32) -> Result<(), Status> {
+    expect_status(try_close_grpc_stream(token_id), &[Status::NotFound])
+}
+
+pub fn try_close_grpc_stream(token_id: u32) -> Result<(), Error> {
     unsafe {
         match proxy_grpc_close(token_id) {
             Status::Ok => Ok(()),
-            Status::NotFound => Err(Status::NotFound),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_grpc_close",
+                status,
+                format!("token_id: {:?}", token_id),
+            )),
         }
     }
 }
@@ -974,6 +1547,26 @@ extern "C" {
 }
 
 pub fn get_grpc_status() -> Result<(u32, Option<String>), Status> {
+    expect_status(try_get_grpc_status(), &[])
+}
+
+pub fn try_get_grpc_status() -> Result<(u32, Option<String>), Error> {
+    match try_get_grpc_status_bytes()? {
+        (status_code, Some(message)) => Ok((
+            status_code,
+            utils::decode_string(message, dispatcher::utf8_policy(), || {
+                "gRPC status message".to_string()
+            })?,
+        )),
+        (status_code, None) => Ok((status_code, None)),
+    }
+}
+
+pub fn get_grpc_status_bytes() -> Result<(u32, Option<Bytes>), Status> {
+    expect_status(try_get_grpc_status_bytes(), &[])
+}
+
+pub fn try_get_grpc_status_bytes() -> Result<(u32, Option<Bytes>), Error> {
     let mut return_code: u32 = 0;
     let mut return_data: *mut u8 = null_mut();
     let mut return_size: usize = 0;
@@ -983,20 +1576,13 @@ pub fn get_grpc_status() -> Result<(u32, Option<String>), Status> {
                 if !return_data.is_null() {
                     Ok((
                         return_code,
-                        Some(
-                            String::from_utf8(Vec::from_raw_parts(
-                                return_data,
-                                return_size,
-                                return_size,
-                            ))
-                            .unwrap(),
-                        ),
+                        Some(Vec::from_raw_parts(return_data, return_size, return_size)),
                     ))
                 } else {
                     Ok((return_code, None))
                 }
             }
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall("proxy_get_status", status, String::new())),
         }
     }
 }
@@ -1006,11 +1592,21 @@ extern "C" {
 }
 
 pub fn set_effective_context(context_id: u32) -> Result<(), Status> {
+    expect_status(
+        try_set_effective_context(context_id),
+        &[Status::BadArgument],
+    )
+}
+
+pub fn try_set_effective_context(context_id: u32) -> Result<(), Error> {
     unsafe {
         match proxy_set_effective_context(context_id) {
             Status::Ok => Ok(()),
-            Status::BadArgument => Err(Status::BadArgument),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_set_effective_context",
+                status,
+                format!("context_id: {:?}", context_id),
+            )),
         }
     }
 }
@@ -1030,6 +1626,21 @@ pub fn call_foreign_function(
     function_name: &str,
     arguments: Option<&[u8]>,
 ) -> Result<Option<Bytes>, Status> {
+    expect_status(
+        try_call_foreign_function(function_name, arguments),
+        &[
+            Status::NotFound,
+            Status::BadArgument,
+            Status::SerializationFailure,
+            Status::InternalFailure,
+        ],
+    )
+}
+
+pub fn try_call_foreign_function(
+    function_name: &str,
+    arguments: Option<&[u8]>,
+) -> Result<Option<Bytes>, Error> {
     let mut return_data: *mut u8 = null_mut();
     let mut return_size: usize = 0;
     unsafe {
@@ -1052,11 +1663,11 @@ pub fn call_foreign_function(
                     Ok(None)
                 }
             }
-            Status::NotFound => Err(Status::NotFound),
-            Status::BadArgument => Err(Status::BadArgument),
-            Status::SerializationFailure => Err(Status::SerializationFailure),
-            Status::InternalFailure => Err(Status::InternalFailure),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_call_foreign_function",
+                status,
+                format!("function_name: {:?}", function_name),
+            )),
         }
     }
 }
@@ -1066,10 +1677,14 @@ extern "C" {
 }
 
 pub fn done() -> Result<(), Status> {
+    expect_status(try_done(), &[])
+}
+
+pub fn try_done() -> Result<(), Error> {
     unsafe {
         match proxy_done() {
             Status::Ok => Ok(()),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall("proxy_done", status, String::new())),
         }
     }
 }
@@ -1084,11 +1699,19 @@ extern "C" {
 }
 
 pub fn define_metric(metric_type: MetricType, name: &str) -> Result<u32, Status> {
+    expect_status(try_define_metric(metric_type, name), &[])
+}
+
+pub fn try_define_metric(metric_type: MetricType, name: &str) -> Result<u32, Error> {
     let mut return_id: u32 = 0;
     unsafe {
         match proxy_define_metric(metric_type, name.as_ptr(), name.len(), &mut return_id) {
             Status::Ok => Ok(return_id),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_define_metric",
+                status,
+                format!("metric_type: {:?}, name: {:?}", metric_type, name),
+            )),
         }
     }
 }
@@ -1098,13 +1721,22 @@ extern "C" {
 }
 
 pub fn get_metric(metric_id: u32) -> Result<u64, Status> {
+    expect_status(
+        try_get_metric(metric_id),
+        &[Status::NotFound, Status::BadArgument],
+    )
+}
+
+pub fn try_get_metric(metric_id: u32) -> Result<u64, Error> {
     let mut return_value: u64 = 0;
     unsafe {
         match proxy_get_metric(metric_id, &mut return_value) {
             Status::Ok => Ok(return_value),
-            Status::NotFound => Err(Status::NotFound),
-            Status::BadArgument => Err(Status::BadArgument),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_get_metric",
+                status,
+                format!("metric_id: {:?}", metric_id),
+            )),
         }
     }
 }
@@ -1114,11 +1746,18 @@ extern "C" {
 }
 
 pub fn record_metric(metric_id: u32, value: u64) -> Result<(), Status> {
+    expect_status(try_record_metric(metric_id, value), &[Status::NotFound])
+}
+
+pub fn try_record_metric(metric_id: u32, value: u64) -> Result<(), Error> {
     unsafe {
         match proxy_record_metric(metric_id, value) {
             Status::Ok => Ok(()),
-            Status::NotFound => Err(Status::NotFound),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_record_metric",
+                status,
+                format!("metric_id: {:?}", metric_id),
+            )),
         }
     }
 }
@@ -1128,30 +1767,46 @@ extern "C" {
 }
 
 pub fn increment_metric(metric_id: u32, offset: i64) -> Result<(), Status> {
+    expect_status(
+        try_increment_metric(metric_id, offset),
+        &[Status::NotFound, Status::BadArgument],
+    )
+}
+
+pub fn try_increment_metric(metric_id: u32, offset: i64) -> Result<(), Error> {
     unsafe {
         match proxy_increment_metric(metric_id, offset) {
             Status::Ok => Ok(()),
-            Status::NotFound => Err(Status::NotFound),
-            Status::BadArgument => Err(Status::BadArgument),
-            status => panic!("unexpected status: {}", status as u32),
+            status => Err(Error::hostcall(
+                "proxy_increment_metric",
+                status,
+                format!("metric_id: {:?}, offset: {:?}", metric_id, offset),
+            )),
         }
     }
 }
 
-mod utils {
-    use crate::types::Bytes;
+fn expect_status<T>(result: Result<T, Error>, expected: &[Status]) -> Result<T, Status> {
+    result.map_err(|error| match error.status() {
+        Some(status) if expected.contains(&status) => status,
+        _ => panic!("unexpected status: {}", error),
+    })
+}
+
+pub(crate) mod utils {
+    use crate::types::{Bytes, Error, Utf8Policy};
     use std::convert::TryFrom;
 
-    pub(super) fn serialize_property_path(path: Vec<&str>) -> Bytes {
+    pub(crate) fn serialize_property_path(path: &[&str]) -> Bytes {
         if path.is_empty() {
             return Vec::new();
         }
         let mut size: usize = 0;
-        for part in &path {
+        for part in path {
             size += part.len() + 1;
         }
         let mut bytes: Bytes = Vec::with_capacity(size);
-        for part in &path {
+        for part in path {
             bytes.extend_from_slice(part.as_bytes());
             bytes.push(0);
         }
@@ -1159,16 +1814,16 @@ mod utils {
         bytes
     }
 
-    pub(super) fn serialize_map(map: Vec<(&str, &str)>) -> Bytes {
+    pub(crate) fn serialize_map(map: Vec<(&str, &str)>) -> Bytes {
         let mut size: usize = 4;
         for (name, value) in &map {
             size += name.len() + value.len() + 10;
         }
         let mut bytes: Bytes = Vec::with_capacity(size);
-        bytes.extend_from_slice(&map.len().to_le_bytes());
+        bytes.extend_from_slice(&(map.len() as u32).to_le_bytes());
         for (name, value) in &map {
-            bytes.extend_from_slice(&name.len().to_le_bytes());
-            bytes.extend_from_slice(&value.len().to_le_bytes());
+            bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
         }
         for (name, value) in &map {
             bytes.extend_from_slice(name.as_bytes());
@@ -1179,16 +1834,16 @@ mod utils {
         bytes
     }
 
-    pub(super) fn serialize_map_bytes(map: Vec<(&str, &[u8])>) -> Bytes {
+    pub(crate) fn serialize_map_bytes(map: Vec<(&str, &[u8])>) -> Bytes {
         let mut size: usize = 4;
         for (name, value) in &map {
             size += name.len() + value.len() + 10;
         }
         let mut bytes: Bytes = Vec::with_capacity(size);
-        bytes.extend_from_slice(&map.len().to_le_bytes());
+        bytes.extend_from_slice(&(map.len() as u32).to_le_bytes());
         for (name, value) in &map {
-            bytes.extend_from_slice(&name.len().to_le_bytes());
-            bytes.extend_from_slice(&value.len().to_le_bytes());
+            bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
         }
         for (name, value) in &map {
             bytes.extend_from_slice(name.as_bytes());
@@ -1199,33 +1854,43 @@ mod utils {
         bytes
     }
 
-    pub(super) fn deserialize_map(bytes: &[u8]) -> Vec<(String, String)> {
-        if bytes.is_empty() {
-            return Vec::new();
+    pub(crate) fn decode_string(
+        bytes: Bytes,
+        policy: Utf8Policy,
+        key: impl FnOnce() -> String,
+    ) -> Result<Option<String>, Error> {
+        match String::from_utf8(bytes) {
+            Ok(value) => Ok(Some(value)),
+            Err(error) => match policy {
+                Utf8Policy::Error => Err(Error::InvalidUtf8 { key: key() }),
+                Utf8Policy::Lossy => {
+                    Ok(Some(String::from_utf8_lossy(error.as_bytes()).into_owned()))
+                }
+                Utf8Policy::Skip => Ok(None),
+            },
         }
-        let size = u32::from_le_bytes(<[u8; 4]>::try_from(&bytes[0..4]).unwrap()) as usize;
-        let mut map = Vec::with_capacity(size);
-        let mut p = 4 + size * 8;
-        for n in 0..size {
-            let s = 4 + n * 8;
-            let size = u32::from_le_bytes(<[u8; 4]>::try_from(&bytes[s..s + 4]).unwrap()) as usize;
-            let key = bytes[p..p + size].to_vec();
-            p += size + 1;
-            let size =
-                u32::from_le_bytes(<[u8; 4]>::try_from(&bytes[s + 4..s + 8]).unwrap()) as usize;
-            let value = bytes[p..p + size].to_vec();
-            p += size + 1;
-            map.push((
-                String::from_utf8(key).unwrap(),
-                String::from_utf8(value).unwrap(),
-            ));
+    }
+
+    pub(crate) fn deserialize_map(
+        bytes: &[u8],
+        policy: Utf8Policy,
+    ) -> Result<Vec<(String, String)>, Error> {
+        let mut map = Vec::new();
+        for (key, value) in deserialize_map_bytes(bytes, policy)? {
+            let value = decode_string(value, policy, || format!("value of {:?}", key))?;
+            if let Some(value) = value {
+                map.push((key, value));
+            }
         }
-        map
+        Ok(map)
     }
 
-    pub(super) fn deserialize_map_bytes(bytes: &[u8]) -> Vec<(String, Bytes)> {
+    pub(crate) fn deserialize_map_bytes(
+        bytes: &[u8],
+        policy: Utf8Policy,
+    ) -> Result<Vec<(String, Bytes)>, Error> {
         if bytes.is_empty() {
-            return Vec::new();
+            return Ok(Vec::new());
         }
         let size = u32::from_le_bytes(<[u8; 4]>::try_from(&bytes[0..4]).unwrap()) as usize;
         let mut map = Vec::with_capacity(size);
@@ -1239,8 +1904,10 @@ mod utils {
                 u32::from_le_bytes(<[u8; 4]>::try_from(&bytes[s + 4..s + 8]).unwrap()) as usize;
             let value = bytes[p..p + size].to_vec();
             p += size + 1;
-            map.push((String::from_utf8(key).unwrap(), value));
+            if let Some(key) = decode_string(key, policy, || "header name".to_string())? {
+                map.push((key, value));
+            }
         }
-        map
+        Ok(map)
     }
 }
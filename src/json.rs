@@ -0,0 +1,487 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON body rewriting.
+//!
+//! [`JsonBody`] is a [`BodyProcessor`] that buffers the body, parses it into
+//! a `serde_json::Value`, and replaces it with the result of a closure or a
+//! [`JsonPatch`]. Bodies that aren't valid JSON, or that the patch doesn't
+//! apply to, are forwarded unchanged.
+//!
+//! ```no_run
+//! use proxy_wasm::body::BodyFilter;
+//! use proxy_wasm::json::JsonBody;
+//! use proxy_wasm::traits::*;
+//! use proxy_wasm::types::*;
+//!
+//! struct Filter {
+//!     request_body: BodyFilter<JsonBody>,
+//! }
+//!
+//! impl Filter {
+//!     fn new() -> Filter {
+//!         let body = JsonBody::new(64 * 1024, |value| {
+//!             value["source"] = "proxy-wasm".into();
+//!             Ok(())
+//!         });
+//!         Filter {
+//!             request_body: BodyFilter::request(body),
+//!         }
+//!     }
+//! }
+//!
+//! impl Context for Filter {}
+//!
+//! impl HttpContext for Filter {
+//!     fn on_http_request_headers(&mut self, _: usize, end_of_stream: bool) -> Action {
+//!         self.request_body.on_headers(end_of_stream)
+//!     }
+//!
+//!     fn on_http_request_body(&mut self, body_size: usize, end_of_stream: bool) -> Action {
+//!         self.request_body.on_body(body_size, end_of_stream)
+//!     }
+//! }
+//! ```
+
+use crate::body::{BodyMode, BodyProcessor};
+use crate::hostcalls;
+use crate::types::*;
+use serde::de::{self, Deserialize, Deserializer};
+use serde_json::Value;
+
+type Rewrite = Box<dyn FnMut(&mut Value) -> Result<(), Error>>;
+
+/// Rewrites buffered JSON bodies of up to `max_size` bytes.
+pub struct JsonBody {
+    max_size: usize,
+    rewrite: Rewrite,
+}
+
+impl JsonBody {
+    /// Rewrites the body with `f`. If it returns an error, the body is
+    /// forwarded unchanged.
+    pub fn new<F>(max_size: usize, f: F) -> JsonBody
+    where
+        F: FnMut(&mut Value) -> Result<(), Error> + 'static,
+    {
+        JsonBody {
+            max_size,
+            rewrite: Box::new(f),
+        }
+    }
+
+    /// Rewrites the body with `patch`.
+    pub fn patch(max_size: usize, patch: JsonPatch) -> JsonBody {
+        JsonBody::new(max_size, move |value| patch.apply(value))
+    }
+}
+
+impl BodyProcessor for JsonBody {
+    fn mode(&self) -> BodyMode {
+        BodyMode::Buffer {
+            max_size: self.max_size,
+        }
+    }
+
+    fn on_body(&mut self, body: &[u8]) -> Option<Bytes> {
+        let result = serde_json::from_slice(body)
+            .map_err(|err| Error::Codec {
+                reason: err.to_string(),
+            })
+            .and_then(|mut value| {
+                (self.rewrite)(&mut value)?;
+                serde_json::to_vec(&value).map_err(|err| Error::Codec {
+                    reason: err.to_string(),
+                })
+            });
+        match result {
+            Ok(body) => Some(body),
+            Err(error) => {
+                let message = format!("forwarding JSON body unchanged: {}", error);
+                let _ = hostcalls::try_log(LogLevel::Warn, &message);
+                None
+            }
+        }
+    }
+}
+
+/// A JSON Patch (RFC 6902) document.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct JsonPatch(Vec<PatchOperation>);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+impl JsonPatch {
+    pub fn new(operations: Vec<PatchOperation>) -> JsonPatch {
+        JsonPatch(operations)
+    }
+
+    pub fn operations(&self) -> &[PatchOperation] {
+        &self.0
+    }
+
+    /// Parses a patch document, i.e. an array of operations.
+    pub fn from_value(document: &Value) -> Result<JsonPatch, Error> {
+        let operations = match document {
+            Value::Array(operations) => operations,
+            _ => return Err(patch_error("patch must be an array".to_string())),
+        };
+        operations
+            .iter()
+            .map(PatchOperation::from_value)
+            .collect::<Result<_, _>>()
+            .map(JsonPatch)
+    }
+
+    /// Applies all operations to `value`. If any of them fails, `value` is
+    /// left unchanged.
+    pub fn apply(&self, value: &mut Value) -> Result<(), Error> {
+        let mut patched = value.clone();
+        for operation in &self.0 {
+            operation.apply(&mut patched)?;
+        }
+        *value = patched;
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonPatch {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let document = Value::deserialize(deserializer)?;
+        JsonPatch::from_value(&document).map_err(de::Error::custom)
+    }
+}
+
+impl PatchOperation {
+    fn from_value(operation: &Value) -> Result<PatchOperation, Error> {
+        let member = |name: &str| match operation.get(name) {
+            Some(Value::String(value)) => Ok(value.clone()),
+            _ => Err(patch_error(format!("operation is missing \"{}\"", name))),
+        };
+        let value = || match operation.get("value") {
+            Some(value) => Ok(value.clone()),
+            None => Err(patch_error("operation is missing \"value\"".to_string())),
+        };
+        match member("op")?.as_str() {
+            "add" => Ok(PatchOperation::Add {
+                path: member("path")?,
+                value: value()?,
+            }),
+            "remove" => Ok(PatchOperation::Remove {
+                path: member("path")?,
+            }),
+            "replace" => Ok(PatchOperation::Replace {
+                path: member("path")?,
+                value: value()?,
+            }),
+            "move" => Ok(PatchOperation::Move {
+                from: member("from")?,
+                path: member("path")?,
+            }),
+            "copy" => Ok(PatchOperation::Copy {
+                from: member("from")?,
+                path: member("path")?,
+            }),
+            "test" => Ok(PatchOperation::Test {
+                path: member("path")?,
+                value: value()?,
+            }),
+            op => Err(patch_error(format!("unknown operation \"{}\"", op))),
+        }
+    }
+
+    fn apply(&self, document: &mut Value) -> Result<(), Error> {
+        match self {
+            PatchOperation::Add { path, value } => add(document, path, value.clone()),
+            PatchOperation::Remove { path } => remove(document, path).map(|_| ()),
+            PatchOperation::Replace { path, value } => match document.pointer_mut(path) {
+                Some(target) => {
+                    *target = value.clone();
+                    Ok(())
+                }
+                None => Err(not_found(path)),
+            },
+            PatchOperation::Move { from, path } => {
+                if path.starts_with(&format!("{}/", from)) {
+                    return Err(patch_error(format!("cannot move {} into itself", from)));
+                }
+                let value = remove(document, from)?;
+                add(document, path, value)
+            }
+            PatchOperation::Copy { from, path } => match document.pointer(from) {
+                Some(value) => add(document, path, value.clone()),
+                None => Err(not_found(from)),
+            },
+            PatchOperation::Test { path, value } => match document.pointer(path) {
+                Some(target) if target == value => Ok(()),
+                Some(_) => Err(patch_error(format!("test failed at {}", path))),
+                None => Err(not_found(path)),
+            },
+        }
+    }
+}
+
+fn add(document: &mut Value, path: &str, value: Value) -> Result<(), Error> {
+    let (parent, token) = match split_pointer(path)? {
+        Some(split) => split,
+        None => {
+            *document = value;
+            return Ok(());
+        }
+    };
+    match document.pointer_mut(parent) {
+        Some(Value::Object(object)) => {
+            object.insert(token, value);
+            Ok(())
+        }
+        Some(Value::Array(array)) => {
+            let index = match token.as_str() {
+                "-" => array.len(),
+                _ => array_index(&token, array.len() + 1, path)?,
+            };
+            array.insert(index, value);
+            Ok(())
+        }
+        _ => Err(not_found(path)),
+    }
+}
+
+fn remove(document: &mut Value, path: &str) -> Result<Value, Error> {
+    let (parent, token) = match split_pointer(path)? {
+        Some(split) => split,
+        None => return Err(patch_error("cannot remove the root".to_string())),
+    };
+    match document.pointer_mut(parent) {
+        Some(Value::Object(object)) => object.remove(&token).ok_or_else(|| not_found(path)),
+        Some(Value::Array(array)) => {
+            let index = array_index(&token, array.len(), path)?;
+            Ok(array.remove(index))
+        }
+        _ => Err(not_found(path)),
+    }
+}
+
+// Splits a JSON pointer into the pointer of the parent and the unescaped
+// last reference token. Returns `None` for the root.
+fn split_pointer(path: &str) -> Result<Option<(&str, String)>, Error> {
+    if path.is_empty() {
+        return Ok(None);
+    }
+    match path.rfind('/') {
+        Some(index) => {
+            let token = path[index + 1..].replace("~1", "/").replace("~0", "~");
+            Ok(Some((&path[..index], token)))
+        }
+        None => Err(patch_error(format!("invalid path {:?}", path))),
+    }
+}
+
+fn array_index(token: &str, len: usize, path: &str) -> Result<usize, Error> {
+    let valid = !token.is_empty()
+        && token.bytes().all(|b| b.is_ascii_digit())
+        && (token == "0" || !token.starts_with('0'));
+    match token.parse::<usize>() {
+        Ok(index) if valid && index < len => Ok(index),
+        _ => Err(not_found(path)),
+    }
+}
+
+fn not_found(path: &str) -> Error {
+    patch_error(format!("path {} not found", path))
+}
+
+fn patch_error(reason: String) -> Error {
+    Error::JsonPatch { reason }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn apply(document: Value, patch: Value) -> Result<Value, Error> {
+        let mut document = document;
+        JsonPatch::from_value(&patch)?.apply(&mut document)?;
+        Ok(document)
+    }
+
+    #[test]
+    fn add() {
+        // RFC 6902, A.1, A.2 and A.16.
+        let document = json!({"foo": ["bar", "baz"]});
+        let patch = json!([
+            {"op": "add", "path": "/baz", "value": "qux"},
+            {"op": "add", "path": "/foo/1", "value": "qux"},
+            {"op": "add", "path": "/foo/-", "value": ["abc", "def"]},
+        ]);
+        assert_eq!(
+            apply(document, patch).unwrap(),
+            json!({"baz": "qux", "foo": ["bar", "qux", "baz", ["abc", "def"]]})
+        );
+
+        // A.12: adding to a nonexistent target.
+        let patch = json!([{"op": "add", "path": "/baz/bat", "value": "qux"}]);
+        assert!(apply(json!({"foo": "bar"}), patch).is_err());
+
+        let patch = json!([{"op": "add", "path": "", "value": [1]}]);
+        assert_eq!(apply(json!({}), patch).unwrap(), json!([1]));
+    }
+
+    #[test]
+    fn remove() {
+        // RFC 6902, A.3 and A.4.
+        let document = json!({"baz": "qux", "foo": ["bar", "qux", "baz"]});
+        let patch = json!([
+            {"op": "remove", "path": "/baz"},
+            {"op": "remove", "path": "/foo/1"},
+        ]);
+        assert_eq!(
+            apply(document, patch).unwrap(),
+            json!({"foo": ["bar", "baz"]})
+        );
+
+        let patch = json!([{"op": "remove", "path": ""}]);
+        assert!(apply(json!({}), patch).is_err());
+    }
+
+    #[test]
+    fn replace_move_and_copy() {
+        // RFC 6902, A.5, A.6 and A.7.
+        let document = json!({
+            "baz": "qux",
+            "foo": {"bar": "baz", "waldo": "fred"},
+            "qux": {"corge": "grault"},
+            "list": ["all", "grass", "cows", "eat"],
+        });
+        let patch = json!([
+            {"op": "replace", "path": "/baz", "value": "boo"},
+            {"op": "move", "from": "/foo/waldo", "path": "/qux/thud"},
+            {"op": "move", "from": "/list/1", "path": "/list/3"},
+            {"op": "copy", "from": "/baz", "path": "/foo/baz"},
+        ]);
+        assert_eq!(
+            apply(document, patch).unwrap(),
+            json!({
+                "baz": "boo",
+                "foo": {"bar": "baz", "baz": "boo"},
+                "qux": {"corge": "grault", "thud": "fred"},
+                "list": ["all", "cows", "eat", "grass"],
+            })
+        );
+
+        let patch = json!([{"op": "replace", "path": "/missing", "value": 1}]);
+        assert!(apply(json!({}), patch).is_err());
+    }
+
+    #[test]
+    fn move_into_child() {
+        let document = json!({"a": {"b": {}}, "ab": 1});
+        let patch = json!([{"op": "move", "from": "/a", "path": "/a/b/c"}]);
+        assert!(apply(document.clone(), patch).is_err());
+
+        // Neither a sibling sharing a prefix, nor the location itself, is a child.
+        let patch = json!([
+            {"op": "move", "from": "/ab", "path": "/a/b/ab"},
+            {"op": "move", "from": "/a", "path": "/a"},
+        ]);
+        assert_eq!(
+            apply(document, patch).unwrap(),
+            json!({"a": {"b": {"ab": 1}}})
+        );
+    }
+
+    #[test]
+    fn test() {
+        // RFC 6902, A.8, A.9 and A.14.
+        let document = json!({"baz": "qux", "foo": ["a", 2, "c"], "/": 9, "~1": 10});
+        let patch = json!([
+            {"op": "test", "path": "/baz", "value": "qux"},
+            {"op": "test", "path": "/foo/1", "value": 2},
+            {"op": "test", "path": "/~01", "value": 10},
+        ]);
+        assert_eq!(apply(document.clone(), patch).unwrap(), document);
+
+        let patch = json!([{"op": "test", "path": "/baz", "value": "bar"}]);
+        assert!(apply(document, patch).is_err());
+    }
+
+    #[test]
+    fn failed_patch_leaves_document_unchanged() {
+        let document = json!({"baz": "qux", "foo": "bar"});
+        let patch = JsonPatch::from_value(&json!([
+            {"op": "add", "path": "/added", "value": true},
+            {"op": "remove", "path": "/foo"},
+            {"op": "test", "path": "/baz", "value": "bar"},
+        ]))
+        .unwrap();
+        let mut patched = document.clone();
+        assert!(patch.apply(&mut patched).is_err());
+        assert_eq!(patched, document);
+    }
+
+    #[test]
+    fn escapes() {
+        // RFC 6902, A.15, and tokens that only unescape correctly if `~1` is
+        // replaced before `~0`.
+        let document = json!({"/": 9, "~1": 10});
+        let patch = json!([
+            {"op": "test", "path": "/~01", "value": 10},
+            {"op": "move", "from": "/~1", "path": "/a~0b"},
+            {"op": "add", "path": "/~01", "value": 11},
+        ]);
+        assert_eq!(apply(document, patch).unwrap(), json!({"a~b": 9, "~1": 11}));
+    }
+
+    #[test]
+    fn array_indices() {
+        let document = json!({"foo": ["a", "b", "c"]});
+        for path in ["/foo/01", "/foo/-1", "/foo/+1", "/foo/3", "/foo/", "/foo/x"] {
+            let patch = json!([{"op": "remove", "path": path}]);
+            assert!(apply(document.clone(), patch).is_err(), "{}", path);
+        }
+        let patch = json!([{"op": "add", "path": "/foo/4", "value": "e"}]);
+        assert!(apply(document.clone(), patch).is_err());
+        let patch = json!([
+            {"op": "add", "path": "/foo/3", "value": "d"},
+            {"op": "remove", "path": "/foo/0"},
+        ]);
+        assert_eq!(
+            apply(document, patch).unwrap(),
+            json!({"foo": ["b", "c", "d"]})
+        );
+    }
+
+    #[test]
+    fn invalid_patches() {
+        for patch in [
+            json!({}),
+            json!([{"op": "frobnicate", "path": "/a"}]),
+            json!([{"op": "add", "path": "/a"}]),
+            json!([{"path": "/a", "value": 1}]),
+            json!([{"op": "move", "path": "/a"}]),
+        ] {
+            assert!(JsonPatch::from_value(&patch).is_err(), "{}", patch);
+        }
+        let patch = json!([{"op": "add", "path": "a", "value": 1}]);
+        assert!(apply(json!({}), patch).is_err());
+    }
+}
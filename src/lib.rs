@@ -12,10 +12,32 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod body;
+pub mod codec;
+pub mod encoding;
+pub mod grpc;
+pub mod header_case;
 pub mod hostcalls;
+pub mod metrics;
+pub mod properties;
+pub mod queue;
+pub mod response;
+pub mod router;
+pub mod shared_data;
 pub mod traits;
 pub mod types;
 
+pub use types::Error;
+
+#[cfg(any(feature = "json", feature = "yaml"))]
+pub mod config;
+#[cfg(feature = "async")]
+pub mod executor;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "testing")]
+pub mod testing;
+
 mod allocator;
 mod dispatcher;
 mod logger;
@@ -58,6 +80,15 @@ pub fn set_log_level(level: types::LogLevel) {
     logger::set_log_level(level);
 }
 
+pub fn set_panic_policy(policy: types::PanicPolicy) {
+    logger::set_panic_hook();
+    dispatcher::set_panic_policy(policy);
+}
+
+pub fn set_utf8_policy(policy: types::Utf8Policy) {
+    dispatcher::set_utf8_policy(policy);
+}
+
 pub fn set_root_context(callback: types::NewRootContext) {
     dispatcher::set_root_context(callback);
 }
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::dispatcher;
 use crate::hostcalls;
 use crate::types::LogLevel;
 use std::panic;
@@ -21,16 +22,30 @@ struct Logger;
 
 static LOGGER: Logger = Logger;
 static INITIALIZED: AtomicBool = AtomicBool::new(false);
+static PANIC_HOOK: AtomicBool = AtomicBool::new(false);
 
 pub(crate) fn set_log_level(level: LogLevel) {
-    if !INITIALIZED.load(Ordering::Relaxed) {
+    if !INITIALIZED.swap(true, Ordering::Relaxed) {
         log::set_logger(&LOGGER).unwrap();
+    }
+    set_panic_hook();
+    LOGGER.set_log_level(level);
+}
+
+pub(crate) fn set_panic_hook() {
+    if !PANIC_HOOK.swap(true, Ordering::Relaxed) {
         panic::set_hook(Box::new(|panic_info| {
-            hostcalls::log(LogLevel::Critical, &panic_info.to_string()).unwrap();
+            let message = match dispatcher::active_context_id() {
+                Some(context_id) if context_id != 0 => {
+                    format!("#{}: {}", context_id, panic_info)
+                }
+                _ => panic_info.to_string(),
+            };
+            // Logging must not panic again while handling a panic.
+            let _ = hostcalls::try_log(LogLevel::Critical, &message);
+            dispatcher::on_panic();
         }));
-        INITIALIZED.store(true, Ordering::Relaxed);
     }
-    LOGGER.set_log_level(level);
 }
 
 impl Logger {
@@ -0,0 +1,345 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Typed metric handles.
+//!
+//! Metrics are defined lazily, on first use, and their ids are cached in the
+//! [`Metrics`] registry that created them. A registry is meant to be created
+//! by the root context and cloned into every context created by it.
+//!
+//! Labels are encoded into the metric name, e.g. `requests_route=api` for the
+//! `requests` counter with the `route=api` label, so that they can be
+//! extracted by the host (e.g. using a `stats_tags` regex in Envoy). Separators are
+//! configurable, and occurrences of them in label names and values are
+//! replaced with the first of `_`, `-` and `.` that isn't part of either
+//! separator (i.e. `-` with the default separators).
+//!
+//! ```no_run
+//! use proxy_wasm::metrics::Metrics;
+//!
+//! let metrics = Metrics::new().with_prefix("myfilter.");
+//! let requests = proxy_wasm::counter!(metrics, "requests", "route" => "api");
+//! requests.inc();
+//! proxy_wasm::counter!(metrics, "responses", "code" => 200).inc();
+//! ```
+
+use crate::hostcalls;
+use crate::types::*;
+use hashbrown::HashMap;
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    registry: Rc<Registry>,
+}
+
+#[derive(Debug)]
+struct Registry {
+    prefix: String,
+    field_separator: String,
+    value_separator: String,
+    ids: RefCell<HashMap<(MetricType, String), u32>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            registry: Rc::new(Registry {
+                prefix: String::new(),
+                field_separator: "_".to_string(),
+                value_separator: "=".to_string(),
+                ids: RefCell::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Prepends `prefix` to the names of all metrics in this registry.
+    pub fn with_prefix(self, prefix: &str) -> Metrics {
+        self.rebuild(|registry| registry.prefix = prefix.to_string())
+    }
+
+    /// Sets the separators used to encode labels into metric names, i.e.
+    /// `<name><field_separator><key><value_separator><value>` for each label.
+    /// They default to `_` and `=`, respectively. Occurrences of separators in
+    /// label names and values are replaced with the first of `_`, `-` and `.`
+    /// that isn't part of either separator, or removed if there is none.
+    pub fn with_tag_separators(self, field_separator: &str, value_separator: &str) -> Metrics {
+        self.rebuild(|registry| {
+            registry.field_separator = field_separator.to_string();
+            registry.value_separator = value_separator.to_string();
+        })
+    }
+
+    fn rebuild(self, f: impl FnOnce(&mut Registry)) -> Metrics {
+        let mut registry = Registry {
+            prefix: self.registry.prefix.clone(),
+            field_separator: self.registry.field_separator.clone(),
+            value_separator: self.registry.value_separator.clone(),
+            ids: RefCell::new(HashMap::new()),
+        };
+        f(&mut registry);
+        Metrics {
+            registry: Rc::new(registry),
+        }
+    }
+
+    pub fn counter(&self, name: &str) -> Counter {
+        Counter(self.handle(MetricType::Counter, name))
+    }
+
+    pub fn gauge(&self, name: &str) -> Gauge {
+        Gauge(self.handle(MetricType::Gauge, name))
+    }
+
+    pub fn histogram(&self, name: &str) -> Histogram {
+        Histogram(self.handle(MetricType::Histogram, name))
+    }
+
+    fn handle(&self, metric_type: MetricType, name: &str) -> Handle {
+        Handle {
+            registry: Rc::clone(&self.registry),
+            metric_type,
+            name: format!("{}{}", self.registry.prefix, name),
+            id: Cell::new(None),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Handle {
+    registry: Rc<Registry>,
+    metric_type: MetricType,
+    name: String,
+    id: Cell<Option<u32>>,
+}
+
+impl Handle {
+    fn with_label(&self, key: &str, value: &dyn fmt::Display) -> Handle {
+        let registry = &self.registry;
+        Handle {
+            registry: Rc::clone(registry),
+            metric_type: self.metric_type,
+            name: format!(
+                "{}{}{}{}{}",
+                self.name,
+                registry.field_separator,
+                registry.sanitize(key),
+                registry.value_separator,
+                registry.sanitize(&value.to_string())
+            ),
+            id: Cell::new(None),
+        }
+    }
+
+    fn id(&self) -> Result<u32, Error> {
+        if let Some(id) = self.id.get() {
+            return Ok(id);
+        }
+        let key = (self.metric_type, self.name.clone());
+        let cached = self.registry.ids.borrow().get(&key).copied();
+        let id = match cached {
+            Some(id) => id,
+            None => {
+                let id = hostcalls::try_define_metric(self.metric_type, &self.name)?;
+                self.registry.ids.borrow_mut().insert(key, id);
+                id
+            }
+        };
+        self.id.set(Some(id));
+        Ok(id)
+    }
+}
+
+impl Registry {
+    // Replacing separators with a character that is part of one of them would
+    // make the encoded name ambiguous.
+    fn sanitize(&self, part: &str) -> String {
+        let separators = [&self.field_separator, &self.value_separator];
+        let replacement = ["_", "-", "."]
+            .iter()
+            .find(|c| separators.iter().all(|separator| !separator.contains(*c)))
+            .map_or("", |c| *c);
+        let mut part = part.to_string();
+        for separator in separators {
+            if !separator.is_empty() {
+                part = part.replace(separator.as_str(), replacement);
+            }
+        }
+        part
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Counter(Handle);
+
+impl Counter {
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    pub fn with_label(&self, key: &str, value: impl fmt::Display) -> Counter {
+        Counter(self.0.with_label(key, &value))
+    }
+
+    pub fn inc(&self) {
+        self.increment(1)
+    }
+
+    pub fn increment(&self, offset: u64) {
+        self.try_increment(offset).unwrap()
+    }
+
+    pub fn try_increment(&self, offset: u64) -> Result<(), Error> {
+        let metric_id = self.0.id()?;
+        match i64::try_from(offset) {
+            Ok(offset) => hostcalls::try_increment_metric(metric_id, offset),
+            Err(_) => Err(Error::hostcall(
+                "proxy_increment_metric",
+                Status::BadArgument,
+                format!("metric_id: {:?}, offset: {:?}", metric_id, offset),
+            )),
+        }
+    }
+
+    pub fn value(&self) -> u64 {
+        self.try_value().unwrap()
+    }
+
+    pub fn try_value(&self) -> Result<u64, Error> {
+        hostcalls::try_get_metric(self.0.id()?)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Gauge(Handle);
+
+impl Gauge {
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    pub fn with_label(&self, key: &str, value: impl fmt::Display) -> Gauge {
+        Gauge(self.0.with_label(key, &value))
+    }
+
+    pub fn set(&self, value: u64) {
+        self.try_set(value).unwrap()
+    }
+
+    pub fn try_set(&self, value: u64) -> Result<(), Error> {
+        hostcalls::try_record_metric(self.0.id()?, value)
+    }
+
+    pub fn increment(&self, offset: i64) {
+        self.try_increment(offset).unwrap()
+    }
+
+    pub fn try_increment(&self, offset: i64) -> Result<(), Error> {
+        hostcalls::try_increment_metric(self.0.id()?, offset)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.try_value().unwrap()
+    }
+
+    pub fn try_value(&self) -> Result<u64, Error> {
+        hostcalls::try_get_metric(self.0.id()?)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Histogram(Handle);
+
+impl Histogram {
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    pub fn with_label(&self, key: &str, value: impl fmt::Display) -> Histogram {
+        Histogram(self.0.with_label(key, &value))
+    }
+
+    pub fn record(&self, value: u64) {
+        self.try_record(value).unwrap()
+    }
+
+    pub fn try_record(&self, value: u64) -> Result<(), Error> {
+        hostcalls::try_record_metric(self.0.id()?, value)
+    }
+}
+
+/// Returns a [`Counter`](crate::metrics::Counter) from a
+/// [`Metrics`](crate::metrics::Metrics) registry, with optional labels.
+#[macro_export]
+macro_rules! counter {
+    ($metrics:expr, $name:expr $(, $key:expr => $value:expr)* $(,)?) => {
+        $metrics.counter($name)$(.with_label($key, $value))*
+    };
+}
+
+/// Returns a [`Gauge`](crate::metrics::Gauge) from a
+/// [`Metrics`](crate::metrics::Metrics) registry, with optional labels.
+#[macro_export]
+macro_rules! gauge {
+    ($metrics:expr, $name:expr $(, $key:expr => $value:expr)* $(,)?) => {
+        $metrics.gauge($name)$(.with_label($key, $value))*
+    };
+}
+
+/// Returns a [`Histogram`](crate::metrics::Histogram) from a
+/// [`Metrics`](crate::metrics::Metrics) registry, with optional labels.
+#[macro_export]
+macro_rules! histogram {
+    ($metrics:expr, $name:expr $(, $key:expr => $value:expr)* $(,)?) => {
+        $metrics.histogram($name)$(.with_label($key, $value))*
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_encoding() {
+        let metrics = Metrics::new().with_prefix("f.");
+        let counter = metrics.counter("req").with_label("route", "api");
+        assert_eq!(counter.name(), "f.req_route=api");
+        let counter = counter.with_label("code", 200);
+        assert_eq!(counter.name(), "f.req_route=api_code=200");
+    }
+
+    #[test]
+    fn label_sanitization() {
+        let metrics = Metrics::new();
+        let gauge = metrics.gauge("g").with_label("http_method", "a=b_c");
+        assert_eq!(gauge.name(), "g_http-method=a-b-c");
+
+        let metrics = Metrics::new().with_tag_separators(".", ":");
+        let gauge = metrics.gauge("g").with_label("a.b", "c:d_e");
+        assert_eq!(gauge.name(), "g.a_b:c_d_e");
+
+        let metrics = Metrics::new().with_tag_separators("_.", "-");
+        let histogram = metrics.histogram("h").with_label("a_.b", "c-d");
+        assert_eq!(histogram.name(), "h_.ab-cd");
+    }
+}
@@ -0,0 +1,691 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Typed accessors for well-known host properties (Envoy attributes).
+//!
+//! Each accessor reads a single property of the current context and decodes
+//! it from the host encoding: strings are UTF-8 (decoded according to the
+//! [`Utf8Policy`]), integers are 64-bit
+//! little-endian, booleans are a single byte, durations and timestamps are
+//! 64-bit little-endian nanoseconds, header maps use the Proxy-Wasm map
+//! serialization, and metadata is a serialized `google.protobuf.Struct`.
+//!
+//! Accessors return `Ok(None)` if the property is not available, and
+//! [`Error::InvalidProperty`] if its value cannot be decoded.
+
+use crate::hostcalls;
+use crate::types::*;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Request attributes.
+
+pub fn request_path() -> Result<Option<String>, Error> {
+    string(&["request", "path"])
+}
+
+pub fn request_url_path() -> Result<Option<String>, Error> {
+    string(&["request", "url_path"])
+}
+
+pub fn request_host() -> Result<Option<String>, Error> {
+    string(&["request", "host"])
+}
+
+pub fn request_scheme() -> Result<Option<String>, Error> {
+    string(&["request", "scheme"])
+}
+
+pub fn request_method() -> Result<Option<String>, Error> {
+    string(&["request", "method"])
+}
+
+pub fn request_headers() -> Result<Option<HeaderMap>, Error> {
+    map(&["request", "headers"])
+}
+
+pub fn request_referer() -> Result<Option<String>, Error> {
+    string(&["request", "referer"])
+}
+
+pub fn request_user_agent() -> Result<Option<String>, Error> {
+    string(&["request", "useragent"])
+}
+
+pub fn request_time() -> Result<Option<SystemTime>, Error> {
+    timestamp(&["request", "time"])
+}
+
+pub fn request_id() -> Result<Option<String>, Error> {
+    string(&["request", "id"])
+}
+
+pub fn request_protocol() -> Result<Option<String>, Error> {
+    string(&["request", "protocol"])
+}
+
+pub fn request_query() -> Result<Option<String>, Error> {
+    string(&["request", "query"])
+}
+
+pub fn request_duration() -> Result<Option<Duration>, Error> {
+    duration(&["request", "duration"])
+}
+
+pub fn request_size() -> Result<Option<u64>, Error> {
+    uint(&["request", "size"])
+}
+
+pub fn request_total_size() -> Result<Option<u64>, Error> {
+    uint(&["request", "total_size"])
+}
+
+// Response attributes.
+
+pub fn response_code() -> Result<Option<u32>, Error> {
+    uint32(&["response", "code"])
+}
+
+pub fn response_code_details() -> Result<Option<String>, Error> {
+    string(&["response", "code_details"])
+}
+
+pub fn response_flags() -> Result<Option<u64>, Error> {
+    uint(&["response", "flags"])
+}
+
+pub fn response_grpc_status() -> Result<Option<u32>, Error> {
+    uint32(&["response", "grpc_status"])
+}
+
+pub fn response_headers() -> Result<Option<HeaderMap>, Error> {
+    map(&["response", "headers"])
+}
+
+pub fn response_trailers() -> Result<Option<HeaderMap>, Error> {
+    map(&["response", "trailers"])
+}
+
+pub fn response_size() -> Result<Option<u64>, Error> {
+    uint(&["response", "size"])
+}
+
+pub fn response_total_size() -> Result<Option<u64>, Error> {
+    uint(&["response", "total_size"])
+}
+
+// Connection attributes.
+
+pub fn source_address() -> Result<Option<String>, Error> {
+    string(&["source", "address"])
+}
+
+pub fn source_port() -> Result<Option<u32>, Error> {
+    uint32(&["source", "port"])
+}
+
+pub fn destination_address() -> Result<Option<String>, Error> {
+    string(&["destination", "address"])
+}
+
+pub fn destination_port() -> Result<Option<u32>, Error> {
+    uint32(&["destination", "port"])
+}
+
+pub fn connection_id() -> Result<Option<u64>, Error> {
+    uint(&["connection", "id"])
+}
+
+pub fn connection_mtls() -> Result<Option<bool>, Error> {
+    boolean(&["connection", "mtls"])
+}
+
+pub fn connection_requested_server_name() -> Result<Option<String>, Error> {
+    string(&["connection", "requested_server_name"])
+}
+
+pub fn connection_tls_version() -> Result<Option<String>, Error> {
+    string(&["connection", "tls_version"])
+}
+
+pub fn connection_subject_local_certificate() -> Result<Option<String>, Error> {
+    string(&["connection", "subject_local_certificate"])
+}
+
+pub fn connection_subject_peer_certificate() -> Result<Option<String>, Error> {
+    string(&["connection", "subject_peer_certificate"])
+}
+
+pub fn connection_termination_details() -> Result<Option<String>, Error> {
+    string(&["connection", "termination_details"])
+}
+
+// Upstream attributes.
+
+/// Address of the upstream host of the upstream connection.
+pub fn upstream_host() -> Result<Option<String>, Error> {
+    string(&["upstream", "address"])
+}
+
+pub fn upstream_port() -> Result<Option<u32>, Error> {
+    uint32(&["upstream", "port"])
+}
+
+pub fn upstream_local_address() -> Result<Option<String>, Error> {
+    string(&["upstream", "local_address"])
+}
+
+pub fn upstream_tls_version() -> Result<Option<String>, Error> {
+    string(&["upstream", "tls_version"])
+}
+
+pub fn upstream_transport_failure_reason() -> Result<Option<String>, Error> {
+    string(&["upstream", "transport_failure_reason"])
+}
+
+// Plugin and configuration attributes.
+
+pub fn plugin_name() -> Result<Option<String>, Error> {
+    string(&["plugin_name"])
+}
+
+pub fn plugin_root_id() -> Result<Option<String>, Error> {
+    string(&["plugin_root_id"])
+}
+
+pub fn plugin_vm_id() -> Result<Option<String>, Error> {
+    string(&["plugin_vm_id"])
+}
+
+pub fn cluster_name() -> Result<Option<String>, Error> {
+    string(&["cluster_name"])
+}
+
+pub fn route_name() -> Result<Option<String>, Error> {
+    string(&["route_name"])
+}
+
+pub fn node_metadata() -> Result<Option<Struct>, Error> {
+    structure(&["node", "metadata"])
+}
+
+pub fn cluster_metadata() -> Result<Option<Struct>, Error> {
+    structure(&["cluster_metadata"])
+}
+
+pub fn listener_metadata() -> Result<Option<Struct>, Error> {
+    structure(&["listener_metadata"])
+}
+
+pub fn route_metadata() -> Result<Option<Struct>, Error> {
+    structure(&["route_metadata"])
+}
+
+pub fn upstream_host_metadata() -> Result<Option<Struct>, Error> {
+    structure(&["upstream_host_metadata"])
+}
+
+// Decoders.
+
+fn raw(path: &[&str]) -> Result<Option<Bytes>, Error> {
+    hostcalls::try_get_property(path.to_vec())
+}
+
+fn invalid(path: &[&str], reason: &'static str) -> Error {
+    Error::InvalidProperty {
+        path: path.join("."),
+        reason,
+    }
+}
+
+fn string(path: &[&str]) -> Result<Option<String>, Error> {
+    hostcalls::try_get_property_string(path.to_vec())
+}
+
+fn int(path: &[&str]) -> Result<Option<i64>, Error> {
+    match raw(path)? {
+        Some(bytes) => match <[u8; 8]>::try_from(bytes.as_slice()) {
+            Ok(bytes) => Ok(Some(i64::from_le_bytes(bytes))),
+            Err(_) => Err(invalid(path, "expected 64-bit integer")),
+        },
+        None => Ok(None),
+    }
+}
+
+fn uint(path: &[&str]) -> Result<Option<u64>, Error> {
+    match int(path)? {
+        Some(value) => match u64::try_from(value) {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(invalid(path, "negative value")),
+        },
+        None => Ok(None),
+    }
+}
+
+fn uint32(path: &[&str]) -> Result<Option<u32>, Error> {
+    match int(path)? {
+        Some(value) => match u32::try_from(value) {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(invalid(path, "value out of range")),
+        },
+        None => Ok(None),
+    }
+}
+
+fn boolean(path: &[&str]) -> Result<Option<bool>, Error> {
+    match raw(path)?.as_deref() {
+        Some([value]) => Ok(Some(*value != 0)),
+        Some(_) => Err(invalid(path, "expected boolean")),
+        None => Ok(None),
+    }
+}
+
+fn duration(path: &[&str]) -> Result<Option<Duration>, Error> {
+    match int(path)? {
+        Some(nanos) => match u64::try_from(nanos) {
+            Ok(nanos) => Ok(Some(Duration::from_nanos(nanos))),
+            Err(_) => Err(invalid(path, "negative duration")),
+        },
+        None => Ok(None),
+    }
+}
+
+fn timestamp(path: &[&str]) -> Result<Option<SystemTime>, Error> {
+    match int(path)? {
+        Some(nanos) if nanos >= 0 => Ok(Some(UNIX_EPOCH + Duration::from_nanos(nanos as u64))),
+        Some(nanos) => match UNIX_EPOCH.checked_sub(Duration::from_nanos(nanos.unsigned_abs())) {
+            Some(time) => Ok(Some(time)),
+            None => Err(invalid(path, "timestamp out of range")),
+        },
+        None => Ok(None),
+    }
+}
+
+fn map(path: &[&str]) -> Result<Option<HeaderMap>, Error> {
+    match raw(path)? {
+        Some(bytes) => HeaderMap::from_bytes(bytes).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn structure(path: &[&str]) -> Result<Option<Struct>, Error> {
+    match raw(path)? {
+        Some(bytes) => match protobuf::decode_struct(&bytes) {
+            Ok(value) => Ok(Some(value)),
+            Err(reason) => Err(invalid(path, reason)),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Decoded `google.protobuf.Struct`.
+pub type Struct = BTreeMap<String, Value>;
+
+/// Decoded `google.protobuf.Value`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Number(f64),
+    String(String),
+    Bool(bool),
+    Struct(Struct),
+    List(Vec<Value>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_struct(&self) -> Option<&Struct> {
+        match self {
+            Value::Struct(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+// Minimal decoder for the well-known `google.protobuf.Struct` type.
+mod protobuf {
+    use super::{Struct, Value};
+    use std::convert::TryFrom;
+
+    const VARINT: u64 = 0;
+    const FIXED64: u64 = 1;
+    const LENGTH_DELIMITED: u64 = 2;
+    const FIXED32: u64 = 5;
+
+    struct Reader<'a> {
+        bytes: &'a [u8],
+    }
+
+    enum Field<'a> {
+        Varint(u64),
+        Fixed64(u64),
+        Bytes(&'a [u8]),
+        Fixed32,
+    }
+
+    impl<'a> Reader<'a> {
+        fn varint(&mut self) -> Option<u64> {
+            let mut value: u64 = 0;
+            for shift in (0..64).step_by(7) {
+                let (byte, rest) = self.bytes.split_first()?;
+                self.bytes = rest;
+                value |= u64::from(byte & 0x7f) << shift;
+                if byte & 0x80 == 0 {
+                    return Some(value);
+                }
+            }
+            None
+        }
+
+        fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+            if len > self.bytes.len() {
+                return None;
+            }
+            let (value, rest) = self.bytes.split_at(len);
+            self.bytes = rest;
+            Some(value)
+        }
+
+        fn field(&mut self) -> Option<Option<(u64, Field<'a>)>> {
+            if self.bytes.is_empty() {
+                return Some(None);
+            }
+            let key = self.varint()?;
+            let field = match key & 7 {
+                VARINT => Field::Varint(self.varint()?),
+                FIXED64 => {
+                    let mut value = [0; 8];
+                    value.copy_from_slice(self.take(8)?);
+                    Field::Fixed64(u64::from_le_bytes(value))
+                }
+                LENGTH_DELIMITED => {
+                    let len = self.varint()?;
+                    Field::Bytes(self.take(usize::try_from(len).ok()?)?)
+                }
+                FIXED32 => {
+                    self.take(4)?;
+                    Field::Fixed32
+                }
+                _ => return None,
+            };
+            Some(Some((key >> 3, field)))
+        }
+    }
+
+    // Same as the default recursion limit of protobuf parsers.
+    const MAX_DEPTH: usize = 100;
+
+    const MALFORMED: &str = "expected google.protobuf.Struct";
+    const TOO_DEEP: &str = "google.protobuf.Struct nested too deeply";
+
+    pub(super) fn decode_struct(bytes: &[u8]) -> Result<Struct, &'static str> {
+        decode_nested_struct(bytes, 0)
+    }
+
+    fn decode_nested_struct(bytes: &[u8], depth: usize) -> Result<Struct, &'static str> {
+        if depth > MAX_DEPTH {
+            return Err(TOO_DEEP);
+        }
+        let mut reader = Reader { bytes };
+        let mut fields = Struct::new();
+        while let Some((number, field)) = reader.field().ok_or(MALFORMED)? {
+            if let (1, Field::Bytes(entry)) = (number, field) {
+                let (key, value) = decode_entry(entry, depth)?;
+                fields.insert(key, value);
+            }
+        }
+        Ok(fields)
+    }
+
+    fn decode_entry(bytes: &[u8], depth: usize) -> Result<(String, Value), &'static str> {
+        let mut reader = Reader { bytes };
+        let mut key = String::new();
+        let mut value = Value::Null;
+        while let Some((number, field)) = reader.field().ok_or(MALFORMED)? {
+            match (number, field) {
+                (1, Field::Bytes(bytes)) => key = string(bytes)?,
+                (2, Field::Bytes(bytes)) => value = decode_value(bytes, depth)?,
+                _ => {}
+            }
+        }
+        Ok((key, value))
+    }
+
+    fn decode_value(bytes: &[u8], depth: usize) -> Result<Value, &'static str> {
+        let mut reader = Reader { bytes };
+        let mut value = Value::Null;
+        while let Some((number, field)) = reader.field().ok_or(MALFORMED)? {
+            value = match (number, field) {
+                (1, Field::Varint(_)) => Value::Null,
+                (2, Field::Fixed64(bits)) => Value::Number(f64::from_bits(bits)),
+                (3, Field::Bytes(bytes)) => Value::String(string(bytes)?),
+                (4, Field::Varint(flag)) => Value::Bool(flag != 0),
+                (5, Field::Bytes(bytes)) => Value::Struct(decode_nested_struct(bytes, depth + 1)?),
+                (6, Field::Bytes(bytes)) => Value::List(decode_list(bytes, depth + 1)?),
+                _ => continue,
+            };
+        }
+        Ok(value)
+    }
+
+    fn decode_list(bytes: &[u8], depth: usize) -> Result<Vec<Value>, &'static str> {
+        if depth > MAX_DEPTH {
+            return Err(TOO_DEEP);
+        }
+        let mut reader = Reader { bytes };
+        let mut values = Vec::new();
+        while let Some((number, field)) = reader.field().ok_or(MALFORMED)? {
+            if let (1, Field::Bytes(bytes)) = (number, field) {
+                values.push(decode_value(bytes, depth)?);
+            }
+        }
+        Ok(values)
+    }
+
+    fn string(bytes: &[u8]) -> Result<String, &'static str> {
+        String::from_utf8(bytes.to_vec()).map_err(|_| MALFORMED)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::MockHost;
+
+    fn varint(mut value: usize) -> Bytes {
+        let mut bytes = Vec::new();
+        while value >= 0x80 {
+            bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+        bytes
+    }
+
+    fn field(number: u8, bytes: &[u8]) -> Bytes {
+        let mut field = vec![number << 3 | 2];
+        field.extend(varint(bytes.len()));
+        field.extend_from_slice(bytes);
+        field
+    }
+
+    fn entry(key: &str, value: &[u8]) -> Bytes {
+        let mut entry = field(1, key.as_bytes());
+        entry.extend(field(2, value));
+        field(1, &entry)
+    }
+
+    // A `Value` with a `Struct` with a single "k" field, nested `depth` times.
+    fn nested(depth: usize) -> Bytes {
+        let mut value = vec![1 << 3, 0];
+        for _ in 0..depth {
+            value = field(5, &entry("k", &value));
+        }
+        value
+    }
+
+    fn set(path: Vec<&str>, value: &[u8]) -> MockHost {
+        let host = MockHost::new();
+        host.set_property(path, Some(value));
+        host
+    }
+
+    fn reason(error: Error) -> &'static str {
+        match error {
+            Error::InvalidProperty { reason, .. } => reason,
+            error => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn structure() {
+        let mut number = vec![2 << 3 | 1];
+        number.extend(1.5f64.to_le_bytes());
+        let mut bytes = entry("number", &number);
+        bytes.extend(entry("bool", &[4 << 3, 1]));
+        bytes.extend(entry("string", &field(3, b"x")));
+        let mut list = field(1, &[1 << 3, 0]);
+        list.extend(field(1, &field(5, &[])));
+        bytes.extend(entry("list", &field(6, &list)));
+        bytes.extend(entry("struct", &nested(2)));
+        let _host = set(vec!["node", "metadata"], &bytes);
+
+        let mut inner = Struct::new();
+        inner.insert("k".to_string(), Value::Null);
+        let mut outer = Struct::new();
+        outer.insert("k".to_string(), Value::Struct(inner));
+        let mut expected = Struct::new();
+        expected.insert("number".to_string(), Value::Number(1.5));
+        expected.insert("bool".to_string(), Value::Bool(true));
+        expected.insert("string".to_string(), Value::String("x".to_string()));
+        let list = vec![Value::Null, Value::Struct(Struct::new())];
+        expected.insert("list".to_string(), Value::List(list));
+        expected.insert("struct".to_string(), Value::Struct(outer));
+        assert_eq!(node_metadata().unwrap(), Some(expected));
+        assert_eq!(route_metadata().unwrap(), None);
+    }
+
+    #[test]
+    fn malformed_structure() {
+        let malformed: [&[u8]; 4] = [
+            &[1 << 3 | 2, 5, 0],
+            &[1 << 3 | 3],
+            &[0x80],
+            &entry("k", &field(3, &[0xff])),
+        ];
+        for bytes in malformed {
+            let _host = set(vec!["node", "metadata"], bytes);
+            let error = node_metadata().unwrap_err();
+            assert_eq!(reason(error), "expected google.protobuf.Struct");
+        }
+    }
+
+    #[test]
+    fn deeply_nested_structure() {
+        let _host = set(vec!["node", "metadata"], &entry("k", &nested(100)));
+        assert!(node_metadata().unwrap().is_some());
+
+        let _host = set(vec!["node", "metadata"], &entry("k", &nested(101)));
+        let error = node_metadata().unwrap_err();
+        assert_eq!(reason(error), "google.protobuf.Struct nested too deeply");
+
+        let mut list = nested(0);
+        for _ in 0..101 {
+            list = field(6, &field(1, &list));
+        }
+        let _host = set(vec!["node", "metadata"], &entry("k", &list));
+        let error = node_metadata().unwrap_err();
+        assert_eq!(reason(error), "google.protobuf.Struct nested too deeply");
+    }
+
+    #[test]
+    fn integers() {
+        let _host = set(vec!["request", "size"], &42i64.to_le_bytes());
+        assert_eq!(request_size().unwrap(), Some(42));
+
+        let _host = set(vec!["request", "size"], &(-1i64).to_le_bytes());
+        assert_eq!(reason(request_size().unwrap_err()), "negative value");
+
+        let _host = set(vec!["response", "code"], &(1i64 << 32).to_le_bytes());
+        assert_eq!(reason(response_code().unwrap_err()), "value out of range");
+
+        let _host = set(vec!["request", "size"], &[42, 0, 0, 0]);
+        let error = request_size().unwrap_err();
+        assert_eq!(reason(error), "expected 64-bit integer");
+
+        let _host = MockHost::new();
+        assert_eq!(request_size().unwrap(), None);
+    }
+
+    #[test]
+    fn booleans() {
+        let _host = set(vec!["connection", "mtls"], &[1]);
+        assert_eq!(connection_mtls().unwrap(), Some(true));
+
+        let _host = set(vec!["connection", "mtls"], &[0]);
+        assert_eq!(connection_mtls().unwrap(), Some(false));
+
+        let _host = set(vec!["connection", "mtls"], &[1, 0]);
+        let error = connection_mtls().unwrap_err();
+        assert_eq!(reason(error), "expected boolean");
+    }
+
+    #[test]
+    fn timestamps_and_durations() {
+        let nanos = 1_600_000_000_123_456_789i64;
+        let _host = set(vec!["request", "time"], &nanos.to_le_bytes());
+        let expected = UNIX_EPOCH + Duration::from_nanos(nanos as u64);
+        assert_eq!(request_time().unwrap(), Some(expected));
+
+        let _host = set(vec!["request", "time"], &(-1_000_000_000i64).to_le_bytes());
+        let expected = UNIX_EPOCH - Duration::from_secs(1);
+        assert_eq!(request_time().unwrap(), Some(expected));
+
+        let _host = set(vec!["request", "duration"], &1_500_000i64.to_le_bytes());
+        let expected = Duration::from_micros(1500);
+        assert_eq!(request_duration().unwrap(), Some(expected));
+
+        let _host = set(vec!["request", "duration"], &(-1i64).to_le_bytes());
+        let error = request_duration().unwrap_err();
+        assert_eq!(reason(error), "negative duration");
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Typed channels on top of shared queues.
+//!
+//! Each value is encoded into a single queue item, so senders and receivers
+//! interoperate with plugins using the raw shared queue hostcalls as long as
+//! they agree on the codec.
+//!
+//! Receivers are registered with [`QueueHandlers`], which drains the queue
+//! from `RootContext::on_queue_ready` and passes the values to the handler in
+//! batches:
+//!
+//! ```no_run
+//! use proxy_wasm::codec::Text;
+//! use proxy_wasm::queue::*;
+//! use proxy_wasm::traits::*;
+//!
+//! struct Aggregator {
+//!     queues: QueueHandlers,
+//! }
+//!
+//! impl Aggregator {
+//!     fn new() -> Aggregator {
+//!         let mut queues = QueueHandlers::new();
+//!         let events = QueueReceiver::register("events", Text).unwrap();
+//!         queues.register(events, |events: Vec<String>| {
+//!             // Aggregate events.
+//!         });
+//!         Aggregator { queues }
+//!     }
+//! }
+//!
+//! impl Context for Aggregator {}
+//!
+//! impl RootContext for Aggregator {
+//!     fn on_queue_ready(&mut self, queue_id: u32) {
+//!         self.queues.on_queue_ready(queue_id).unwrap();
+//!     }
+//! }
+//! ```
+
+use crate::codec::Codec;
+use crate::hostcalls;
+use crate::types::*;
+use hashbrown::HashMap;
+use std::marker::PhantomData;
+
+pub struct QueueSender<T, C: Codec<T>> {
+    queue_id: u32,
+    codec: C,
+    value_type: PhantomData<fn(&T)>,
+}
+
+impl<T, C: Codec<T>> QueueSender<T, C> {
+    pub fn new(queue_id: u32, codec: C) -> QueueSender<T, C> {
+        QueueSender {
+            queue_id,
+            codec,
+            value_type: PhantomData,
+        }
+    }
+
+    /// Resolves the queue registered under `name` by the VM `vm_id`.
+    /// Returns `None` if it wasn't registered yet.
+    pub fn resolve(vm_id: &str, name: &str, codec: C) -> Result<Option<QueueSender<T, C>>, Error> {
+        Ok(hostcalls::try_resolve_shared_queue(vm_id, name)?
+            .map(|queue_id| QueueSender::new(queue_id, codec)))
+    }
+
+    pub fn queue_id(&self) -> u32 {
+        self.queue_id
+    }
+
+    pub fn send(&self, value: &T) -> Result<(), Error> {
+        let bytes = self.codec.encode(value)?;
+        hostcalls::try_enqueue_shared_queue(self.queue_id, Some(&bytes))
+    }
+
+    /// Encodes all values before enqueuing any of them, so that an encoding
+    /// error doesn't leave a partial batch in the queue.
+    pub fn send_all<'a, I>(&self, values: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: 'a,
+    {
+        let items = values
+            .into_iter()
+            .map(|value| self.codec.encode(value))
+            .collect::<Result<Vec<Bytes>, Error>>()?;
+        for item in items {
+            hostcalls::try_enqueue_shared_queue(self.queue_id, Some(&item))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct QueueReceiver<T, C: Codec<T>> {
+    queue_id: u32,
+    codec: C,
+    value_type: PhantomData<fn() -> T>,
+}
+
+impl<T, C: Codec<T>> QueueReceiver<T, C> {
+    /// Registers the queue `name` in the current VM.
+    pub fn register(name: &str, codec: C) -> Result<QueueReceiver<T, C>, Error> {
+        let queue_id = hostcalls::try_register_shared_queue(name)?;
+        Ok(QueueReceiver {
+            queue_id,
+            codec,
+            value_type: PhantomData,
+        })
+    }
+
+    pub fn queue_id(&self) -> u32 {
+        self.queue_id
+    }
+
+    /// Dequeues a single value. Returns `None` if the queue is empty.
+    pub fn recv(&self) -> Result<Option<T>, Error> {
+        match hostcalls::try_dequeue_shared_queue(self.queue_id)? {
+            Some(bytes) => self.codec.decode(&bytes).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Dequeues up to `max` items. Items that fail to decode are logged and
+    /// dropped, since they were already removed from the queue.
+    pub fn drain(&self, max: usize) -> Result<Vec<T>, Error> {
+        Ok(self.drain_counted(max)?.0)
+    }
+
+    // Same as `drain`, but also returns the number of items dequeued,
+    // including the ones that failed to decode.
+    fn drain_counted(&self, max: usize) -> Result<(Vec<T>, usize), Error> {
+        let mut values = Vec::new();
+        let mut dequeued = 0;
+        while dequeued < max {
+            let bytes = match hostcalls::try_dequeue_shared_queue(self.queue_id)? {
+                Some(bytes) => bytes,
+                None => break,
+            };
+            dequeued += 1;
+            match self.codec.decode(&bytes) {
+                Ok(value) => values.push(value),
+                Err(err) => {
+                    let message =
+                        format!("dropping item from shared queue {}: {}", self.queue_id, err);
+                    let _ = hostcalls::try_log(LogLevel::Warn, &message);
+                }
+            }
+        }
+        Ok((values, dequeued))
+    }
+}
+
+type Handler = Box<dyn FnMut() -> Result<(), Error>>;
+
+/// Per-queue handlers, dispatched from `RootContext::on_queue_ready`.
+#[derive(Default)]
+pub struct QueueHandlers {
+    handlers: HashMap<u32, Handler>,
+}
+
+impl QueueHandlers {
+    pub fn new() -> QueueHandlers {
+        QueueHandlers::default()
+    }
+
+    /// Registers `handler` for the queue of `receiver`. The handler is called
+    /// with all the values available in the queue when it becomes ready.
+    pub fn register<T, C, F>(&mut self, receiver: QueueReceiver<T, C>, handler: F)
+    where
+        T: 'static,
+        C: Codec<T> + 'static,
+        F: FnMut(Vec<T>) + 'static,
+    {
+        self.register_batched(receiver, usize::MAX, handler)
+    }
+
+    /// Same as [`register`](Self::register), but calls the handler with at
+    /// most `batch_size` values at a time.
+    pub fn register_batched<T, C, F>(
+        &mut self,
+        receiver: QueueReceiver<T, C>,
+        batch_size: usize,
+        mut handler: F,
+    ) where
+        T: 'static,
+        C: Codec<T> + 'static,
+        F: FnMut(Vec<T>) + 'static,
+    {
+        let batch_size = batch_size.max(1);
+        self.handlers.insert(
+            receiver.queue_id(),
+            Box::new(move || loop {
+                let (values, dequeued) = receiver.drain_counted(batch_size)?;
+                if !values.is_empty() {
+                    handler(values);
+                }
+                if dequeued < batch_size {
+                    return Ok(());
+                }
+            }),
+        );
+    }
+
+    pub fn unregister(&mut self, queue_id: u32) -> bool {
+        self.handlers.remove(&queue_id).is_some()
+    }
+
+    /// Drains the queue into its handler. Returns `false` if there is no
+    /// handler registered for `queue_id`.
+    pub fn on_queue_ready(&mut self, queue_id: u32) -> Result<bool, Error> {
+        match self.handlers.get_mut(&queue_id) {
+            Some(handler) => handler().map(|()| true),
+            None => Ok(false),
+        }
+    }
+}
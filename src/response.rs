@@ -0,0 +1,260 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local responses.
+//!
+//! [`LocalResponse`] builds a response sent by the plugin instead of the
+//! upstream, with `content-type` and `content-length` set from the body.
+//! Requests with a gRPC `content-type` get a gRPC response instead, with the
+//! status code mapped to a gRPC status.
+//!
+//! ```no_run
+//! use proxy_wasm::response::LocalResponse;
+//! use proxy_wasm::traits::*;
+//! use proxy_wasm::types::*;
+//!
+//! struct Filter;
+//!
+//! impl Context for Filter {}
+//!
+//! impl HttpContext for Filter {
+//!     fn on_http_request_headers(&mut self, _: usize, _: bool) -> Action {
+//!         if self.get_http_request_header("authorization").is_none() {
+//!             LocalResponse::new(401)
+//!                 .header("www-authenticate", "Bearer")
+//!                 .text("Missing credentials.\n")
+//!                 .details("missing_credentials")
+//!                 .send();
+//!             return Action::Pause;
+//!         }
+//!         Action::Continue
+//!     }
+//! }
+//! ```
+
+use crate::hostcalls;
+use crate::types::*;
+use std::fmt::Display;
+use std::time::Duration;
+
+/// Builder for a local response.
+#[derive(Clone, Debug)]
+pub struct LocalResponse {
+    status_code: u32,
+    headers: Vec<(String, Bytes)>,
+    body: Option<Bytes>,
+    content_type: Option<String>,
+    body_type: Option<&'static str>,
+    details: Option<String>,
+    grpc_status: Option<GrpcStatusCode>,
+    grpc_message: Option<String>,
+    body_message: Option<String>,
+    error: Option<Error>,
+}
+
+impl LocalResponse {
+    pub fn new(status_code: u32) -> LocalResponse {
+        LocalResponse {
+            status_code,
+            headers: Vec::new(),
+            body: None,
+            content_type: None,
+            body_type: None,
+            details: None,
+            grpc_status: None,
+            grpc_message: None,
+            body_message: None,
+            error: None,
+        }
+    }
+
+    pub fn status(mut self, status_code: u32) -> LocalResponse {
+        self.status_code = status_code;
+        self
+    }
+
+    /// Adds a header. Values are formatted with `Display`, so that numbers
+    /// and other typed values can be passed directly.
+    pub fn header<V: Display>(mut self, name: &str, value: V) -> LocalResponse {
+        let value = value.to_string().into_bytes();
+        self.headers.push((name.to_ascii_lowercase(), value));
+        self
+    }
+
+    pub fn header_bytes(mut self, name: &str, value: &[u8]) -> LocalResponse {
+        self.headers
+            .push((name.to_ascii_lowercase(), value.to_vec()));
+        self
+    }
+
+    pub fn location(self, location: &str) -> LocalResponse {
+        self.header("location", location)
+    }
+
+    pub fn cache_control(self, cache_control: &str) -> LocalResponse {
+        self.header("cache-control", cache_control)
+    }
+
+    /// Sets `retry-after`, in whole seconds.
+    pub fn retry_after(self, delay: Duration) -> LocalResponse {
+        self.header("retry-after", delay.as_secs())
+    }
+
+    /// Overrides the `content-type` set by the body helpers.
+    pub fn content_type(mut self, content_type: &str) -> LocalResponse {
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+
+    /// Sets the body, without a `content-type`.
+    pub fn body(mut self, body: &[u8]) -> LocalResponse {
+        self.body = Some(body.to_vec());
+        self.body_type = None;
+        self.body_message = None;
+        self
+    }
+
+    /// Sets a `text/plain` body. It's also used as the `grpc-message` of
+    /// gRPC responses.
+    pub fn text(mut self, text: &str) -> LocalResponse {
+        self.body = Some(text.as_bytes().to_vec());
+        self.body_message = Some(text.trim_end().to_string());
+        self.body_type = Some("text/plain; charset=utf-8");
+        self
+    }
+
+    pub fn html(mut self, html: &str) -> LocalResponse {
+        self.body = Some(html.as_bytes().to_vec());
+        self.body_type = Some("text/html; charset=utf-8");
+        self.body_message = None;
+        self
+    }
+
+    /// Sets an `application/json` body. Serialization errors are returned
+    /// by [`try_send`](Self::try_send).
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::Serialize + ?Sized>(mut self, value: &T) -> LocalResponse {
+        match serde_json::to_vec(value) {
+            Ok(body) => self.body = Some(body),
+            Err(err) => {
+                self.error = Some(Error::Codec {
+                    reason: err.to_string(),
+                })
+            }
+        }
+        self.body_type = Some("application/json");
+        self.body_message = None;
+        self
+    }
+
+    /// Sets the reason for the response, reported by Envoy as
+    /// `%RESPONSE_CODE_DETAILS%`. Envoy expects it to contain no whitespace.
+    pub fn details(mut self, details: &str) -> LocalResponse {
+        self.details = Some(details.to_string());
+        self
+    }
+
+    /// Overrides the gRPC status derived from the status code.
+    pub fn grpc_status(mut self, grpc_status: GrpcStatusCode) -> LocalResponse {
+        self.grpc_status = Some(grpc_status);
+        self
+    }
+
+    /// Overrides the `grpc-message` taken from a text body.
+    pub fn grpc_message(mut self, grpc_message: &str) -> LocalResponse {
+        self.grpc_message = Some(grpc_message.to_string());
+        self
+    }
+
+    /// Sends the response, as a gRPC response if the request has a gRPC
+    /// `content-type`. The stream should be paused afterwards.
+    pub fn send(&self) {
+        self.try_send().unwrap()
+    }
+
+    pub fn try_send(&self) -> Result<(), Error> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        let content_type =
+            hostcalls::try_get_map_value(MapType::HttpRequestHeaders, "content-type")?;
+        if content_type.map_or(false, |content_type| is_grpc(&content_type)) {
+            self.send_grpc()
+        } else {
+            self.send_http()
+        }
+    }
+
+    fn send_http(&self) -> Result<(), Error> {
+        let body = self.body.as_deref().unwrap_or_default();
+        let content_length = body.len().to_string();
+        let mut headers: Vec<(&str, &[u8])> = self
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_slice()))
+            .collect();
+        if !self.has_header("content-type") {
+            let content_type = self.content_type.as_deref().or(self.body_type);
+            if let Some(content_type) = content_type {
+                headers.push(("content-type", content_type.as_bytes()));
+            }
+        }
+        if !self.has_header("content-length") {
+            headers.push(("content-length", content_length.as_bytes()));
+        }
+        hostcalls::try_send_local_response(
+            self.status_code,
+            self.details.as_deref(),
+            headers,
+            self.body.as_deref(),
+            None,
+        )
+    }
+
+    fn send_grpc(&self) -> Result<(), Error> {
+        let grpc_status = self.grpc_status.unwrap_or_else(|| match self.status_code {
+            200..=299 => GrpcStatusCode::Ok,
+            status_code => GrpcStatusCode::from_http_status(status_code),
+        });
+        // The body of gRPC responses is the `grpc-message`, and the host
+        // sets `content-type`.
+        let metadata = self
+            .headers
+            .iter()
+            .filter(|(name, _)| name != "content-type" && name != "content-length")
+            .map(|(name, value)| (name.as_str(), value.as_slice()))
+            .collect();
+        hostcalls::try_send_local_response(
+            200,
+            self.details.as_deref(),
+            metadata,
+            self.grpc_message
+                .as_ref()
+                .or(self.body_message.as_ref())
+                .map(String::as_bytes),
+            Some(grpc_status),
+        )
+    }
+
+    fn has_header(&self, name: &str) -> bool {
+        self.headers.iter().any(|(key, _)| key == name)
+    }
+}
+
+fn is_grpc(content_type: &str) -> bool {
+    let content_type = content_type.trim().to_ascii_lowercase();
+    content_type == "application/grpc"
+        || content_type.starts_with("application/grpc+")
+        || content_type.starts_with("application/grpc;")
+}
@@ -0,0 +1,473 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Request routing.
+//!
+//! A [`Router`] matches the request headers against a list of [`Route`]s, in
+//! the order they were added, and calls the handler of the first one that
+//! matches. It's meant to be built once, e.g. in `on_configure`, and shared
+//! with each `HttpContext` through an `Rc`.
+//!
+//! ```no_run
+//! use proxy_wasm::router::*;
+//! use proxy_wasm::traits::*;
+//! use proxy_wasm::types::*;
+//! use std::rc::Rc;
+//!
+//! struct Filter {
+//!     router: Rc<Router<Filter>>,
+//! }
+//!
+//! fn router() -> Router<Filter> {
+//!     Router::new()
+//!         .add(Route::get().path("/users/{id}"), |filter: &mut Filter, params| {
+//!             let body = format!("user {}\n", params.get("id").unwrap());
+//!             filter.send_http_response(200, vec![], Some(body.as_bytes()));
+//!             Action::Pause
+//!         })
+//!         .add(Route::any().prefix("/admin/").header_present("x-admin"), |_, _| {
+//!             Action::Continue
+//!         })
+//! }
+//!
+//! impl Context for Filter {}
+//!
+//! impl HttpContext for Filter {
+//!     fn on_http_request_headers(&mut self, _: usize, _: bool) -> Action {
+//!         let router = Rc::clone(&self.router);
+//!         router.route(self)
+//!     }
+//! }
+//! ```
+
+use crate::hostcalls;
+use crate::types::*;
+
+type Handler<C> = Box<dyn Fn(&mut C, &Params) -> Action>;
+
+/// Dispatches requests to the handler of the first matching [`Route`].
+pub struct Router<C> {
+    routes: Vec<(Route, Handler<C>)>,
+    fallback: Option<Handler<C>>,
+}
+
+impl<C> Default for Router<C> {
+    fn default() -> Self {
+        Router {
+            routes: Vec::new(),
+            fallback: None,
+        }
+    }
+}
+
+impl<C> Router<C> {
+    pub fn new() -> Router<C> {
+        Router::default()
+    }
+
+    pub fn add<F>(mut self, route: Route, handler: F) -> Router<C>
+    where
+        F: Fn(&mut C, &Params) -> Action + 'static,
+    {
+        self.routes.push((route, Box::new(handler)));
+        self
+    }
+
+    /// Sets the handler for requests that don't match any route. Without it,
+    /// such requests continue unchanged.
+    pub fn fallback<F>(mut self, handler: F) -> Router<C>
+    where
+        F: Fn(&mut C, &Params) -> Action + 'static,
+    {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Matches the current request and calls the handler of the first
+    /// matching route. Call it from `on_http_request_headers`, and return the
+    /// action it returns.
+    pub fn route(&self, context: &mut C) -> Action {
+        self.try_route(context).unwrap()
+    }
+
+    pub fn try_route(&self, context: &mut C) -> Result<Action, Error> {
+        let mut request = Request::default();
+        for (route, handler) in &self.routes {
+            if let Some(params) = route.matches(&mut request)? {
+                return Ok(handler(context, &params));
+            }
+        }
+        match &self.fallback {
+            Some(handler) => Ok(handler(context, &Params::default())),
+            None => Ok(Action::Continue),
+        }
+    }
+}
+
+/// Parameters extracted from the path by a template or a regex.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Conditions on the request headers. A route matches if all of them hold.
+#[derive(Default)]
+pub struct Route {
+    method: Option<String>,
+    path: Option<PathMatcher>,
+    host: Option<String>,
+    headers: Vec<(String, HeaderMatcher)>,
+}
+
+enum PathMatcher {
+    Exact(String),
+    Prefix(String),
+    Template(Vec<Segment>),
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+}
+
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest(String),
+}
+
+enum HeaderMatcher {
+    Present,
+    Exact(String),
+    Predicate(Box<dyn Fn(&str) -> bool>),
+}
+
+impl Route {
+    /// Matches any request.
+    pub fn any() -> Route {
+        Route::default()
+    }
+
+    pub fn get() -> Route {
+        Route::any().method("GET")
+    }
+
+    pub fn post() -> Route {
+        Route::any().method("POST")
+    }
+
+    pub fn put() -> Route {
+        Route::any().method("PUT")
+    }
+
+    pub fn delete() -> Route {
+        Route::any().method("DELETE")
+    }
+
+    pub fn method(mut self, method: &str) -> Route {
+        self.method = Some(method.to_ascii_uppercase());
+        self
+    }
+
+    /// Matches the path, without the query string, exactly.
+    pub fn exact(mut self, path: &str) -> Route {
+        self.path = Some(PathMatcher::Exact(path.to_string()));
+        self
+    }
+
+    /// Matches paths that start with `prefix`.
+    pub fn prefix(mut self, prefix: &str) -> Route {
+        self.path = Some(PathMatcher::Prefix(prefix.to_string()));
+        self
+    }
+
+    /// Matches the path, without the query string, against a template, where
+    /// `{name}` captures a single segment, and a final `{*name}` captures the
+    /// rest of the path. Captured values aren't percent-decoded.
+    pub fn path(mut self, template: &str) -> Route {
+        let segments = template
+            .trim_start_matches('/')
+            .split('/')
+            .map(|segment| {
+                match segment
+                    .strip_prefix('{')
+                    .and_then(|name| name.strip_suffix('}'))
+                {
+                    Some(name) => match name.strip_prefix('*') {
+                        Some(name) => Segment::Rest(name.to_string()),
+                        None => Segment::Param(name.to_string()),
+                    },
+                    None => Segment::Literal(segment.to_string()),
+                }
+            })
+            .collect();
+        self.path = Some(PathMatcher::Template(segments));
+        self
+    }
+
+    /// Matches the path, without the query string, against `regex`. Named
+    /// capture groups are extracted as parameters. The regex isn't anchored,
+    /// so use `^` and `$` to match the whole path.
+    #[cfg(feature = "regex")]
+    pub fn regex(mut self, regex: regex::Regex) -> Route {
+        self.path = Some(PathMatcher::Regex(regex));
+        self
+    }
+
+    /// Matches `:authority`, ignoring the port and case. A leading `*.`
+    /// matches any subdomain.
+    pub fn host(mut self, host: &str) -> Route {
+        self.host = Some(host.to_ascii_lowercase());
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Route {
+        let matcher = HeaderMatcher::Exact(value.to_string());
+        self.headers.push((name.to_ascii_lowercase(), matcher));
+        self
+    }
+
+    pub fn header_present(mut self, name: &str) -> Route {
+        self.headers
+            .push((name.to_ascii_lowercase(), HeaderMatcher::Present));
+        self
+    }
+
+    /// Matches if the header is present and `predicate` returns `true` for
+    /// its value.
+    pub fn header_matches<F>(mut self, name: &str, predicate: F) -> Route
+    where
+        F: Fn(&str) -> bool + 'static,
+    {
+        let matcher = HeaderMatcher::Predicate(Box::new(predicate));
+        self.headers.push((name.to_ascii_lowercase(), matcher));
+        self
+    }
+
+    fn matches(&self, request: &mut Request) -> Result<Option<Params>, Error> {
+        if let Some(method) = &self.method {
+            if request.header(":method")?.as_deref() != Some(method.as_str()) {
+                return Ok(None);
+            }
+        }
+        if let Some(host) = &self.host {
+            match request.header(":authority")? {
+                Some(authority) if host_matches(host, &authority) => {}
+                _ => return Ok(None),
+            }
+        }
+        for (name, matcher) in &self.headers {
+            let value = request.header(name)?;
+            let matched = match (matcher, value) {
+                (_, None) => false,
+                (HeaderMatcher::Present, Some(_)) => true,
+                (HeaderMatcher::Exact(expected), Some(value)) => *expected == value,
+                (HeaderMatcher::Predicate(predicate), Some(value)) => predicate(&value),
+            };
+            if !matched {
+                return Ok(None);
+            }
+        }
+        let matcher = match &self.path {
+            Some(matcher) => matcher,
+            None => return Ok(Some(Params::default())),
+        };
+        let path = request.header(":path")?.unwrap_or_default();
+        let path = path.split('?').next().unwrap_or_default();
+        Ok(match matcher {
+            PathMatcher::Exact(expected) => Some(Params::default()).filter(|_| expected == path),
+            PathMatcher::Prefix(prefix) => {
+                Some(Params::default()).filter(|_| path.starts_with(prefix.as_str()))
+            }
+            PathMatcher::Template(segments) => match_template(segments, path),
+            #[cfg(feature = "regex")]
+            PathMatcher::Regex(regex) => regex.captures(path).map(|captures| {
+                let params = regex
+                    .capture_names()
+                    .flatten()
+                    .filter_map(|name| {
+                        let value = captures.name(name)?.as_str();
+                        Some((name.to_string(), value.to_string()))
+                    })
+                    .collect();
+                Params(params)
+            }),
+        })
+    }
+}
+
+fn match_template(segments: &[Segment], path: &str) -> Option<Params> {
+    let mut params = Vec::new();
+    let mut rest = path.strip_prefix('/')?;
+    for (index, segment) in segments.iter().enumerate() {
+        let last = index + 1 == segments.len();
+        if let Segment::Rest(name) = segment {
+            params.push((name.clone(), rest.to_string()));
+            return Some(Params(params));
+        }
+        let (value, remaining) = match rest.find('/') {
+            Some(end) if !last => (&rest[..end], &rest[end + 1..]),
+            None if last => (rest, ""),
+            _ => return None,
+        };
+        match segment {
+            Segment::Literal(literal) if literal == value => {}
+            Segment::Param(name) if !value.is_empty() => {
+                params.push((name.clone(), value.to_string()));
+            }
+            _ => return None,
+        }
+        rest = remaining;
+    }
+    Some(Params(params))
+}
+
+fn host_matches(pattern: &str, authority: &str) -> bool {
+    // Strips the port, but not from an IPv6 address without one.
+    let host = match authority.rfind(':') {
+        Some(index) if !authority[index..].contains(']') => &authority[..index],
+        _ => authority,
+    };
+    let host = host.to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.strip_suffix(domain).map_or(false, |subdomain| {
+            subdomain.len() > 1 && subdomain.ends_with('.')
+        }),
+        None => host == pattern,
+    }
+}
+
+// The request headers, read from the host at most once per name.
+#[derive(Default)]
+struct Request {
+    headers: Vec<(String, Option<String>)>,
+}
+
+impl Request {
+    fn header(&mut self, name: &str) -> Result<Option<String>, Error> {
+        if let Some((_, value)) = self.headers.iter().find(|(key, _)| key == name) {
+            return Ok(value.clone());
+        }
+        let value = hostcalls::try_get_map_value(MapType::HttpRequestHeaders, name)?;
+        self.headers.push((name.to_string(), value.clone()));
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn match_path(template: &str, path: &str) -> Option<Vec<(String, String)>> {
+        match Route::any().path(template).path {
+            Some(PathMatcher::Template(segments)) => {
+                match_template(&segments, path).map(|params| params.0)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn params(params: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        let params = params
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Some(params)
+    }
+
+    #[test]
+    fn template() {
+        let template = "/users/{id}/posts/{post}";
+        assert_eq!(
+            match_path(template, "/users/1/posts/2"),
+            params(&[("id", "1"), ("post", "2")])
+        );
+        assert_eq!(match_path(template, "/users/1/posts"), None);
+        assert_eq!(match_path(template, "/users/1/posts/2/3"), None);
+        assert_eq!(match_path(template, "/users/1/comments/2"), None);
+        assert_eq!(match_path(template, "users/1/posts/2"), None);
+        assert_eq!(match_path("/", "/"), params(&[]));
+        assert_eq!(match_path("/", ""), None);
+    }
+
+    #[test]
+    fn template_trailing_slash() {
+        assert_eq!(match_path("/users/{id}", "/users/1/"), None);
+        assert_eq!(match_path("/users/", "/users/"), params(&[]));
+        assert_eq!(match_path("/users/", "/users"), None);
+        assert_eq!(match_path("/users", "/users/"), None);
+    }
+
+    #[test]
+    fn template_empty_segments() {
+        assert_eq!(match_path("/users/{id}", "/users/"), None);
+        assert_eq!(match_path("/users/{id}/posts", "/users//posts"), None);
+        assert_eq!(match_path("/a//b", "/a//b"), params(&[]));
+        assert_eq!(match_path("/a/b", "/a//b"), None);
+    }
+
+    #[test]
+    fn template_rest() {
+        let template = "/files/{bucket}/{*rest}";
+        assert_eq!(
+            match_path(template, "/files/b/dir/file.txt"),
+            params(&[("bucket", "b"), ("rest", "dir/file.txt")])
+        );
+        assert_eq!(
+            match_path(template, "/files/b/dir/"),
+            params(&[("bucket", "b"), ("rest", "dir/")])
+        );
+        assert_eq!(
+            match_path(template, "/files/b/"),
+            params(&[("bucket", "b"), ("rest", "")])
+        );
+        assert_eq!(match_path(template, "/files/b"), None);
+    }
+
+    #[test]
+    fn host() {
+        assert!(host_matches("example.com", "example.com"));
+        assert!(host_matches("example.com", "Example.COM:8080"));
+        assert!(!host_matches("example.com", "a.example.com"));
+
+        assert!(host_matches("*.example.com", "a.example.com"));
+        assert!(host_matches("*.example.com", "a.example.com:8080"));
+        assert!(host_matches("*.example.com", "a.b.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(!host_matches("*.example.com", ".example.com"));
+        assert!(!host_matches("*.example.com", "aexample.com"));
+    }
+
+    #[test]
+    fn host_ipv6() {
+        assert!(host_matches("[::1]", "[::1]"));
+        assert!(host_matches("[::1]", "[::1]:8080"));
+        assert!(!host_matches("[::1]", "[::2]:8080"));
+        assert!(host_matches("[2001:db8::a]", "[2001:DB8::A]:443"));
+    }
+}
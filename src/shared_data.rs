@@ -0,0 +1,142 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Typed key/value store on top of shared data.
+//!
+//! Keys are prefixed with a namespace (by default, the plugin name), so that
+//! plugins sharing a VM don't overwrite each other's entries. Updates use the
+//! CAS token returned by the host and are retried on `Status::CasMismatch`,
+//! up to a bounded number of attempts.
+//!
+//! ```no_run
+//! use proxy_wasm::codec::Text;
+//! use proxy_wasm::shared_data::SharedStore;
+//!
+//! let store = SharedStore::new("ratelimit", Text);
+//! let count = store
+//!     .update("requests", |count: Option<String>| {
+//!         let count: u64 = count.and_then(|c| c.parse().ok()).unwrap_or(0);
+//!         (count + 1).to_string()
+//!     })
+//!     .unwrap();
+//! ```
+
+use crate::codec::Codec;
+#[cfg(feature = "json")]
+use crate::codec::Json;
+use crate::hostcalls;
+use crate::properties;
+use crate::types::*;
+use std::marker::PhantomData;
+
+const DEFAULT_MAX_ATTEMPTS: usize = 8;
+
+// CAS token used to create missing keys. Hosts ignore the token when the key
+// doesn't exist, and compare it with the token of the existing value
+// otherwise, so a token that the host is very unlikely to ever hand out makes
+// the write fail with `Status::CasMismatch` if the key was created
+// concurrently (a CAS of 0 would overwrite it instead).
+const CREATE_CAS: u32 = u32::MAX;
+
+pub struct SharedStore<T, C: Codec<T>> {
+    namespace: String,
+    codec: C,
+    max_attempts: usize,
+    value_type: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> SharedStore<T, Json> {
+    /// Creates a store of JSON-encoded values.
+    pub fn json(namespace: &str) -> SharedStore<T, Json> {
+        SharedStore::new(namespace, Json)
+    }
+}
+
+impl<T, C: Codec<T>> SharedStore<T, C> {
+    pub fn new(namespace: &str, codec: C) -> SharedStore<T, C> {
+        SharedStore {
+            namespace: namespace.to_string(),
+            codec,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            value_type: PhantomData,
+        }
+    }
+
+    /// Creates a store namespaced by the name of the current plugin.
+    pub fn for_plugin(codec: C) -> Result<SharedStore<T, C>, Error> {
+        let namespace = properties::plugin_name()?.unwrap_or_default();
+        Ok(SharedStore::new(&namespace, codec))
+    }
+
+    /// Sets the number of attempts made by [`update`](Self::update) before
+    /// giving up on CAS mismatches.
+    pub fn with_max_attempts(mut self, max_attempts: usize) -> SharedStore<T, C> {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}/{}", self.namespace, key)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<T>, Error> {
+        Ok(self.get_with_cas(&self.key(key))?.0)
+    }
+
+    /// Stores `value`, overwriting any concurrent updates.
+    pub fn set(&self, key: &str, value: &T) -> Result<(), Error> {
+        let bytes = self.codec.encode(value)?;
+        hostcalls::try_set_shared_data(&self.key(key), Some(&bytes), None)
+    }
+
+    /// Replaces the value with the one returned by `f`, which is called with
+    /// the current value. If the value was modified concurrently, `f` is
+    /// called again with the new value, until the update succeeds or the
+    /// attempts are exhausted, in which case the `Status::CasMismatch` error
+    /// is returned.
+    pub fn update<F>(&self, key: &str, mut f: F) -> Result<T, Error>
+    where
+        F: FnMut(Option<T>) -> T,
+    {
+        let key = self.key(key);
+        let mut attempt = 1;
+        loop {
+            let (value, cas) = self.get_with_cas(&key)?;
+            let value = f(value);
+            let bytes = self.codec.encode(&value)?;
+            let cas = cas.unwrap_or(CREATE_CAS);
+            match hostcalls::try_set_shared_data(&key, Some(&bytes), Some(cas)) {
+                Ok(()) => return Ok(value),
+                Err(err)
+                    if err.status() == Some(Status::CasMismatch) && attempt < self.max_attempts =>
+                {
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn get_with_cas(&self, key: &str) -> Result<(Option<T>, Option<u32>), Error> {
+        match hostcalls::try_get_shared_data(key)? {
+            (Some(bytes), cas) => Ok((Some(self.codec.decode(&bytes)?), cas)),
+            (None, cas) => Ok((None, cas)),
+        }
+    }
+}
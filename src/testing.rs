@@ -0,0 +1,1698 @@
+// Copyright 2020 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-process host for unit-testing plugins on the native target.
+//!
+//! Enabling the `testing` feature links a native implementation of every
+//! Proxy-Wasm hostcall used by this crate, backed by thread-local state.
+//! [`MockHost`] drives the real dispatcher through the same `proxy_on_*`
+//! entry points that the proxy would call, so that tests can assert on the
+//! resulting headers, local responses and dispatched calls.
+
+use crate::dispatcher;
+use crate::header_case;
+use crate::hostcalls::utils;
+use crate::types::*;
+use hashbrown::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+thread_local! {
+static HOST: RefCell<State> = RefCell::new(State::new());
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalResponse {
+    pub status_code: u32,
+    pub status_code_details: Option<String>,
+    pub headers: Vec<(String, Bytes)>,
+    pub body: Option<Bytes>,
+    pub grpc_status: Option<i32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpCall {
+    pub token_id: u32,
+    pub context_id: u32,
+    pub upstream: String,
+    pub headers: Vec<(String, Bytes)>,
+    pub body: Option<Bytes>,
+    pub trailers: Vec<(String, Bytes)>,
+    pub timeout: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrpcCall {
+    pub token_id: u32,
+    pub context_id: u32,
+    pub upstream: String,
+    pub service_name: String,
+    pub method_name: String,
+    pub initial_metadata: Vec<(String, Bytes)>,
+    pub message: Option<Bytes>,
+    pub timeout: Duration,
+    pub cancelled: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrpcStream {
+    pub token_id: u32,
+    pub context_id: u32,
+    pub upstream: String,
+    pub service_name: String,
+    pub method_name: String,
+    pub initial_metadata: Vec<(String, Bytes)>,
+    pub messages: Vec<Bytes>,
+    pub end_stream: bool,
+    pub closed: bool,
+    pub cancelled: bool,
+}
+
+struct Queue {
+    vm_id: String,
+    name: String,
+    items: VecDeque<Bytes>,
+}
+
+struct Metric {
+    metric_type: MetricType,
+    name: String,
+    value: u64,
+}
+
+type ForeignFunction = Rc<dyn Fn(&[u8]) -> Option<Bytes>>;
+
+// Parts of the state are only used by the native ABI.
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+struct State {
+    contexts: Vec<u32>,
+    current_context: u32,
+    log_level: LogLevel,
+    logs: Vec<(LogLevel, String)>,
+    current_time: SystemTime,
+    tick_periods: HashMap<u32, Duration>,
+    buffers: HashMap<(u32, BufferType), Bytes>,
+    maps: HashMap<(u32, MapType), Vec<(String, Bytes)>>,
+    properties: HashMap<Bytes, Bytes>,
+    shared_data: HashMap<String, (Bytes, u32)>,
+    next_cas: u32,
+    queues: Vec<Queue>,
+    metrics: Vec<Metric>,
+    continued_streams: HashMap<u32, Vec<StreamType>>,
+    closed_streams: HashMap<u32, Vec<StreamType>>,
+    local_responses: HashMap<u32, LocalResponse>,
+    next_token: u32,
+    http_calls: Vec<HttpCall>,
+    grpc_calls: Vec<GrpcCall>,
+    grpc_streams: Vec<GrpcStream>,
+    grpc_status: (u32, Option<Bytes>),
+    foreign_functions: HashMap<String, ForeignFunction>,
+    done: Vec<u32>,
+    failures: HashMap<&'static str, Status>,
+}
+
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+impl State {
+    fn new() -> State {
+        State {
+            contexts: Vec::new(),
+            current_context: 0,
+            log_level: LogLevel::Trace,
+            logs: Vec::new(),
+            current_time: UNIX_EPOCH,
+            tick_periods: HashMap::new(),
+            buffers: HashMap::new(),
+            maps: HashMap::new(),
+            properties: HashMap::new(),
+            shared_data: HashMap::new(),
+            next_cas: 1,
+            queues: Vec::new(),
+            metrics: Vec::new(),
+            continued_streams: HashMap::new(),
+            closed_streams: HashMap::new(),
+            local_responses: HashMap::new(),
+            next_token: 1,
+            http_calls: Vec::new(),
+            grpc_calls: Vec::new(),
+            grpc_streams: Vec::new(),
+            grpc_status: (0, None),
+            foreign_functions: HashMap::new(),
+            done: Vec::new(),
+            failures: HashMap::new(),
+        }
+    }
+
+    // Callout responses are not owned by any stream, so they are shared
+    // between all contexts, just like in the proxy.
+    fn map_key(&self, map_type: MapType) -> (u32, MapType) {
+        match map_type {
+            MapType::GrpcReceiveInitialMetadata
+            | MapType::GrpcReceiveTrailingMetadata
+            | MapType::HttpCallResponseHeaders
+            | MapType::HttpCallResponseTrailers => (0, map_type),
+            _ => (self.current_context, map_type),
+        }
+    }
+
+    fn buffer_key(&self, buffer_type: BufferType) -> (u32, BufferType) {
+        match buffer_type {
+            BufferType::HttpCallResponseBody | BufferType::GrpcReceiveBuffer => (0, buffer_type),
+            _ => (self.current_context, buffer_type),
+        }
+    }
+
+    fn next_token(&mut self) -> u32 {
+        let token_id = self.next_token;
+        self.next_token += 1;
+        token_id
+    }
+}
+
+fn with_state<T>(f: impl FnOnce(&mut State) -> T) -> T {
+    HOST.with(|host| f(&mut host.borrow_mut()))
+}
+
+fn to_map(map: Vec<(&str, &str)>) -> Vec<(String, Bytes)> {
+    map.into_iter()
+        .map(|(name, value)| (name.to_lowercase(), value.as_bytes().to_vec()))
+        .collect()
+}
+
+fn to_map_bytes(map: Vec<(&str, &[u8])>) -> Vec<(String, Bytes)> {
+    map.into_iter()
+        .map(|(name, value)| (name.to_lowercase(), value.to_vec()))
+        .collect()
+}
+
+pub struct MockHost {
+    next_context_id: Cell<u32>,
+}
+
+impl MockHost {
+    // Resets the host and the dispatcher of the current thread, so it must be
+    // called before registering root, stream or HTTP context constructors.
+    pub fn new() -> MockHost {
+        HOST.with(|host| *host.borrow_mut() = State::new());
+        dispatcher::reset();
+        MockHost {
+            next_context_id: Cell::new(1),
+        }
+    }
+
+    // Makes every subsequent call to the given hostcall (e.g. "proxy_set_buffer_bytes")
+    // fail with the given status, without touching the host state.
+    pub fn fail_hostcall(&self, name: &'static str, status: Status) {
+        with_state(|state| state.failures.insert(name, status));
+    }
+
+    pub fn clear_hostcall_failure(&self, name: &'static str) {
+        with_state(|state| state.failures.remove(name));
+    }
+
+    pub fn logs(&self) -> Vec<(LogLevel, String)> {
+        with_state(|state| state.logs.clone())
+    }
+
+    pub fn set_log_level(&self, level: LogLevel) {
+        with_state(|state| state.log_level = level)
+    }
+
+    pub fn set_current_time(&self, time: SystemTime) {
+        with_state(|state| state.current_time = time)
+    }
+
+    pub fn advance_time(&self, duration: Duration) {
+        with_state(|state| state.current_time += duration)
+    }
+
+    pub fn tick_period(&self, root_context_id: u32) -> Option<Duration> {
+        with_state(|state| state.tick_periods.get(&root_context_id).cloned())
+    }
+
+    pub fn buffer(&self, context_id: u32, buffer_type: BufferType) -> Option<Bytes> {
+        with_state(|state| state.buffers.get(&(context_id, buffer_type)).cloned())
+    }
+
+    pub fn set_buffer(&self, context_id: u32, buffer_type: BufferType, value: Option<&[u8]>) {
+        with_state(|state| match value {
+            Some(value) => {
+                state
+                    .buffers
+                    .insert((context_id, buffer_type), value.to_vec());
+            }
+            None => {
+                state.buffers.remove(&(context_id, buffer_type));
+            }
+        })
+    }
+
+    pub fn map(&self, context_id: u32, map_type: MapType) -> Vec<(String, String)> {
+        self.map_bytes(context_id, map_type)
+            .into_iter()
+            .map(|(name, value)| (name, String::from_utf8_lossy(&value).into_owned()))
+            .collect()
+    }
+
+    pub fn map_bytes(&self, context_id: u32, map_type: MapType) -> Vec<(String, Bytes)> {
+        with_state(|state| {
+            state
+                .maps
+                .get(&(context_id, map_type))
+                .cloned()
+                .unwrap_or_default()
+        })
+    }
+
+    /// Returns the map with header names in the case recorded by
+    /// [`header_case`], i.e. as a peer applying the
+    /// hints would send them.
+    ///
+    /// ```
+    /// use proxy_wasm::testing::MockHost;
+    /// use proxy_wasm::traits::*;
+    /// use proxy_wasm::types::*;
+    ///
+    /// struct Cors;
+    ///
+    /// impl Context for Cors {}
+    ///
+    /// impl HttpContext for Cors {
+    ///     fn on_http_response_headers(&mut self, _: usize, _: bool) -> Action {
+    ///         self.set_http_response_header_with_case("Access-Control-Allow-Origin", Some("*"));
+    ///         Action::Continue
+    ///     }
+    /// }
+    ///
+    /// struct Root;
+    ///
+    /// impl Context for Root {}
+    ///
+    /// impl RootContext for Root {
+    ///     fn get_type(&self) -> Option<ContextType> {
+    ///         Some(ContextType::HttpContext)
+    ///     }
+    ///
+    ///     fn create_http_context(&self, _: u32) -> Option<Box<dyn HttpContext>> {
+    ///         Some(Box::new(Cors))
+    ///     }
+    /// }
+    ///
+    /// let host = MockHost::new();
+    /// proxy_wasm::set_root_context(|_| Box::new(Root));
+    /// let root = host.create_root_context();
+    /// let http = host.create_http_context(root);
+    /// host.on_http_response_headers(http, vec![(":status", "200")], false);
+    ///
+    /// let headers = host.map(http, MapType::HttpResponseHeaders);
+    /// assert!(headers.contains(&("access-control-allow-origin".to_string(), "*".to_string())));
+    /// assert_eq!(
+    ///     host.map_with_case(http, MapType::HttpResponseHeaders),
+    ///     vec![
+    ///         (":status".to_string(), "200".to_string()),
+    ///         ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+    ///     ]
+    /// );
+    /// ```
+    pub fn map_with_case(&self, context_id: u32, map_type: MapType) -> Vec<(String, String)> {
+        header_case::restore_case(self.map(context_id, map_type))
+    }
+
+    pub fn map_value(&self, context_id: u32, map_type: MapType, name: &str) -> Option<String> {
+        self.map_bytes(context_id, map_type)
+            .into_iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| String::from_utf8_lossy(&value).into_owned())
+    }
+
+    pub fn set_map(&self, context_id: u32, map_type: MapType, map: Vec<(&str, &str)>) {
+        with_state(|state| state.maps.insert((context_id, map_type), to_map(map)));
+    }
+
+    pub fn set_map_bytes(&self, context_id: u32, map_type: MapType, map: Vec<(&str, &[u8])>) {
+        with_state(|state| state.maps.insert((context_id, map_type), to_map_bytes(map)));
+    }
+
+    pub fn property(&self, path: Vec<&str>) -> Option<Bytes> {
+        let path = utils::serialize_property_path(&path);
+        with_state(|state| state.properties.get(&path).cloned())
+    }
+
+    pub fn set_property(&self, path: Vec<&str>, value: Option<&[u8]>) {
+        let path = utils::serialize_property_path(&path);
+        with_state(|state| match value {
+            Some(value) => {
+                state.properties.insert(path, value.to_vec());
+            }
+            None => {
+                state.properties.remove(&path);
+            }
+        })
+    }
+
+    pub fn shared_data(&self, key: &str) -> Option<Bytes> {
+        with_state(|state| state.shared_data.get(key).map(|(value, _)| value.clone()))
+    }
+
+    pub fn set_shared_data(&self, key: &str, value: &[u8]) {
+        with_state(|state| {
+            let cas = state.next_cas;
+            state.next_cas += 1;
+            state
+                .shared_data
+                .insert(key.to_string(), (value.to_vec(), cas));
+        })
+    }
+
+    pub fn queue_id(&self, vm_id: &str, name: &str) -> Option<u32> {
+        with_state(|state| {
+            state
+                .queues
+                .iter()
+                .position(|queue| queue.vm_id == vm_id && queue.name == name)
+                .map(|index| index as u32 + 1)
+        })
+    }
+
+    pub fn queue_items(&self, queue_id: u32) -> Vec<Bytes> {
+        with_state(|state| {
+            (queue_id as usize)
+                .checked_sub(1)
+                .and_then(|index| state.queues.get(index))
+                .map(|queue| queue.items.iter().cloned().collect())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Enqueues `value` in the shared queue `queue_id`, without notifying the
+    /// VM. Panics if there is no such queue.
+    pub fn enqueue(&self, queue_id: u32, value: &[u8]) {
+        with_state(|state| {
+            let queue = (queue_id as usize)
+                .checked_sub(1)
+                .and_then(|index| state.queues.get_mut(index));
+            match queue {
+                Some(queue) => queue.items.push_back(value.to_vec()),
+                None => panic!("no shared queue with id {}", queue_id),
+            }
+        })
+    }
+
+    pub fn metric(&self, name: &str) -> Option<(MetricType, u64)> {
+        with_state(|state| {
+            state
+                .metrics
+                .iter()
+                .find(|metric| metric.name == name)
+                .map(|metric| (metric.metric_type, metric.value))
+        })
+    }
+
+    pub fn continued_streams(&self, context_id: u32) -> Vec<StreamType> {
+        with_state(|state| {
+            state
+                .continued_streams
+                .get(&context_id)
+                .cloned()
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn closed_streams(&self, context_id: u32) -> Vec<StreamType> {
+        with_state(|state| {
+            state
+                .closed_streams
+                .get(&context_id)
+                .cloned()
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn local_response(&self, context_id: u32) -> Option<LocalResponse> {
+        with_state(|state| state.local_responses.get(&context_id).cloned())
+    }
+
+    pub fn http_calls(&self) -> Vec<HttpCall> {
+        with_state(|state| state.http_calls.clone())
+    }
+
+    pub fn grpc_calls(&self) -> Vec<GrpcCall> {
+        with_state(|state| state.grpc_calls.clone())
+    }
+
+    pub fn grpc_streams(&self) -> Vec<GrpcStream> {
+        with_state(|state| state.grpc_streams.clone())
+    }
+
+    pub fn register_foreign_function(
+        &self,
+        name: &str,
+        function: impl Fn(&[u8]) -> Option<Bytes> + 'static,
+    ) {
+        with_state(|state| {
+            state
+                .foreign_functions
+                .insert(name.to_string(), Rc::new(function))
+        });
+    }
+
+    pub fn done_contexts(&self) -> Vec<u32> {
+        with_state(|state| state.done.clone())
+    }
+
+    fn enter(&self, context_id: u32) {
+        with_state(|state| state.current_context = context_id);
+    }
+
+    fn create_context(&self, root_context_id: u32) -> u32 {
+        let context_id = self.next_context_id.get();
+        self.next_context_id.set(context_id + 1);
+        with_state(|state| state.contexts.push(context_id));
+        self.enter(context_id);
+        dispatcher::proxy_on_context_create(context_id, root_context_id);
+        context_id
+    }
+
+    pub fn create_root_context(&self) -> u32 {
+        self.create_context(0)
+    }
+
+    pub fn create_http_context(&self, root_context_id: u32) -> u32 {
+        self.create_context(root_context_id)
+    }
+
+    pub fn create_stream_context(&self, root_context_id: u32) -> u32 {
+        self.create_context(root_context_id)
+    }
+
+    pub fn on_vm_start(&self, root_context_id: u32, vm_configuration: Option<&[u8]>) -> bool {
+        self.set_buffer(
+            root_context_id,
+            BufferType::VmConfiguration,
+            vm_configuration,
+        );
+        self.enter(root_context_id);
+        dispatcher::proxy_on_vm_start(root_context_id, vm_configuration.map_or(0, |c| c.len()))
+    }
+
+    pub fn on_configure(&self, root_context_id: u32, plugin_configuration: Option<&[u8]>) -> bool {
+        self.set_buffer(
+            root_context_id,
+            BufferType::PluginConfiguration,
+            plugin_configuration,
+        );
+        self.enter(root_context_id);
+        dispatcher::proxy_on_configure(root_context_id, plugin_configuration.map_or(0, |c| c.len()))
+    }
+
+    pub fn on_tick(&self, root_context_id: u32) {
+        self.enter(root_context_id);
+        dispatcher::proxy_on_tick(root_context_id)
+    }
+
+    pub fn on_queue_ready(&self, root_context_id: u32, queue_id: u32) {
+        self.enter(root_context_id);
+        dispatcher::proxy_on_queue_ready(root_context_id, queue_id)
+    }
+
+    /// Calls a function exported by the plugin, with `data` available in
+    /// `BufferType::CallData`.
+    pub fn on_foreign_function(&self, root_context_id: u32, function_id: u32, data: &[u8]) {
+        self.set_buffer(root_context_id, BufferType::CallData, Some(data));
+        self.enter(root_context_id);
+        dispatcher::proxy_on_foreign_function(root_context_id, function_id, data.len())
+    }
+
+    pub fn on_new_connection(&self, context_id: u32) -> Action {
+        self.enter(context_id);
+        dispatcher::proxy_on_new_connection(context_id)
+    }
+
+    pub fn on_downstream_data(&self, context_id: u32, data: &[u8], end_of_stream: bool) -> Action {
+        self.set_buffer(context_id, BufferType::DownstreamData, Some(data));
+        self.enter(context_id);
+        dispatcher::proxy_on_downstream_data(context_id, data.len(), end_of_stream)
+    }
+
+    pub fn on_downstream_close(&self, context_id: u32, peer_type: PeerType) {
+        self.enter(context_id);
+        dispatcher::proxy_on_downstream_connection_close(context_id, peer_type)
+    }
+
+    pub fn on_upstream_data(&self, context_id: u32, data: &[u8], end_of_stream: bool) -> Action {
+        self.set_buffer(context_id, BufferType::UpstreamData, Some(data));
+        self.enter(context_id);
+        dispatcher::proxy_on_upstream_data(context_id, data.len(), end_of_stream)
+    }
+
+    pub fn on_upstream_close(&self, context_id: u32, peer_type: PeerType) {
+        self.enter(context_id);
+        dispatcher::proxy_on_upstream_connection_close(context_id, peer_type)
+    }
+
+    pub fn on_http_request_headers(
+        &self,
+        context_id: u32,
+        headers: Vec<(&str, &str)>,
+        end_of_stream: bool,
+    ) -> Action {
+        let num_headers = headers.len();
+        self.set_map(context_id, MapType::HttpRequestHeaders, headers);
+        self.enter(context_id);
+        dispatcher::proxy_on_request_headers(context_id, num_headers, end_of_stream)
+    }
+
+    // Appends the chunk to the buffered body, so the size passed to the
+    // plugin covers everything that was buffered by returning Action::Pause.
+    pub fn on_http_request_body(
+        &self,
+        context_id: u32,
+        chunk: &[u8],
+        end_of_stream: bool,
+    ) -> Action {
+        let body_size = self.append_buffer(context_id, BufferType::HttpRequestBody, chunk);
+        self.enter(context_id);
+        let action = dispatcher::proxy_on_request_body(context_id, body_size, end_of_stream);
+        if action == Action::Continue {
+            self.set_buffer(context_id, BufferType::HttpRequestBody, None);
+        }
+        action
+    }
+
+    pub fn on_http_request_trailers(&self, context_id: u32, trailers: Vec<(&str, &str)>) -> Action {
+        let num_trailers = trailers.len();
+        self.set_map(context_id, MapType::HttpRequestTrailers, trailers);
+        self.enter(context_id);
+        dispatcher::proxy_on_request_trailers(context_id, num_trailers)
+    }
+
+    pub fn on_http_response_headers(
+        &self,
+        context_id: u32,
+        headers: Vec<(&str, &str)>,
+        end_of_stream: bool,
+    ) -> Action {
+        let num_headers = headers.len();
+        self.set_map(context_id, MapType::HttpResponseHeaders, headers);
+        self.enter(context_id);
+        dispatcher::proxy_on_response_headers(context_id, num_headers, end_of_stream)
+    }
+
+    pub fn on_http_response_body(
+        &self,
+        context_id: u32,
+        chunk: &[u8],
+        end_of_stream: bool,
+    ) -> Action {
+        let body_size = self.append_buffer(context_id, BufferType::HttpResponseBody, chunk);
+        self.enter(context_id);
+        let action = dispatcher::proxy_on_response_body(context_id, body_size, end_of_stream);
+        if action == Action::Continue {
+            self.set_buffer(context_id, BufferType::HttpResponseBody, None);
+        }
+        action
+    }
+
+    pub fn on_http_response_trailers(
+        &self,
+        context_id: u32,
+        trailers: Vec<(&str, &str)>,
+    ) -> Action {
+        let num_trailers = trailers.len();
+        self.set_map(context_id, MapType::HttpResponseTrailers, trailers);
+        self.enter(context_id);
+        dispatcher::proxy_on_response_trailers(context_id, num_trailers)
+    }
+
+    fn append_buffer(&self, context_id: u32, buffer_type: BufferType, chunk: &[u8]) -> usize {
+        with_state(|state| {
+            let buffer = state.buffers.entry((context_id, buffer_type)).or_default();
+            buffer.extend_from_slice(chunk);
+            buffer.len()
+        })
+    }
+
+    pub fn on_http_call_response(
+        &self,
+        token_id: u32,
+        headers: Vec<(&str, &str)>,
+        body: Option<&[u8]>,
+        trailers: Vec<(&str, &str)>,
+    ) {
+        let context_id = with_state(|state| {
+            state
+                .http_calls
+                .iter()
+                .find(|call| call.token_id == token_id)
+                .map_or(0, |call| call.context_id)
+        });
+        let (num_headers, body_size, num_trailers) =
+            (headers.len(), body.map_or(0, |b| b.len()), trailers.len());
+        self.set_map(0, MapType::HttpCallResponseHeaders, headers);
+        self.set_buffer(0, BufferType::HttpCallResponseBody, body);
+        self.set_map(0, MapType::HttpCallResponseTrailers, trailers);
+        self.enter(context_id);
+        dispatcher::proxy_on_http_call_response(
+            context_id,
+            token_id,
+            num_headers,
+            body_size,
+            num_trailers,
+        );
+        self.set_map(0, MapType::HttpCallResponseHeaders, vec![]);
+        self.set_buffer(0, BufferType::HttpCallResponseBody, None);
+        self.set_map(0, MapType::HttpCallResponseTrailers, vec![]);
+    }
+
+    pub fn on_grpc_receive_initial_metadata(&self, token_id: u32, metadata: Vec<(&str, &[u8])>) {
+        let num_elements = metadata.len() as u32;
+        self.set_map_bytes(0, MapType::GrpcReceiveInitialMetadata, metadata);
+        dispatcher::proxy_on_grpc_receive_initial_metadata(0, token_id, num_elements);
+        self.set_map_bytes(0, MapType::GrpcReceiveInitialMetadata, vec![]);
+    }
+
+    pub fn on_grpc_receive(&self, token_id: u32, message: &[u8]) {
+        self.set_buffer(0, BufferType::GrpcReceiveBuffer, Some(message));
+        dispatcher::proxy_on_grpc_receive(0, token_id, message.len());
+        self.set_buffer(0, BufferType::GrpcReceiveBuffer, None);
+    }
+
+    pub fn on_grpc_receive_trailing_metadata(&self, token_id: u32, metadata: Vec<(&str, &[u8])>) {
+        let num_elements = metadata.len() as u32;
+        self.set_map_bytes(0, MapType::GrpcReceiveTrailingMetadata, metadata);
+        dispatcher::proxy_on_grpc_receive_trailing_metadata(0, token_id, num_elements);
+        self.set_map_bytes(0, MapType::GrpcReceiveTrailingMetadata, vec![]);
+    }
+
+    pub fn on_grpc_close(&self, token_id: u32, status_code: u32, message: Option<&str>) {
+        with_state(|state| {
+            state.grpc_status = (status_code, message.map(|m| m.as_bytes().to_vec()))
+        });
+        dispatcher::proxy_on_grpc_close(0, token_id, status_code);
+        with_state(|state| state.grpc_status = (0, None));
+    }
+
+    pub fn on_log(&self, context_id: u32) {
+        self.enter(context_id);
+        dispatcher::proxy_on_log(context_id)
+    }
+
+    pub fn on_done(&self, context_id: u32) -> bool {
+        self.enter(context_id);
+        dispatcher::proxy_on_done(context_id)
+    }
+
+    pub fn on_delete(&self, context_id: u32) {
+        self.enter(context_id);
+        dispatcher::proxy_on_delete(context_id);
+        with_state(|state| state.contexts.retain(|id| *id != context_id));
+    }
+}
+
+impl Default for MockHost {
+    fn default() -> MockHost {
+        MockHost::new()
+    }
+}
+
+// Native implementation of the ABI imported by the `hostcalls` module. On
+// wasm32, these symbols would shadow the imports from the real host.
+#[cfg(not(target_arch = "wasm32"))]
+mod abi {
+    use super::*;
+    use std::ptr::null_mut;
+
+    unsafe fn slice<'a>(data: *const u8, size: usize) -> &'a [u8] {
+        if data.is_null() || size == 0 {
+            &[]
+        } else {
+            std::slice::from_raw_parts(data, size)
+        }
+    }
+
+    unsafe fn string(data: *const u8, size: usize) -> String {
+        String::from_utf8_lossy(slice(data, size)).into_owned()
+    }
+
+    unsafe fn optional(data: *const u8, size: usize) -> Option<Bytes> {
+        if data.is_null() {
+            None
+        } else {
+            Some(slice(data, size).to_vec())
+        }
+    }
+
+    unsafe fn map(data: *const u8, size: usize) -> Vec<(String, Bytes)> {
+        utils::deserialize_map_bytes(slice(data, size), Utf8Policy::Lossy)
+            .unwrap()
+            .into_iter()
+            .map(|(name, value)| (name.to_lowercase(), value))
+            .collect()
+    }
+
+    // Memory handed over to the plugin must come from its allocator, since
+    // the SDK takes ownership of it. Empty values are returned as null, just
+    // like in the proxy.
+    unsafe fn return_bytes(value: &[u8], return_data: *mut *mut u8, return_size: *mut usize) {
+        if value.is_empty() {
+            *return_data = null_mut();
+            *return_size = 0;
+        } else {
+            let data = crate::allocator::proxy_on_memory_allocate(value.len());
+            std::ptr::copy_nonoverlapping(value.as_ptr(), data, value.len());
+            *return_data = data;
+            *return_size = value.len();
+        }
+    }
+
+    fn injected_failure(name: &str) -> Option<Status> {
+        with_state(|state| state.failures.get(name).copied())
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_log(
+        level: LogLevel,
+        message_data: *const u8,
+        message_size: usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_log") {
+            return status;
+        }
+        let message = string(message_data, message_size);
+        with_state(|state| state.logs.push((level, message)));
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_get_log_level(return_level: *mut LogLevel) -> Status {
+        if let Some(status) = injected_failure("proxy_get_log_level") {
+            return status;
+        }
+        *return_level = with_state(|state| state.log_level);
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_get_current_time_nanoseconds(return_time: *mut u64) -> Status {
+        if let Some(status) = injected_failure("proxy_get_current_time_nanoseconds") {
+            return status;
+        }
+        let time = with_state(|state| state.current_time);
+        *return_time = time.duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64;
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_set_tick_period_milliseconds(period: u32) -> Status {
+        if let Some(status) = injected_failure("proxy_set_tick_period_milliseconds") {
+            return status;
+        }
+        with_state(|state| {
+            let context_id = state.current_context;
+            state
+                .tick_periods
+                .insert(context_id, Duration::from_millis(period as u64))
+        });
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_get_buffer_bytes(
+        buffer_type: BufferType,
+        start: usize,
+        max_size: usize,
+        return_buffer_data: *mut *mut u8,
+        return_buffer_size: *mut usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_get_buffer_bytes") {
+            return status;
+        }
+        let buffer = with_state(|state| state.buffers.get(&state.buffer_key(buffer_type)).cloned());
+        match buffer {
+            Some(buffer) => {
+                let start = start.min(buffer.len());
+                let end = start.saturating_add(max_size).min(buffer.len());
+                return_bytes(&buffer[start..end], return_buffer_data, return_buffer_size);
+                Status::Ok
+            }
+            None => Status::NotFound,
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_set_buffer_bytes(
+        buffer_type: BufferType,
+        start: usize,
+        size: usize,
+        buffer_data: *const u8,
+        buffer_size: usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_set_buffer_bytes") {
+            return status;
+        }
+        let value = slice(buffer_data, buffer_size);
+        with_state(|state| {
+            let key = state.buffer_key(buffer_type);
+            let buffer = state.buffers.entry(key).or_default();
+            if start > buffer.len() {
+                return Status::BadArgument;
+            }
+            let end = start.saturating_add(size).min(buffer.len());
+            buffer.splice(start..end, value.iter().cloned());
+            Status::Ok
+        })
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_get_buffer_status(
+        buffer_type: BufferType,
+        return_buffer_size: *mut usize,
+        return_flags: *mut u32,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_get_buffer_status") {
+            return status;
+        }
+        let size = with_state(|state| {
+            state
+                .buffers
+                .get(&state.buffer_key(buffer_type))
+                .map(|buffer| buffer.len())
+        });
+        match size {
+            Some(size) => {
+                *return_buffer_size = size;
+                *return_flags = 0;
+                Status::Ok
+            }
+            None => Status::NotFound,
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_get_header_map_size(
+        map_type: MapType,
+        return_map_size: *mut usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_get_header_map_size") {
+            return status;
+        }
+        // Same as Envoy, i.e. the total size of names and values.
+        *return_map_size = with_state(|state| {
+            state.maps.get(&state.map_key(map_type)).map_or(0, |map| {
+                map.iter()
+                    .map(|(name, value)| name.len() + value.len())
+                    .sum()
+            })
+        });
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_get_header_map_pairs(
+        map_type: MapType,
+        return_map_data: *mut *mut u8,
+        return_map_size: *mut usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_get_header_map_pairs") {
+            return status;
+        }
+        let map = with_state(|state| {
+            state
+                .maps
+                .get(&state.map_key(map_type))
+                .cloned()
+                .unwrap_or_default()
+        });
+        let serialized_map = utils::serialize_map_bytes(
+            map.iter()
+                .map(|(name, value)| (name.as_str(), value.as_slice()))
+                .collect(),
+        );
+        return_bytes(&serialized_map, return_map_data, return_map_size);
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_set_header_map_pairs(
+        map_type: MapType,
+        map_data: *const u8,
+        map_size: usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_set_header_map_pairs") {
+            return status;
+        }
+        let map = map(map_data, map_size);
+        with_state(|state| state.maps.insert(state.map_key(map_type), map));
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_get_header_map_value(
+        map_type: MapType,
+        key_data: *const u8,
+        key_size: usize,
+        return_value_data: *mut *mut u8,
+        return_value_size: *mut usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_get_header_map_value") {
+            return status;
+        }
+        let key = string(key_data, key_size);
+        let value = with_state(|state| {
+            state.maps.get(&state.map_key(map_type)).and_then(|map| {
+                map.iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(&key))
+                    .map(|(_, value)| value.clone())
+            })
+        });
+        match value {
+            Some(value) => {
+                return_bytes(&value, return_value_data, return_value_size);
+                Status::Ok
+            }
+            None => Status::NotFound,
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_replace_header_map_value(
+        map_type: MapType,
+        key_data: *const u8,
+        key_size: usize,
+        value_data: *const u8,
+        value_size: usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_replace_header_map_value") {
+            return status;
+        }
+        let key = string(key_data, key_size).to_lowercase();
+        let value = slice(value_data, value_size).to_vec();
+        with_state(|state| {
+            let map = state.maps.entry(state.map_key(map_type)).or_default();
+            map.retain(|(name, _)| *name != key);
+            map.push((key, value));
+        });
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_remove_header_map_value(
+        map_type: MapType,
+        key_data: *const u8,
+        key_size: usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_remove_header_map_value") {
+            return status;
+        }
+        let key = string(key_data, key_size).to_lowercase();
+        with_state(|state| {
+            if let Some(map) = state.maps.get_mut(&state.map_key(map_type)) {
+                map.retain(|(name, _)| *name != key);
+            }
+        });
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_add_header_map_value(
+        map_type: MapType,
+        key_data: *const u8,
+        key_size: usize,
+        value_data: *const u8,
+        value_size: usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_add_header_map_value") {
+            return status;
+        }
+        let key = string(key_data, key_size).to_lowercase();
+        let value = slice(value_data, value_size).to_vec();
+        with_state(|state| {
+            state
+                .maps
+                .entry(state.map_key(map_type))
+                .or_default()
+                .push((key, value))
+        });
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_get_property(
+        path_data: *const u8,
+        path_size: usize,
+        return_value_data: *mut *mut u8,
+        return_value_size: *mut usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_get_property") {
+            return status;
+        }
+        let path = slice(path_data, path_size).to_vec();
+        match with_state(|state| state.properties.get(&path).cloned()) {
+            Some(value) => {
+                return_bytes(&value, return_value_data, return_value_size);
+                Status::Ok
+            }
+            None => Status::NotFound,
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_set_property(
+        path_data: *const u8,
+        path_size: usize,
+        value_data: *const u8,
+        value_size: usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_set_property") {
+            return status;
+        }
+        let path = slice(path_data, path_size).to_vec();
+        let value = slice(value_data, value_size).to_vec();
+        with_state(|state| state.properties.insert(path, value));
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_get_shared_data(
+        key_data: *const u8,
+        key_size: usize,
+        return_value_data: *mut *mut u8,
+        return_value_size: *mut usize,
+        return_cas: *mut u32,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_get_shared_data") {
+            return status;
+        }
+        let key = string(key_data, key_size);
+        match with_state(|state| state.shared_data.get(&key).cloned()) {
+            Some((value, cas)) => {
+                return_bytes(&value, return_value_data, return_value_size);
+                *return_cas = cas;
+                Status::Ok
+            }
+            None => Status::NotFound,
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_set_shared_data(
+        key_data: *const u8,
+        key_size: usize,
+        value_data: *const u8,
+        value_size: usize,
+        cas: u32,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_set_shared_data") {
+            return status;
+        }
+        let key = string(key_data, key_size);
+        let value = slice(value_data, value_size).to_vec();
+        with_state(|state| {
+            if let Some((_, current_cas)) = state.shared_data.get(&key) {
+                if cas != 0 && cas != *current_cas {
+                    return Status::CasMismatch;
+                }
+            }
+            let new_cas = state.next_cas;
+            state.next_cas += 1;
+            state.shared_data.insert(key, (value, new_cas));
+            Status::Ok
+        })
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_register_shared_queue(
+        name_data: *const u8,
+        name_size: usize,
+        return_id: *mut u32,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_register_shared_queue") {
+            return status;
+        }
+        let name = string(name_data, name_size);
+        *return_id = with_state(|state| {
+            match state
+                .queues
+                .iter()
+                .position(|queue| queue.vm_id.is_empty() && queue.name == name)
+            {
+                Some(index) => index as u32 + 1,
+                None => {
+                    state.queues.push(Queue {
+                        vm_id: String::new(),
+                        name,
+                        items: VecDeque::new(),
+                    });
+                    state.queues.len() as u32
+                }
+            }
+        });
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_resolve_shared_queue(
+        vm_id_data: *const u8,
+        vm_id_size: usize,
+        name_data: *const u8,
+        name_size: usize,
+        return_id: *mut u32,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_resolve_shared_queue") {
+            return status;
+        }
+        let vm_id = string(vm_id_data, vm_id_size);
+        let name = string(name_data, name_size);
+        let queue_id = with_state(|state| {
+            state
+                .queues
+                .iter()
+                .position(|queue| queue.vm_id == vm_id && queue.name == name)
+        });
+        match queue_id {
+            Some(index) => {
+                *return_id = index as u32 + 1;
+                Status::Ok
+            }
+            None => Status::NotFound,
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_dequeue_shared_queue(
+        queue_id: u32,
+        return_value_data: *mut *mut u8,
+        return_value_size: *mut usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_dequeue_shared_queue") {
+            return status;
+        }
+        let item = with_state(|state| {
+            state
+                .queues
+                .get_mut((queue_id as usize).wrapping_sub(1))
+                .map(|queue| queue.items.pop_front())
+        });
+        match item {
+            Some(Some(value)) => {
+                return_bytes(&value, return_value_data, return_value_size);
+                Status::Ok
+            }
+            Some(None) => Status::Empty,
+            None => Status::NotFound,
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_enqueue_shared_queue(
+        queue_id: u32,
+        value_data: *const u8,
+        value_size: usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_enqueue_shared_queue") {
+            return status;
+        }
+        let value = slice(value_data, value_size).to_vec();
+        with_state(
+            |state| match state.queues.get_mut((queue_id as usize).wrapping_sub(1)) {
+                Some(queue) => {
+                    queue.items.push_back(value);
+                    Status::Ok
+                }
+                None => Status::NotFound,
+            },
+        )
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_continue_stream(stream_type: StreamType) -> Status {
+        if let Some(status) = injected_failure("proxy_continue_stream") {
+            return status;
+        }
+        with_state(|state| {
+            let context_id = state.current_context;
+            state
+                .continued_streams
+                .entry(context_id)
+                .or_default()
+                .push(stream_type)
+        });
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_close_stream(stream_type: StreamType) -> Status {
+        if let Some(status) = injected_failure("proxy_close_stream") {
+            return status;
+        }
+        with_state(|state| {
+            let context_id = state.current_context;
+            state
+                .closed_streams
+                .entry(context_id)
+                .or_default()
+                .push(stream_type)
+        });
+        Status::Ok
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_send_local_response(
+        status_code: u32,
+        status_code_details_data: *const u8,
+        status_code_details_size: usize,
+        body_data: *const u8,
+        body_size: usize,
+        headers_data: *const u8,
+        headers_size: usize,
+        grpc_status: i32,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_send_local_response") {
+            return status;
+        }
+        let response = LocalResponse {
+            status_code,
+            status_code_details: optional(status_code_details_data, status_code_details_size)
+                .map(|details| String::from_utf8_lossy(&details).into_owned()),
+            headers: map(headers_data, headers_size),
+            body: optional(body_data, body_size),
+            grpc_status: if grpc_status < 0 {
+                None
+            } else {
+                Some(grpc_status)
+            },
+        };
+        with_state(|state| {
+            let context_id = state.current_context;
+            state.local_responses.insert(context_id, response)
+        });
+        Status::Ok
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_http_call(
+        upstream_data: *const u8,
+        upstream_size: usize,
+        headers_data: *const u8,
+        headers_size: usize,
+        body_data: *const u8,
+        body_size: usize,
+        trailers_data: *const u8,
+        trailers_size: usize,
+        timeout: u32,
+        return_token: *mut u32,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_http_call") {
+            return status;
+        }
+        let headers = map(headers_data, headers_size);
+        for required in &[":method", ":path", ":authority"] {
+            if !headers.iter().any(|(name, _)| name == required) {
+                return Status::BadArgument;
+            }
+        }
+        let upstream = string(upstream_data, upstream_size);
+        let body = optional(body_data, body_size);
+        let trailers = map(trailers_data, trailers_size);
+        *return_token = with_state(|state| {
+            let token_id = state.next_token();
+            state.http_calls.push(HttpCall {
+                token_id,
+                context_id: state.current_context,
+                upstream,
+                headers,
+                body,
+                trailers,
+                timeout: Duration::from_millis(timeout as u64),
+            });
+            token_id
+        });
+        Status::Ok
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_grpc_call(
+        upstream_data: *const u8,
+        upstream_size: usize,
+        service_name_data: *const u8,
+        service_name_size: usize,
+        method_name_data: *const u8,
+        method_name_size: usize,
+        initial_metadata_data: *const u8,
+        initial_metadata_size: usize,
+        message_data_data: *const u8,
+        message_data_size: usize,
+        timeout: u32,
+        return_callout_id: *mut u32,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_grpc_call") {
+            return status;
+        }
+        let call = GrpcCall {
+            token_id: 0,
+            context_id: 0,
+            upstream: string(upstream_data, upstream_size),
+            service_name: string(service_name_data, service_name_size),
+            method_name: string(method_name_data, method_name_size),
+            initial_metadata: map(initial_metadata_data, initial_metadata_size),
+            message: optional(message_data_data, message_data_size),
+            timeout: Duration::from_millis(timeout as u64),
+            cancelled: false,
+        };
+        *return_callout_id = with_state(|state| {
+            let token_id = state.next_token();
+            state.grpc_calls.push(GrpcCall {
+                token_id,
+                context_id: state.current_context,
+                ..call
+            });
+            token_id
+        });
+        Status::Ok
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_grpc_stream(
+        upstream_data: *const u8,
+        upstream_size: usize,
+        service_name_data: *const u8,
+        service_name_size: usize,
+        method_name_data: *const u8,
+        method_name_size: usize,
+        initial_metadata_data: *const u8,
+        initial_metadata_size: usize,
+        return_stream_id: *mut u32,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_grpc_stream") {
+            return status;
+        }
+        let stream = GrpcStream {
+            token_id: 0,
+            context_id: 0,
+            upstream: string(upstream_data, upstream_size),
+            service_name: string(service_name_data, service_name_size),
+            method_name: string(method_name_data, method_name_size),
+            initial_metadata: map(initial_metadata_data, initial_metadata_size),
+            messages: Vec::new(),
+            end_stream: false,
+            closed: false,
+            cancelled: false,
+        };
+        *return_stream_id = with_state(|state| {
+            let token_id = state.next_token();
+            state.grpc_streams.push(GrpcStream {
+                token_id,
+                context_id: state.current_context,
+                ..stream
+            });
+            token_id
+        });
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_grpc_send(
+        token: u32,
+        message_ptr: *const u8,
+        message_len: usize,
+        end_stream: bool,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_grpc_send") {
+            return status;
+        }
+        let message = slice(message_ptr, message_len).to_vec();
+        with_state(|state| {
+            match state
+                .grpc_streams
+                .iter_mut()
+                .find(|stream| stream.token_id == token)
+            {
+                Some(stream) if stream.end_stream => Status::BadArgument,
+                Some(stream) => {
+                    stream.messages.push(message);
+                    stream.end_stream = end_stream;
+                    Status::Ok
+                }
+                None => Status::NotFound,
+            }
+        })
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_grpc_cancel(token_id: u32) -> Status {
+        if let Some(status) = injected_failure("proxy_grpc_cancel") {
+            return status;
+        }
+        with_state(|state| {
+            if let Some(call) = state
+                .grpc_calls
+                .iter_mut()
+                .find(|call| call.token_id == token_id)
+            {
+                call.cancelled = true;
+                Status::Ok
+            } else if let Some(stream) = state
+                .grpc_streams
+                .iter_mut()
+                .find(|stream| stream.token_id == token_id)
+            {
+                stream.cancelled = true;
+                Status::Ok
+            } else {
+                Status::NotFound
+            }
+        })
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_grpc_close(token_id: u32) -> Status {
+        if let Some(status) = injected_failure("proxy_grpc_close") {
+            return status;
+        }
+        with_state(|state| {
+            match state
+                .grpc_streams
+                .iter_mut()
+                .find(|stream| stream.token_id == token_id)
+            {
+                Some(stream) => {
+                    stream.closed = true;
+                    Status::Ok
+                }
+                None => Status::NotFound,
+            }
+        })
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_get_status(
+        return_code: *mut u32,
+        return_message_data: *mut *mut u8,
+        return_message_size: *mut usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_get_status") {
+            return status;
+        }
+        let (code, message) = with_state(|state| state.grpc_status.clone());
+        *return_code = code;
+        return_bytes(
+            message.as_deref().unwrap_or(&[]),
+            return_message_data,
+            return_message_size,
+        );
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_set_effective_context(context_id: u32) -> Status {
+        if let Some(status) = injected_failure("proxy_set_effective_context") {
+            return status;
+        }
+        with_state(|state| {
+            if state.contexts.contains(&context_id) {
+                state.current_context = context_id;
+                Status::Ok
+            } else {
+                Status::BadArgument
+            }
+        })
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_call_foreign_function(
+        function_name_data: *const u8,
+        function_name_size: usize,
+        arguments_data: *const u8,
+        arguments_size: usize,
+        results_data: *mut *mut u8,
+        results_size: *mut usize,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_call_foreign_function") {
+            return status;
+        }
+        let function_name = string(function_name_data, function_name_size);
+        let arguments = slice(arguments_data, arguments_size);
+        // Functions are called without borrowing the state, since they can
+        // make hostcalls themselves.
+        let function = with_state(|state| state.foreign_functions.get(&function_name).cloned());
+        match function.map(|function| function(arguments)) {
+            Some(result) => {
+                return_bytes(result.as_deref().unwrap_or(&[]), results_data, results_size);
+                Status::Ok
+            }
+            None => Status::NotFound,
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_done() -> Status {
+        if let Some(status) = injected_failure("proxy_done") {
+            return status;
+        }
+        with_state(|state| {
+            let context_id = state.current_context;
+            state.done.push(context_id)
+        });
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_define_metric(
+        metric_type: MetricType,
+        name_data: *const u8,
+        name_size: usize,
+        return_id: *mut u32,
+    ) -> Status {
+        if let Some(status) = injected_failure("proxy_define_metric") {
+            return status;
+        }
+        let name = string(name_data, name_size);
+        *return_id =
+            with_state(
+                |state| match state.metrics.iter().position(|metric| metric.name == name) {
+                    Some(index) => index as u32 + 1,
+                    None => {
+                        state.metrics.push(Metric {
+                            metric_type,
+                            name,
+                            value: 0,
+                        });
+                        state.metrics.len() as u32
+                    }
+                },
+            );
+        Status::Ok
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_get_metric(metric_id: u32, return_value: *mut u64) -> Status {
+        if let Some(status) = injected_failure("proxy_get_metric") {
+            return status;
+        }
+        match with_state(|state| {
+            state
+                .metrics
+                .get((metric_id as usize).wrapping_sub(1))
+                .map(|metric| metric.value)
+        }) {
+            Some(value) => {
+                *return_value = value;
+                Status::Ok
+            }
+            None => Status::NotFound,
+        }
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_record_metric(metric_id: u32, value: u64) -> Status {
+        if let Some(status) = injected_failure("proxy_record_metric") {
+            return status;
+        }
+        with_state(
+            |state| match state.metrics.get_mut((metric_id as usize).wrapping_sub(1)) {
+                Some(metric) => {
+                    metric.value = value;
+                    Status::Ok
+                }
+                None => Status::NotFound,
+            },
+        )
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn proxy_increment_metric(metric_id: u32, offset: i64) -> Status {
+        if let Some(status) = injected_failure("proxy_increment_metric") {
+            return status;
+        }
+        with_state(
+            |state| match state.metrics.get_mut((metric_id as usize).wrapping_sub(1)) {
+                Some(metric) if metric.metric_type == MetricType::Counter && offset < 0 => {
+                    Status::BadArgument
+                }
+                Some(metric) => {
+                    metric.value = (metric.value as i64).wrapping_add(offset) as u64;
+                    Status::Ok
+                }
+                None => Status::NotFound,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hostcalls;
+
+    #[test]
+    fn shared_queues() {
+        let host = MockHost::new();
+        let queue_id = hostcalls::register_shared_queue("queue").unwrap();
+        host.enqueue(queue_id, b"one");
+        hostcalls::enqueue_shared_queue(queue_id, Some(b"two")).unwrap();
+        assert_eq!(
+            host.queue_items(queue_id),
+            [b"one".to_vec(), b"two".to_vec()]
+        );
+        assert!(host.queue_items(0).is_empty());
+        assert!(host.queue_items(queue_id + 1).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "no shared queue with id 0")]
+    fn enqueue_to_unknown_queue() {
+        let host = MockHost::new();
+        hostcalls::register_shared_queue("queue").unwrap();
+        host.enqueue(0, b"one");
+    }
+
+    #[test]
+    fn reentrant_foreign_function() {
+        let host = MockHost::new();
+        host.register_foreign_function("log", |arguments| {
+            let message = String::from_utf8_lossy(arguments);
+            hostcalls::log(LogLevel::Info, &message).unwrap();
+            Some(b"logged".to_vec())
+        });
+        let result = hostcalls::call_foreign_function("log", Some(b"hello")).unwrap();
+        assert_eq!(result, Some(b"logged".to_vec()));
+        assert_eq!(host.logs(), [(LogLevel::Info, "hello".to_string())]);
+        assert!(hostcalls::call_foreign_function("missing", None).is_err());
+    }
+}
@@ -12,25 +12,61 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::dispatcher;
+use crate::grpc::{self, ErrorDetail};
+use crate::header_case;
 use crate::hostcalls;
 use crate::types::*;
 use std::time::{Duration, SystemTime};
 
 pub trait Context {
     fn get_current_time(&self) -> SystemTime {
-        hostcalls::get_current_time().unwrap()
+        self.try_get_current_time().unwrap()
+    }
+
+    fn try_get_current_time(&self) -> Result<SystemTime, Error> {
+        hostcalls::try_get_current_time()
+    }
+
+    /// Returns the log level of the host, below which logs are dropped.
+    fn get_log_level(&self) -> LogLevel {
+        self.try_get_log_level().unwrap()
+    }
+
+    fn try_get_log_level(&self) -> Result<LogLevel, Error> {
+        hostcalls::try_get_log_level()
     }
 
     fn get_property(&self, path: Vec<&str>) -> Option<Bytes> {
-        hostcalls::get_property(path).unwrap()
+        self.try_get_property(path).unwrap()
+    }
+
+    fn try_get_property(&self, path: Vec<&str>) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_property(path)
+    }
+
+    fn get_property_string(&self, path: Vec<&str>) -> Option<String> {
+        self.try_get_property_string(path).unwrap()
+    }
+
+    fn try_get_property_string(&self, path: Vec<&str>) -> Result<Option<String>, Error> {
+        hostcalls::try_get_property_string(path)
     }
 
     fn set_property(&self, path: Vec<&str>, value: Option<&[u8]>) {
-        hostcalls::set_property(path, value).unwrap()
+        self.try_set_property(path, value).unwrap()
+    }
+
+    fn try_set_property(&self, path: Vec<&str>, value: Option<&[u8]>) -> Result<(), Error> {
+        hostcalls::try_set_property(path, value)
     }
 
     fn get_shared_data(&self, key: &str) -> (Option<Bytes>, Option<u32>) {
-        hostcalls::get_shared_data(key).unwrap()
+        self.try_get_shared_data(key).unwrap()
+    }
+
+    fn try_get_shared_data(&self, key: &str) -> Result<(Option<Bytes>, Option<u32>), Error> {
+        hostcalls::try_get_shared_data(key)
     }
 
     fn set_shared_data(
@@ -42,22 +78,47 @@ pub trait Context {
         hostcalls::set_shared_data(key, value, cas)
     }
 
+    fn try_set_shared_data(
+        &self,
+        key: &str,
+        value: Option<&[u8]>,
+        cas: Option<u32>,
+    ) -> Result<(), Error> {
+        hostcalls::try_set_shared_data(key, value, cas)
+    }
+
     fn register_shared_queue(&self, name: &str) -> u32 {
-        hostcalls::register_shared_queue(name).unwrap()
+        self.try_register_shared_queue(name).unwrap()
+    }
+
+    fn try_register_shared_queue(&self, name: &str) -> Result<u32, Error> {
+        hostcalls::try_register_shared_queue(name)
     }
 
     fn resolve_shared_queue(&self, vm_id: &str, name: &str) -> Option<u32> {
-        hostcalls::resolve_shared_queue(vm_id, name).unwrap()
+        self.try_resolve_shared_queue(vm_id, name).unwrap()
+    }
+
+    fn try_resolve_shared_queue(&self, vm_id: &str, name: &str) -> Result<Option<u32>, Error> {
+        hostcalls::try_resolve_shared_queue(vm_id, name)
     }
 
     fn dequeue_shared_queue(&self, queue_id: u32) -> Result<Option<Bytes>, Status> {
         hostcalls::dequeue_shared_queue(queue_id)
     }
 
+    fn try_dequeue_shared_queue(&self, queue_id: u32) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_dequeue_shared_queue(queue_id)
+    }
+
     fn enqueue_shared_queue(&self, queue_id: u32, value: Option<&[u8]>) -> Result<(), Status> {
         hostcalls::enqueue_shared_queue(queue_id, value)
     }
 
+    fn try_enqueue_shared_queue(&self, queue_id: u32, value: Option<&[u8]>) -> Result<(), Error> {
+        hostcalls::try_enqueue_shared_queue(queue_id, value)
+    }
+
     fn dispatch_http_call(
         &self,
         upstream: &str,
@@ -69,6 +130,21 @@ pub trait Context {
         hostcalls::dispatch_http_call(upstream, headers, body, trailers, timeout)
     }
 
+    fn try_dispatch_http_call(
+        &self,
+        upstream: &str,
+        headers: Vec<(&str, &str)>,
+        body: Option<&[u8]>,
+        trailers: Vec<(&str, &str)>,
+        timeout: Duration,
+    ) -> Result<u32, Error> {
+        hostcalls::try_dispatch_http_call(upstream, headers, body, trailers, timeout)
+    }
+
+    fn dispatch_http_call_request(&self, request: HttpCallRequest) -> Result<u32, Error> {
+        hostcalls::try_dispatch_http_call_request(&request)
+    }
+
     fn on_http_call_response(
         &mut self,
         _token_id: u32,
@@ -78,40 +154,99 @@ pub trait Context {
     ) {
     }
 
+    fn on_http_call_result(&mut self, _token_id: u32, _response: HttpCallResponse) {}
+
     fn get_http_call_response_headers(&self) -> Vec<(String, String)> {
-        hostcalls::get_map(MapType::HttpCallResponseHeaders).unwrap()
+        self.try_get_http_call_response_headers().unwrap()
+    }
+
+    fn try_get_http_call_response_headers(&self) -> Result<Vec<(String, String)>, Error> {
+        hostcalls::try_get_map(MapType::HttpCallResponseHeaders)
     }
 
     fn get_http_call_response_headers_bytes(&self) -> Vec<(String, Bytes)> {
-        hostcalls::get_map_bytes(MapType::HttpCallResponseHeaders).unwrap()
+        self.try_get_http_call_response_headers_bytes().unwrap()
+    }
+
+    fn try_get_http_call_response_headers_bytes(&self) -> Result<Vec<(String, Bytes)>, Error> {
+        hostcalls::try_get_map_bytes(MapType::HttpCallResponseHeaders)
+    }
+
+    fn get_http_call_response_headers_map(&self) -> HeaderMap {
+        self.try_get_http_call_response_headers_map().unwrap()
+    }
+
+    fn try_get_http_call_response_headers_map(&self) -> Result<HeaderMap, Error> {
+        hostcalls::try_get_header_map(MapType::HttpCallResponseHeaders)
     }
 
     fn get_http_call_response_header(&self, name: &str) -> Option<String> {
-        hostcalls::get_map_value(MapType::HttpCallResponseHeaders, name).unwrap()
+        self.try_get_http_call_response_header(name).unwrap()
+    }
+
+    fn try_get_http_call_response_header(&self, name: &str) -> Result<Option<String>, Error> {
+        hostcalls::try_get_map_value(MapType::HttpCallResponseHeaders, name)
     }
 
     fn get_http_call_response_header_bytes(&self, name: &str) -> Option<Bytes> {
-        hostcalls::get_map_value_bytes(MapType::HttpCallResponseHeaders, name).unwrap()
+        self.try_get_http_call_response_header_bytes(name).unwrap()
+    }
+
+    fn try_get_http_call_response_header_bytes(&self, name: &str) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_map_value_bytes(MapType::HttpCallResponseHeaders, name)
     }
 
     fn get_http_call_response_body(&self, start: usize, max_size: usize) -> Option<Bytes> {
-        hostcalls::get_buffer(BufferType::HttpCallResponseBody, start, max_size).unwrap()
+        self.try_get_http_call_response_body(start, max_size)
+            .unwrap()
+    }
+
+    fn try_get_http_call_response_body(
+        &self,
+        start: usize,
+        max_size: usize,
+    ) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_buffer(BufferType::HttpCallResponseBody, start, max_size)
     }
 
     fn get_http_call_response_trailers(&self) -> Vec<(String, String)> {
-        hostcalls::get_map(MapType::HttpCallResponseTrailers).unwrap()
+        self.try_get_http_call_response_trailers().unwrap()
+    }
+
+    fn try_get_http_call_response_trailers(&self) -> Result<Vec<(String, String)>, Error> {
+        hostcalls::try_get_map(MapType::HttpCallResponseTrailers)
     }
 
     fn get_http_call_response_trailers_bytes(&self) -> Vec<(String, Bytes)> {
-        hostcalls::get_map_bytes(MapType::HttpCallResponseTrailers).unwrap()
+        self.try_get_http_call_response_trailers_bytes().unwrap()
+    }
+
+    fn try_get_http_call_response_trailers_bytes(&self) -> Result<Vec<(String, Bytes)>, Error> {
+        hostcalls::try_get_map_bytes(MapType::HttpCallResponseTrailers)
+    }
+
+    fn get_http_call_response_trailers_map(&self) -> HeaderMap {
+        self.try_get_http_call_response_trailers_map().unwrap()
+    }
+
+    fn try_get_http_call_response_trailers_map(&self) -> Result<HeaderMap, Error> {
+        hostcalls::try_get_header_map(MapType::HttpCallResponseTrailers)
     }
 
     fn get_http_call_response_trailer(&self, name: &str) -> Option<String> {
-        hostcalls::get_map_value(MapType::HttpCallResponseTrailers, name).unwrap()
+        self.try_get_http_call_response_trailer(name).unwrap()
+    }
+
+    fn try_get_http_call_response_trailer(&self, name: &str) -> Result<Option<String>, Error> {
+        hostcalls::try_get_map_value(MapType::HttpCallResponseTrailers, name)
     }
 
     fn get_http_call_response_trailer_bytes(&self, name: &str) -> Option<Bytes> {
-        hostcalls::get_map_value_bytes(MapType::HttpCallResponseTrailers, name).unwrap()
+        self.try_get_http_call_response_trailer_bytes(name).unwrap()
+    }
+
+    fn try_get_http_call_response_trailer_bytes(&self, name: &str) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_map_value_bytes(MapType::HttpCallResponseTrailers, name)
     }
 
     fn dispatch_grpc_call(
@@ -133,14 +268,48 @@ pub trait Context {
         )
     }
 
+    fn try_dispatch_grpc_call(
+        &self,
+        upstream_name: &str,
+        service_name: &str,
+        method_name: &str,
+        initial_metadata: Vec<(&str, &[u8])>,
+        message: Option<&[u8]>,
+        timeout: Duration,
+    ) -> Result<u32, Error> {
+        hostcalls::try_dispatch_grpc_call(
+            upstream_name,
+            service_name,
+            method_name,
+            initial_metadata,
+            message,
+            timeout,
+        )
+    }
+
     fn on_grpc_call_response(&mut self, _token_id: u32, _status_code: u32, _response_size: usize) {}
 
     fn get_grpc_call_response_body(&self, start: usize, max_size: usize) -> Option<Bytes> {
-        hostcalls::get_buffer(BufferType::GrpcReceiveBuffer, start, max_size).unwrap()
+        self.try_get_grpc_call_response_body(start, max_size)
+            .unwrap()
+    }
+
+    fn try_get_grpc_call_response_body(
+        &self,
+        start: usize,
+        max_size: usize,
+    ) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_buffer(BufferType::GrpcReceiveBuffer, start, max_size)
     }
 
     fn cancel_grpc_call(&self, token_id: u32) {
-        hostcalls::cancel_grpc_call(token_id).unwrap()
+        self.try_cancel_grpc_call(token_id).unwrap()
+    }
+
+    fn try_cancel_grpc_call(&self, token_id: u32) -> Result<(), Error> {
+        hostcalls::try_cancel_grpc_call(token_id)?;
+        dispatcher::forget_callout(token_id);
+        Ok(())
     }
 
     fn open_grpc_stream(
@@ -153,48 +322,160 @@ pub trait Context {
         hostcalls::open_grpc_stream(cluster_name, service_name, method_name, initial_metadata)
     }
 
+    fn try_open_grpc_stream(
+        &self,
+        cluster_name: &str,
+        service_name: &str,
+        method_name: &str,
+        initial_metadata: Vec<(&str, &[u8])>,
+    ) -> Result<u32, Error> {
+        hostcalls::try_open_grpc_stream(cluster_name, service_name, method_name, initial_metadata)
+    }
+
     fn on_grpc_stream_initial_metadata(&mut self, _token_id: u32, _num_elements: u32) {}
 
     fn get_grpc_stream_initial_metadata(&self) -> Vec<(String, Bytes)> {
-        hostcalls::get_map_bytes(MapType::GrpcReceiveInitialMetadata).unwrap()
+        self.try_get_grpc_stream_initial_metadata().unwrap()
+    }
+
+    fn try_get_grpc_stream_initial_metadata(&self) -> Result<Vec<(String, Bytes)>, Error> {
+        hostcalls::try_get_map_bytes(MapType::GrpcReceiveInitialMetadata)
     }
 
     fn get_grpc_stream_initial_metadata_value(&self, name: &str) -> Option<Bytes> {
-        hostcalls::get_map_value_bytes(MapType::GrpcReceiveInitialMetadata, name).unwrap()
+        self.try_get_grpc_stream_initial_metadata_value(name)
+            .unwrap()
+    }
+
+    fn try_get_grpc_stream_initial_metadata_value(
+        &self,
+        name: &str,
+    ) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_map_value_bytes(MapType::GrpcReceiveInitialMetadata, name)
     }
 
     fn send_grpc_stream_message(&self, token_id: u32, message: Option<&[u8]>, end_stream: bool) {
-        hostcalls::send_grpc_stream_message(token_id, message, end_stream).unwrap()
+        self.try_send_grpc_stream_message(token_id, message, end_stream)
+            .unwrap()
+    }
+
+    fn try_send_grpc_stream_message(
+        &self,
+        token_id: u32,
+        message: Option<&[u8]>,
+        end_stream: bool,
+    ) -> Result<(), Error> {
+        hostcalls::try_send_grpc_stream_message(token_id, message, end_stream)
     }
 
     fn on_grpc_stream_message(&mut self, _token_id: u32, _message_size: usize) {}
 
     fn get_grpc_stream_message(&mut self, start: usize, max_size: usize) -> Option<Bytes> {
-        hostcalls::get_buffer(BufferType::GrpcReceiveBuffer, start, max_size).unwrap()
+        self.try_get_grpc_stream_message(start, max_size).unwrap()
+    }
+
+    fn try_get_grpc_stream_message(
+        &mut self,
+        start: usize,
+        max_size: usize,
+    ) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_buffer(BufferType::GrpcReceiveBuffer, start, max_size)
     }
 
     fn on_grpc_stream_trailing_metadata(&mut self, _token_id: u32, _num_elements: u32) {}
 
     fn get_grpc_stream_trailing_metadata(&self) -> Vec<(String, Bytes)> {
-        hostcalls::get_map_bytes(MapType::GrpcReceiveTrailingMetadata).unwrap()
+        self.try_get_grpc_stream_trailing_metadata().unwrap()
+    }
+
+    fn try_get_grpc_stream_trailing_metadata(&self) -> Result<Vec<(String, Bytes)>, Error> {
+        hostcalls::try_get_map_bytes(MapType::GrpcReceiveTrailingMetadata)
     }
 
     fn get_grpc_stream_trailing_metadata_value(&self, name: &str) -> Option<Bytes> {
-        hostcalls::get_map_value_bytes(MapType::GrpcReceiveTrailingMetadata, name).unwrap()
+        self.try_get_grpc_stream_trailing_metadata_value(name)
+            .unwrap()
+    }
+
+    fn try_get_grpc_stream_trailing_metadata_value(
+        &self,
+        name: &str,
+    ) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_map_value_bytes(MapType::GrpcReceiveTrailingMetadata, name)
     }
 
     fn cancel_grpc_stream(&self, token_id: u32) {
-        hostcalls::cancel_grpc_stream(token_id).unwrap()
+        self.try_cancel_grpc_stream(token_id).unwrap()
+    }
+
+    fn try_cancel_grpc_stream(&self, token_id: u32) -> Result<(), Error> {
+        hostcalls::try_cancel_grpc_stream(token_id)?;
+        dispatcher::forget_callout(token_id);
+        Ok(())
     }
 
     fn close_grpc_stream(&self, token_id: u32) {
-        hostcalls::close_grpc_stream(token_id).unwrap()
+        self.try_close_grpc_stream(token_id).unwrap()
+    }
+
+    fn try_close_grpc_stream(&self, token_id: u32) -> Result<(), Error> {
+        hostcalls::try_close_grpc_stream(token_id)
     }
 
     fn on_grpc_stream_close(&mut self, _token_id: u32, _status_code: u32) {}
 
+    /// Returns the tokens of the HTTP callouts, gRPC callouts and gRPC
+    /// streams of this context that are still pending.
+    fn pending_callouts(&self) -> Vec<u32> {
+        dispatcher::pending_callouts()
+    }
+
+    /// Stops tracking an HTTP callout, so that its response is dropped.
+    /// Returns `false` if the callout is not pending.
+    fn cancel_http_call(&self, token_id: u32) -> bool {
+        dispatcher::forget_callout(token_id)
+    }
+
+    /// Called instead of the response callback of a pending callout if its
+    /// context is being deleted, or if the host didn't complete it in time.
+    fn on_callout_cancelled(&mut self, _token_id: u32, _reason: CancelReason) {}
+
+    fn schedule_after(&self, delay: Duration) -> u32 {
+        self.try_schedule_after(delay).unwrap()
+    }
+
+    fn try_schedule_after(&self, delay: Duration) -> Result<u32, Error> {
+        dispatcher::schedule_timer(delay, None)
+    }
+
+    fn schedule_every(&self, interval: Duration) -> u32 {
+        self.try_schedule_every(interval).unwrap()
+    }
+
+    fn try_schedule_every(&self, interval: Duration) -> Result<u32, Error> {
+        dispatcher::schedule_timer(interval, Some(interval))
+    }
+
+    fn cancel_timer(&self, timer_id: u32) -> bool {
+        dispatcher::cancel_timer(timer_id)
+    }
+
+    fn on_timer(&mut self, _timer_id: u32) {}
+
     fn get_grpc_status(&self) -> (u32, Option<String>) {
-        hostcalls::get_grpc_status().unwrap()
+        self.try_get_grpc_status().unwrap()
+    }
+
+    fn try_get_grpc_status(&self) -> Result<(u32, Option<String>), Error> {
+        hostcalls::try_get_grpc_status()
+    }
+
+    fn get_grpc_status_bytes(&self) -> (u32, Option<Bytes>) {
+        self.try_get_grpc_status_bytes().unwrap()
+    }
+
+    fn try_get_grpc_status_bytes(&self) -> Result<(u32, Option<Bytes>), Error> {
+        hostcalls::try_get_grpc_status_bytes()
     }
 
     fn call_foreign_function(
@@ -205,12 +486,24 @@ pub trait Context {
         hostcalls::call_foreign_function(function_name, arguments)
     }
 
+    fn try_call_foreign_function(
+        &self,
+        function_name: &str,
+        arguments: Option<&[u8]>,
+    ) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_call_foreign_function(function_name, arguments)
+    }
+
     fn on_done(&mut self) -> bool {
         true
     }
 
     fn done(&self) {
-        hostcalls::done().unwrap()
+        self.try_done().unwrap()
+    }
+
+    fn try_done(&self) -> Result<(), Error> {
+        hostcalls::try_done()
     }
 }
 
@@ -220,7 +513,11 @@ pub trait RootContext: Context {
     }
 
     fn get_vm_configuration(&self) -> Option<Bytes> {
-        hostcalls::get_buffer(BufferType::VmConfiguration, 0, usize::MAX).unwrap()
+        self.try_get_vm_configuration().unwrap()
+    }
+
+    fn try_get_vm_configuration(&self) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_buffer(BufferType::VmConfiguration, 0, usize::MAX)
     }
 
     fn on_configure(&mut self, _plugin_configuration_size: usize) -> bool {
@@ -228,17 +525,37 @@ pub trait RootContext: Context {
     }
 
     fn get_plugin_configuration(&self) -> Option<Bytes> {
-        hostcalls::get_buffer(BufferType::PluginConfiguration, 0, usize::MAX).unwrap()
+        self.try_get_plugin_configuration().unwrap()
+    }
+
+    fn try_get_plugin_configuration(&self) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_buffer(BufferType::PluginConfiguration, 0, usize::MAX)
     }
 
     fn set_tick_period(&self, period: Duration) {
-        hostcalls::set_tick_period(period).unwrap()
+        self.try_set_tick_period(period).unwrap()
+    }
+
+    fn try_set_tick_period(&self, period: Duration) -> Result<(), Error> {
+        dispatcher::set_tick_period(period)
     }
 
     fn on_tick(&mut self) {}
 
     fn on_queue_ready(&mut self, _queue_id: u32) {}
 
+    /// Called when the host calls a function exported by the plugin, with
+    /// arguments of `data_size` bytes available in `BufferType::CallData`.
+    fn on_foreign_function(&mut self, _function_id: u32, _data_size: usize) {}
+
+    fn get_foreign_function_data(&self) -> Option<Bytes> {
+        self.try_get_foreign_function_data().unwrap()
+    }
+
+    fn try_get_foreign_function_data(&self) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_buffer(BufferType::CallData, 0, usize::MAX)
+    }
+
     fn on_log(&mut self) {}
 
     fn create_http_context(&self, _context_id: u32) -> Option<Box<dyn HttpContext>> {
@@ -264,19 +581,44 @@ pub trait StreamContext: Context {
     }
 
     fn get_downstream_data(&self, start: usize, max_size: usize) -> Option<Bytes> {
-        hostcalls::get_buffer(BufferType::DownstreamData, start, max_size).unwrap()
+        self.try_get_downstream_data(start, max_size).unwrap()
+    }
+
+    fn try_get_downstream_data(
+        &self,
+        start: usize,
+        max_size: usize,
+    ) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_buffer(BufferType::DownstreamData, start, max_size)
     }
 
     fn set_downstream_data(&self, start: usize, size: usize, value: &[u8]) {
-        hostcalls::set_buffer(BufferType::DownstreamData, start, size, value).unwrap()
+        self.try_set_downstream_data(start, size, value).unwrap()
+    }
+
+    fn try_set_downstream_data(
+        &self,
+        start: usize,
+        size: usize,
+        value: &[u8],
+    ) -> Result<(), Error> {
+        hostcalls::try_set_buffer(BufferType::DownstreamData, start, size, value)
     }
 
     fn resume_downstream(&self) {
-        hostcalls::resume_downstream().unwrap()
+        self.try_resume_downstream().unwrap()
+    }
+
+    fn try_resume_downstream(&self) -> Result<(), Error> {
+        hostcalls::try_resume_downstream()
     }
 
     fn close_downstream(&self) {
-        hostcalls::close_downstream().unwrap()
+        self.try_close_downstream().unwrap()
+    }
+
+    fn try_close_downstream(&self) -> Result<(), Error> {
+        hostcalls::try_close_downstream()
     }
 
     fn on_downstream_close(&mut self, _peer_type: PeerType) {}
@@ -286,19 +628,35 @@ pub trait StreamContext: Context {
     }
 
     fn get_upstream_data(&self, start: usize, max_size: usize) -> Option<Bytes> {
-        hostcalls::get_buffer(BufferType::UpstreamData, start, max_size).unwrap()
+        self.try_get_upstream_data(start, max_size).unwrap()
+    }
+
+    fn try_get_upstream_data(&self, start: usize, max_size: usize) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_buffer(BufferType::UpstreamData, start, max_size)
     }
 
     fn set_upstream_data(&self, start: usize, size: usize, value: &[u8]) {
-        hostcalls::set_buffer(BufferType::UpstreamData, start, size, value).unwrap()
+        self.try_set_upstream_data(start, size, value).unwrap()
+    }
+
+    fn try_set_upstream_data(&self, start: usize, size: usize, value: &[u8]) -> Result<(), Error> {
+        hostcalls::try_set_buffer(BufferType::UpstreamData, start, size, value)
     }
 
     fn resume_upstream(&self) {
-        hostcalls::resume_upstream().unwrap()
+        self.try_resume_upstream().unwrap()
+    }
+
+    fn try_resume_upstream(&self) -> Result<(), Error> {
+        hostcalls::try_resume_upstream()
     }
 
     fn close_upstream(&self) {
-        hostcalls::close_upstream().unwrap()
+        self.try_close_upstream().unwrap()
+    }
+
+    fn try_close_upstream(&self) -> Result<(), Error> {
+        hostcalls::try_close_upstream()
     }
 
     fn on_upstream_close(&mut self, _peer_type: PeerType) {}
@@ -312,43 +670,95 @@ pub trait HttpContext: Context {
     }
 
     fn get_http_request_headers(&self) -> Vec<(String, String)> {
-        hostcalls::get_map(MapType::HttpRequestHeaders).unwrap()
+        self.try_get_http_request_headers().unwrap()
+    }
+
+    fn try_get_http_request_headers(&self) -> Result<Vec<(String, String)>, Error> {
+        hostcalls::try_get_map(MapType::HttpRequestHeaders)
     }
 
     fn get_http_request_headers_bytes(&self) -> Vec<(String, Bytes)> {
-        hostcalls::get_map_bytes(MapType::HttpRequestHeaders).unwrap()
+        self.try_get_http_request_headers_bytes().unwrap()
+    }
+
+    fn try_get_http_request_headers_bytes(&self) -> Result<Vec<(String, Bytes)>, Error> {
+        hostcalls::try_get_map_bytes(MapType::HttpRequestHeaders)
+    }
+
+    fn get_http_request_headers_map(&self) -> HeaderMap {
+        self.try_get_http_request_headers_map().unwrap()
+    }
+
+    fn try_get_http_request_headers_map(&self) -> Result<HeaderMap, Error> {
+        hostcalls::try_get_header_map(MapType::HttpRequestHeaders)
     }
 
     fn set_http_request_headers(&self, headers: Vec<(&str, &str)>) {
-        hostcalls::set_map(MapType::HttpRequestHeaders, headers).unwrap()
+        self.try_set_http_request_headers(headers).unwrap()
+    }
+
+    fn try_set_http_request_headers(&self, headers: Vec<(&str, &str)>) -> Result<(), Error> {
+        hostcalls::try_set_map(MapType::HttpRequestHeaders, headers)
     }
 
     fn set_http_request_headers_bytes(&self, headers: Vec<(&str, &[u8])>) {
-        hostcalls::set_map_bytes(MapType::HttpRequestHeaders, headers).unwrap()
+        self.try_set_http_request_headers_bytes(headers).unwrap()
+    }
+
+    fn try_set_http_request_headers_bytes(&self, headers: Vec<(&str, &[u8])>) -> Result<(), Error> {
+        hostcalls::try_set_map_bytes(MapType::HttpRequestHeaders, headers)
     }
 
     fn get_http_request_header(&self, name: &str) -> Option<String> {
-        hostcalls::get_map_value(MapType::HttpRequestHeaders, name).unwrap()
+        self.try_get_http_request_header(name).unwrap()
+    }
+
+    fn try_get_http_request_header(&self, name: &str) -> Result<Option<String>, Error> {
+        hostcalls::try_get_map_value(MapType::HttpRequestHeaders, name)
     }
 
     fn get_http_request_header_bytes(&self, name: &str) -> Option<Bytes> {
-        hostcalls::get_map_value_bytes(MapType::HttpRequestHeaders, name).unwrap()
+        self.try_get_http_request_header_bytes(name).unwrap()
+    }
+
+    fn try_get_http_request_header_bytes(&self, name: &str) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_map_value_bytes(MapType::HttpRequestHeaders, name)
     }
 
     fn set_http_request_header(&self, name: &str, value: Option<&str>) {
-        hostcalls::set_map_value(MapType::HttpRequestHeaders, name, value).unwrap()
+        self.try_set_http_request_header(name, value).unwrap()
+    }
+
+    fn try_set_http_request_header(&self, name: &str, value: Option<&str>) -> Result<(), Error> {
+        hostcalls::try_set_map_value(MapType::HttpRequestHeaders, name, value)
     }
 
     fn set_http_request_header_bytes(&self, name: &str, value: Option<&[u8]>) {
-        hostcalls::set_map_value_bytes(MapType::HttpRequestHeaders, name, value).unwrap()
+        self.try_set_http_request_header_bytes(name, value).unwrap()
+    }
+
+    fn try_set_http_request_header_bytes(
+        &self,
+        name: &str,
+        value: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        hostcalls::try_set_map_value_bytes(MapType::HttpRequestHeaders, name, value)
     }
 
     fn add_http_request_header(&self, name: &str, value: &str) {
-        hostcalls::add_map_value(MapType::HttpRequestHeaders, name, value).unwrap()
+        self.try_add_http_request_header(name, value).unwrap()
+    }
+
+    fn try_add_http_request_header(&self, name: &str, value: &str) -> Result<(), Error> {
+        hostcalls::try_add_map_value(MapType::HttpRequestHeaders, name, value)
     }
 
     fn add_http_request_header_bytes(&self, name: &str, value: &[u8]) {
-        hostcalls::add_map_value_bytes(MapType::HttpRequestHeaders, name, value).unwrap()
+        self.try_add_http_request_header_bytes(name, value).unwrap()
+    }
+
+    fn try_add_http_request_header_bytes(&self, name: &str, value: &[u8]) -> Result<(), Error> {
+        hostcalls::try_add_map_value_bytes(MapType::HttpRequestHeaders, name, value)
     }
 
     fn on_http_request_body(&mut self, _body_size: usize, _end_of_stream: bool) -> Action {
@@ -356,11 +766,28 @@ pub trait HttpContext: Context {
     }
 
     fn get_http_request_body(&self, start: usize, max_size: usize) -> Option<Bytes> {
-        hostcalls::get_buffer(BufferType::HttpRequestBody, start, max_size).unwrap()
+        self.try_get_http_request_body(start, max_size).unwrap()
+    }
+
+    fn try_get_http_request_body(
+        &self,
+        start: usize,
+        max_size: usize,
+    ) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_buffer(BufferType::HttpRequestBody, start, max_size)
     }
 
     fn set_http_request_body(&self, start: usize, size: usize, value: &[u8]) {
-        hostcalls::set_buffer(BufferType::HttpRequestBody, start, size, value).unwrap()
+        self.try_set_http_request_body(start, size, value).unwrap()
+    }
+
+    fn try_set_http_request_body(
+        &self,
+        start: usize,
+        size: usize,
+        value: &[u8],
+    ) -> Result<(), Error> {
+        hostcalls::try_set_buffer(BufferType::HttpRequestBody, start, size, value)
     }
 
     fn on_http_request_trailers(&mut self, _num_trailers: usize) -> Action {
@@ -368,51 +795,116 @@ pub trait HttpContext: Context {
     }
 
     fn get_http_request_trailers(&self) -> Vec<(String, String)> {
-        hostcalls::get_map(MapType::HttpRequestTrailers).unwrap()
+        self.try_get_http_request_trailers().unwrap()
+    }
+
+    fn try_get_http_request_trailers(&self) -> Result<Vec<(String, String)>, Error> {
+        hostcalls::try_get_map(MapType::HttpRequestTrailers)
     }
 
     fn get_http_request_trailers_bytes(&self) -> Vec<(String, Bytes)> {
-        hostcalls::get_map_bytes(MapType::HttpRequestTrailers).unwrap()
+        self.try_get_http_request_trailers_bytes().unwrap()
+    }
+
+    fn try_get_http_request_trailers_bytes(&self) -> Result<Vec<(String, Bytes)>, Error> {
+        hostcalls::try_get_map_bytes(MapType::HttpRequestTrailers)
+    }
+
+    fn get_http_request_trailers_map(&self) -> HeaderMap {
+        self.try_get_http_request_trailers_map().unwrap()
+    }
+
+    fn try_get_http_request_trailers_map(&self) -> Result<HeaderMap, Error> {
+        hostcalls::try_get_header_map(MapType::HttpRequestTrailers)
     }
 
     fn set_http_request_trailers(&self, trailers: Vec<(&str, &str)>) {
-        hostcalls::set_map(MapType::HttpRequestTrailers, trailers).unwrap()
+        self.try_set_http_request_trailers(trailers).unwrap()
+    }
+
+    fn try_set_http_request_trailers(&self, trailers: Vec<(&str, &str)>) -> Result<(), Error> {
+        hostcalls::try_set_map(MapType::HttpRequestTrailers, trailers)
     }
 
     fn set_http_request_trailers_bytes(&self, trailers: Vec<(&str, &[u8])>) {
-        hostcalls::set_map_bytes(MapType::HttpRequestTrailers, trailers).unwrap()
+        self.try_set_http_request_trailers_bytes(trailers).unwrap()
+    }
+
+    fn try_set_http_request_trailers_bytes(
+        &self,
+        trailers: Vec<(&str, &[u8])>,
+    ) -> Result<(), Error> {
+        hostcalls::try_set_map_bytes(MapType::HttpRequestTrailers, trailers)
     }
 
     fn get_http_request_trailer(&self, name: &str) -> Option<String> {
-        hostcalls::get_map_value(MapType::HttpRequestTrailers, name).unwrap()
+        self.try_get_http_request_trailer(name).unwrap()
+    }
+
+    fn try_get_http_request_trailer(&self, name: &str) -> Result<Option<String>, Error> {
+        hostcalls::try_get_map_value(MapType::HttpRequestTrailers, name)
     }
 
     fn get_http_request_trailer_bytes(&self, name: &str) -> Option<Bytes> {
-        hostcalls::get_map_value_bytes(MapType::HttpRequestTrailers, name).unwrap()
+        self.try_get_http_request_trailer_bytes(name).unwrap()
+    }
+
+    fn try_get_http_request_trailer_bytes(&self, name: &str) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_map_value_bytes(MapType::HttpRequestTrailers, name)
     }
 
     fn set_http_request_trailer(&self, name: &str, value: Option<&str>) {
-        hostcalls::set_map_value(MapType::HttpRequestTrailers, name, value).unwrap()
+        self.try_set_http_request_trailer(name, value).unwrap()
+    }
+
+    fn try_set_http_request_trailer(&self, name: &str, value: Option<&str>) -> Result<(), Error> {
+        hostcalls::try_set_map_value(MapType::HttpRequestTrailers, name, value)
     }
 
     fn set_http_request_trailer_bytes(&self, name: &str, value: Option<&[u8]>) {
-        hostcalls::set_map_value_bytes(MapType::HttpRequestTrailers, name, value).unwrap()
+        self.try_set_http_request_trailer_bytes(name, value)
+            .unwrap()
+    }
+
+    fn try_set_http_request_trailer_bytes(
+        &self,
+        name: &str,
+        value: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        hostcalls::try_set_map_value_bytes(MapType::HttpRequestTrailers, name, value)
     }
 
     fn add_http_request_trailer(&self, name: &str, value: &str) {
-        hostcalls::add_map_value(MapType::HttpRequestTrailers, name, value).unwrap()
+        self.try_add_http_request_trailer(name, value).unwrap()
+    }
+
+    fn try_add_http_request_trailer(&self, name: &str, value: &str) -> Result<(), Error> {
+        hostcalls::try_add_map_value(MapType::HttpRequestTrailers, name, value)
     }
 
     fn add_http_request_trailer_bytes(&self, name: &str, value: &[u8]) {
-        hostcalls::add_map_value_bytes(MapType::HttpRequestTrailers, name, value).unwrap()
+        self.try_add_http_request_trailer_bytes(name, value)
+            .unwrap()
+    }
+
+    fn try_add_http_request_trailer_bytes(&self, name: &str, value: &[u8]) -> Result<(), Error> {
+        hostcalls::try_add_map_value_bytes(MapType::HttpRequestTrailers, name, value)
     }
 
     fn resume_http_request(&self) {
-        hostcalls::resume_http_request().unwrap()
+        self.try_resume_http_request().unwrap()
+    }
+
+    fn try_resume_http_request(&self) -> Result<(), Error> {
+        hostcalls::try_resume_http_request()
     }
 
     fn reset_http_request(&self) {
-        hostcalls::reset_http_request().unwrap()
+        self.try_reset_http_request().unwrap()
+    }
+
+    fn try_reset_http_request(&self) -> Result<(), Error> {
+        hostcalls::try_reset_http_request()
     }
 
     fn on_http_response_headers(&mut self, _num_headers: usize, _end_of_stream: bool) -> Action {
@@ -420,43 +912,125 @@ pub trait HttpContext: Context {
     }
 
     fn get_http_response_headers(&self) -> Vec<(String, String)> {
-        hostcalls::get_map(MapType::HttpResponseHeaders).unwrap()
+        self.try_get_http_response_headers().unwrap()
+    }
+
+    fn try_get_http_response_headers(&self) -> Result<Vec<(String, String)>, Error> {
+        hostcalls::try_get_map(MapType::HttpResponseHeaders)
     }
 
     fn get_http_response_headers_bytes(&self) -> Vec<(String, Bytes)> {
-        hostcalls::get_map_bytes(MapType::HttpResponseHeaders).unwrap()
+        self.try_get_http_response_headers_bytes().unwrap()
+    }
+
+    fn try_get_http_response_headers_bytes(&self) -> Result<Vec<(String, Bytes)>, Error> {
+        hostcalls::try_get_map_bytes(MapType::HttpResponseHeaders)
+    }
+
+    fn get_http_response_headers_map(&self) -> HeaderMap {
+        self.try_get_http_response_headers_map().unwrap()
+    }
+
+    fn try_get_http_response_headers_map(&self) -> Result<HeaderMap, Error> {
+        hostcalls::try_get_header_map(MapType::HttpResponseHeaders)
     }
 
     fn set_http_response_headers(&self, headers: Vec<(&str, &str)>) {
-        hostcalls::set_map(MapType::HttpResponseHeaders, headers).unwrap()
+        self.try_set_http_response_headers(headers).unwrap()
+    }
+
+    fn try_set_http_response_headers(&self, headers: Vec<(&str, &str)>) -> Result<(), Error> {
+        hostcalls::try_set_map(MapType::HttpResponseHeaders, headers)
     }
 
     fn set_http_response_headers_bytes(&self, headers: Vec<(&str, &[u8])>) {
-        hostcalls::set_map_bytes(MapType::HttpResponseHeaders, headers).unwrap()
+        self.try_set_http_response_headers_bytes(headers).unwrap()
+    }
+
+    fn try_set_http_response_headers_bytes(
+        &self,
+        headers: Vec<(&str, &[u8])>,
+    ) -> Result<(), Error> {
+        hostcalls::try_set_map_bytes(MapType::HttpResponseHeaders, headers)
     }
 
     fn get_http_response_header(&self, name: &str) -> Option<String> {
-        hostcalls::get_map_value(MapType::HttpResponseHeaders, name).unwrap()
+        self.try_get_http_response_header(name).unwrap()
+    }
+
+    fn try_get_http_response_header(&self, name: &str) -> Result<Option<String>, Error> {
+        hostcalls::try_get_map_value(MapType::HttpResponseHeaders, name)
     }
 
     fn get_http_response_header_bytes(&self, name: &str) -> Option<Bytes> {
-        hostcalls::get_map_value_bytes(MapType::HttpResponseHeaders, name).unwrap()
+        self.try_get_http_response_header_bytes(name).unwrap()
+    }
+
+    fn try_get_http_response_header_bytes(&self, name: &str) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_map_value_bytes(MapType::HttpResponseHeaders, name)
     }
 
     fn set_http_response_header(&self, name: &str, value: Option<&str>) {
-        hostcalls::set_map_value(MapType::HttpResponseHeaders, name, value).unwrap()
+        self.try_set_http_response_header(name, value).unwrap()
+    }
+
+    fn try_set_http_response_header(&self, name: &str, value: Option<&str>) -> Result<(), Error> {
+        hostcalls::try_set_map_value(MapType::HttpResponseHeaders, name, value)
     }
 
     fn set_http_response_header_bytes(&self, name: &str, value: Option<&[u8]>) {
-        hostcalls::set_map_value_bytes(MapType::HttpResponseHeaders, name, value).unwrap()
+        self.try_set_http_response_header_bytes(name, value)
+            .unwrap()
+    }
+
+    fn try_set_http_response_header_bytes(
+        &self,
+        name: &str,
+        value: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        hostcalls::try_set_map_value_bytes(MapType::HttpResponseHeaders, name, value)
     }
 
     fn add_http_response_header(&self, name: &str, value: &str) {
-        hostcalls::add_map_value(MapType::HttpResponseHeaders, name, value).unwrap()
+        self.try_add_http_response_header(name, value).unwrap()
+    }
+
+    fn try_add_http_response_header(&self, name: &str, value: &str) -> Result<(), Error> {
+        hostcalls::try_add_map_value(MapType::HttpResponseHeaders, name, value)
     }
 
     fn add_http_response_header_bytes(&self, name: &str, value: &[u8]) {
-        hostcalls::add_map_value_bytes(MapType::HttpResponseHeaders, name, value).unwrap()
+        self.try_add_http_response_header_bytes(name, value)
+            .unwrap()
+    }
+
+    fn try_add_http_response_header_bytes(&self, name: &str, value: &[u8]) -> Result<(), Error> {
+        hostcalls::try_add_map_value_bytes(MapType::HttpResponseHeaders, name, value)
+    }
+
+    /// Sets a response header, and records the case of `name` in the
+    /// [`header_case::HEADER_CASE_HEADER`] companion header, since the host
+    /// lowercases header names.
+    fn set_http_response_header_with_case(&self, name: &str, value: Option<&str>) {
+        self.try_set_http_response_header_with_case(name, value)
+            .unwrap()
+    }
+
+    fn try_set_http_response_header_with_case(
+        &self,
+        name: &str,
+        value: Option<&str>,
+    ) -> Result<(), Error> {
+        header_case::set_header_with_case(MapType::HttpResponseHeaders, name, value)
+    }
+
+    fn add_http_response_header_with_case(&self, name: &str, value: &str) {
+        self.try_add_http_response_header_with_case(name, value)
+            .unwrap()
+    }
+
+    fn try_add_http_response_header_with_case(&self, name: &str, value: &str) -> Result<(), Error> {
+        header_case::add_header_with_case(MapType::HttpResponseHeaders, name, value)
     }
 
     fn on_http_response_body(&mut self, _body_size: usize, _end_of_stream: bool) -> Action {
@@ -464,11 +1038,28 @@ pub trait HttpContext: Context {
     }
 
     fn get_http_response_body(&self, start: usize, max_size: usize) -> Option<Bytes> {
-        hostcalls::get_buffer(BufferType::HttpResponseBody, start, max_size).unwrap()
+        self.try_get_http_response_body(start, max_size).unwrap()
+    }
+
+    fn try_get_http_response_body(
+        &self,
+        start: usize,
+        max_size: usize,
+    ) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_buffer(BufferType::HttpResponseBody, start, max_size)
     }
 
     fn set_http_response_body(&self, start: usize, size: usize, value: &[u8]) {
-        hostcalls::set_buffer(BufferType::HttpResponseBody, start, size, value).unwrap()
+        self.try_set_http_response_body(start, size, value).unwrap()
+    }
+
+    fn try_set_http_response_body(
+        &self,
+        start: usize,
+        size: usize,
+        value: &[u8],
+    ) -> Result<(), Error> {
+        hostcalls::try_set_buffer(BufferType::HttpResponseBody, start, size, value)
     }
 
     fn on_http_response_trailers(&mut self, _num_trailers: usize) -> Action {
@@ -476,51 +1067,116 @@ pub trait HttpContext: Context {
     }
 
     fn get_http_response_trailers(&self) -> Vec<(String, String)> {
-        hostcalls::get_map(MapType::HttpResponseTrailers).unwrap()
+        self.try_get_http_response_trailers().unwrap()
+    }
+
+    fn try_get_http_response_trailers(&self) -> Result<Vec<(String, String)>, Error> {
+        hostcalls::try_get_map(MapType::HttpResponseTrailers)
     }
 
     fn get_http_response_trailers_bytes(&self) -> Vec<(String, Bytes)> {
-        hostcalls::get_map_bytes(MapType::HttpResponseTrailers).unwrap()
+        self.try_get_http_response_trailers_bytes().unwrap()
+    }
+
+    fn try_get_http_response_trailers_bytes(&self) -> Result<Vec<(String, Bytes)>, Error> {
+        hostcalls::try_get_map_bytes(MapType::HttpResponseTrailers)
+    }
+
+    fn get_http_response_trailers_map(&self) -> HeaderMap {
+        self.try_get_http_response_trailers_map().unwrap()
+    }
+
+    fn try_get_http_response_trailers_map(&self) -> Result<HeaderMap, Error> {
+        hostcalls::try_get_header_map(MapType::HttpResponseTrailers)
     }
 
     fn set_http_response_trailers(&self, trailers: Vec<(&str, &str)>) {
-        hostcalls::set_map(MapType::HttpResponseTrailers, trailers).unwrap()
+        self.try_set_http_response_trailers(trailers).unwrap()
+    }
+
+    fn try_set_http_response_trailers(&self, trailers: Vec<(&str, &str)>) -> Result<(), Error> {
+        hostcalls::try_set_map(MapType::HttpResponseTrailers, trailers)
     }
 
     fn set_http_response_trailers_bytes(&self, trailers: Vec<(&str, &[u8])>) {
-        hostcalls::set_map_bytes(MapType::HttpResponseTrailers, trailers).unwrap()
+        self.try_set_http_response_trailers_bytes(trailers).unwrap()
+    }
+
+    fn try_set_http_response_trailers_bytes(
+        &self,
+        trailers: Vec<(&str, &[u8])>,
+    ) -> Result<(), Error> {
+        hostcalls::try_set_map_bytes(MapType::HttpResponseTrailers, trailers)
     }
 
     fn get_http_response_trailer(&self, name: &str) -> Option<String> {
-        hostcalls::get_map_value(MapType::HttpResponseTrailers, name).unwrap()
+        self.try_get_http_response_trailer(name).unwrap()
+    }
+
+    fn try_get_http_response_trailer(&self, name: &str) -> Result<Option<String>, Error> {
+        hostcalls::try_get_map_value(MapType::HttpResponseTrailers, name)
     }
 
     fn get_http_response_trailer_bytes(&self, name: &str) -> Option<Bytes> {
-        hostcalls::get_map_value_bytes(MapType::HttpResponseTrailers, name).unwrap()
+        self.try_get_http_response_trailer_bytes(name).unwrap()
+    }
+
+    fn try_get_http_response_trailer_bytes(&self, name: &str) -> Result<Option<Bytes>, Error> {
+        hostcalls::try_get_map_value_bytes(MapType::HttpResponseTrailers, name)
     }
 
     fn set_http_response_trailer(&self, name: &str, value: Option<&str>) {
-        hostcalls::set_map_value(MapType::HttpResponseTrailers, name, value).unwrap()
+        self.try_set_http_response_trailer(name, value).unwrap()
+    }
+
+    fn try_set_http_response_trailer(&self, name: &str, value: Option<&str>) -> Result<(), Error> {
+        hostcalls::try_set_map_value(MapType::HttpResponseTrailers, name, value)
     }
 
     fn set_http_response_trailer_bytes(&self, name: &str, value: Option<&[u8]>) {
-        hostcalls::set_map_value_bytes(MapType::HttpResponseTrailers, name, value).unwrap()
+        self.try_set_http_response_trailer_bytes(name, value)
+            .unwrap()
+    }
+
+    fn try_set_http_response_trailer_bytes(
+        &self,
+        name: &str,
+        value: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        hostcalls::try_set_map_value_bytes(MapType::HttpResponseTrailers, name, value)
     }
 
     fn add_http_response_trailer(&self, name: &str, value: &str) {
-        hostcalls::add_map_value(MapType::HttpResponseTrailers, name, value).unwrap()
+        self.try_add_http_response_trailer(name, value).unwrap()
+    }
+
+    fn try_add_http_response_trailer(&self, name: &str, value: &str) -> Result<(), Error> {
+        hostcalls::try_add_map_value(MapType::HttpResponseTrailers, name, value)
     }
 
     fn add_http_response_trailer_bytes(&self, name: &str, value: &[u8]) {
-        hostcalls::add_map_value_bytes(MapType::HttpResponseTrailers, name, value).unwrap()
+        self.try_add_http_response_trailer_bytes(name, value)
+            .unwrap()
+    }
+
+    fn try_add_http_response_trailer_bytes(&self, name: &str, value: &[u8]) -> Result<(), Error> {
+        hostcalls::try_add_map_value_bytes(MapType::HttpResponseTrailers, name, value)
     }
 
     fn resume_http_response(&self) {
-        hostcalls::resume_http_response().unwrap()
+        self.try_resume_http_response().unwrap()
+    }
+
+    fn try_resume_http_response(&self) -> Result<(), Error> {
+        hostcalls::try_resume_http_response()
     }
 
     fn reset_http_response(&self) {
-        hostcalls::reset_http_response().unwrap()
+        self.try_reset_http_response().unwrap()
+    }
+
+    fn try_reset_http_response(&self) -> Result<(), Error> {
+        hostcalls::try_reset_http_response()
     }
 
     fn send_http_response(
@@ -529,7 +1185,17 @@ pub trait HttpContext: Context {
         headers: Vec<(&str, &str)>,
         body: Option<&[u8]>,
     ) {
-        hostcalls::send_http_response(status_code, headers, body).unwrap()
+        self.try_send_http_response(status_code, headers, body)
+            .unwrap()
+    }
+
+    fn try_send_http_response(
+        &self,
+        status_code: u32,
+        headers: Vec<(&str, &str)>,
+        body: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        hostcalls::try_send_http_response(status_code, headers, body)
     }
 
     fn send_grpc_response(
@@ -538,7 +1204,115 @@ pub trait HttpContext: Context {
         grpc_status_message: Option<&str>,
         custom_metadata: Vec<(&str, &[u8])>,
     ) {
-        hostcalls::send_grpc_response(grpc_status, grpc_status_message, custom_metadata).unwrap()
+        self.try_send_grpc_response(grpc_status, grpc_status_message, custom_metadata)
+            .unwrap()
+    }
+
+    fn try_send_grpc_response(
+        &self,
+        grpc_status: GrpcStatusCode,
+        grpc_status_message: Option<&str>,
+        custom_metadata: Vec<(&str, &[u8])>,
+    ) -> Result<(), Error> {
+        hostcalls::try_send_grpc_response(grpc_status, grpc_status_message, custom_metadata)
+    }
+
+    /// Same as `send_http_response`, but also sets the reason for the
+    /// response, reported by Envoy as `%RESPONSE_CODE_DETAILS%`. Envoy
+    /// expects it to contain no whitespace.
+    fn send_http_response_with_code_details(
+        &self,
+        status_code: u32,
+        status_code_details: &str,
+        headers: Vec<(&str, &str)>,
+        body: Option<&[u8]>,
+    ) {
+        self.try_send_http_response_with_code_details(
+            status_code,
+            status_code_details,
+            headers,
+            body,
+        )
+        .unwrap()
+    }
+
+    fn try_send_http_response_with_code_details(
+        &self,
+        status_code: u32,
+        status_code_details: &str,
+        headers: Vec<(&str, &str)>,
+        body: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        hostcalls::try_send_http_response_with_code_details(
+            status_code,
+            Some(status_code_details),
+            headers,
+            body,
+        )
+    }
+
+    /// Same as `send_grpc_response`, but also sets the reason for the
+    /// response, reported by Envoy as `%RESPONSE_CODE_DETAILS%`.
+    fn send_grpc_response_with_code_details(
+        &self,
+        grpc_status: GrpcStatusCode,
+        grpc_status_message: Option<&str>,
+        status_code_details: &str,
+        custom_metadata: Vec<(&str, &[u8])>,
+    ) {
+        self.try_send_grpc_response_with_code_details(
+            grpc_status,
+            grpc_status_message,
+            status_code_details,
+            custom_metadata,
+        )
+        .unwrap()
+    }
+
+    fn try_send_grpc_response_with_code_details(
+        &self,
+        grpc_status: GrpcStatusCode,
+        grpc_status_message: Option<&str>,
+        status_code_details: &str,
+        custom_metadata: Vec<(&str, &[u8])>,
+    ) -> Result<(), Error> {
+        hostcalls::try_send_grpc_response_with_code_details(
+            grpc_status,
+            grpc_status_message,
+            Some(status_code_details),
+            custom_metadata,
+        )
+    }
+
+    /// Same as `send_grpc_response`, but also sends the `details` of the
+    /// error in the `grpc-status-details-bin` trailer.
+    fn send_grpc_response_with_details(
+        &self,
+        grpc_status: GrpcStatusCode,
+        grpc_status_message: Option<&str>,
+        details: &[ErrorDetail],
+        custom_metadata: Vec<(&str, &[u8])>,
+    ) {
+        self.try_send_grpc_response_with_details(
+            grpc_status,
+            grpc_status_message,
+            details,
+            custom_metadata,
+        )
+        .unwrap()
+    }
+
+    fn try_send_grpc_response_with_details(
+        &self,
+        grpc_status: GrpcStatusCode,
+        grpc_status_message: Option<&str>,
+        details: &[ErrorDetail],
+        custom_metadata: Vec<(&str, &[u8])>,
+    ) -> Result<(), Error> {
+        let status_details = grpc::encode_status_details(grpc_status, grpc_status_message, details);
+        let mut metadata = custom_metadata;
+        metadata.push((grpc::STATUS_DETAILS_HEADER, status_details.as_bytes()));
+        hostcalls::try_send_grpc_response(grpc_status, grpc_status_message, metadata)
     }
 
     fn on_log(&mut self) {}
@@ -13,6 +13,9 @@
 // limitations under the License.
 
 use crate::traits::*;
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::Duration;
 
 pub type NewRootContext = fn(context_id: u32) -> Box<dyn RootContext>;
 pub type NewStreamContext = fn(context_id: u32, root_context_id: u32) -> Box<dyn StreamContext>;
@@ -46,9 +49,14 @@ pub enum Status {
     BadArgument = 2,
     SerializationFailure = 3,
     ParseFailure = 4,
+    BadExpression = 5,
+    InvalidMemoryAccess = 6,
     Empty = 7,
     CasMismatch = 8,
+    ResultMismatch = 9,
     InternalFailure = 10,
+    BrokenConnection = 11,
+    Unimplemented = 12,
 }
 
 #[repr(u32)]
@@ -81,6 +89,26 @@ pub enum BufferType {
     GrpcReceiveBuffer = 5,
     VmConfiguration = 6,
     PluginConfiguration = 7,
+    CallData = 8,
+}
+
+/// Flags returned with the size of a buffer by `proxy_get_buffer_status`.
+/// ABI 0.2.1 doesn't define any of them, and Envoy always returns none.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct BufferFlags(u32);
+
+impl BufferFlags {
+    pub fn from_bits(bits: u32) -> BufferFlags {
+        BufferFlags(bits)
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(&self, other: BufferFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
 }
 
 #[repr(u32)]
@@ -138,4 +166,584 @@ pub enum GrpcStatusCode {
     Unauthenticated = 16,
 }
 
+impl GrpcStatusCode {
+    // Codes that aren't known to this version of the SDK are mapped to
+    // `GrpcStatusCode::Unknown`.
+    pub(crate) fn from_code(code: u32) -> GrpcStatusCode {
+        GrpcStatusCode::try_from(code).unwrap_or(GrpcStatusCode::Unknown)
+    }
+
+    /// Maps the status of an HTTP response without `grpc-status` to a gRPC
+    /// status code, as specified by gRPC's HTTP to gRPC status code mapping.
+    pub fn from_http_status(status_code: u32) -> GrpcStatusCode {
+        match status_code {
+            400 => GrpcStatusCode::Internal,
+            401 => GrpcStatusCode::Unauthenticated,
+            403 => GrpcStatusCode::PermissionDenied,
+            404 => GrpcStatusCode::Unimplemented,
+            429 | 502 | 503 | 504 => GrpcStatusCode::Unavailable,
+            _ => GrpcStatusCode::Unknown,
+        }
+    }
+}
+
+// Returns the unknown code as the error.
+impl TryFrom<u32> for GrpcStatusCode {
+    type Error = u32;
+
+    fn try_from(code: u32) -> Result<Self, Self::Error> {
+        match code {
+            0 => Ok(GrpcStatusCode::Ok),
+            1 => Ok(GrpcStatusCode::Cancelled),
+            2 => Ok(GrpcStatusCode::Unknown),
+            3 => Ok(GrpcStatusCode::InvalidArgument),
+            4 => Ok(GrpcStatusCode::DeadlineExceeded),
+            5 => Ok(GrpcStatusCode::NotFound),
+            6 => Ok(GrpcStatusCode::AlreadyExists),
+            7 => Ok(GrpcStatusCode::PermissionDenied),
+            8 => Ok(GrpcStatusCode::ResourceExhausted),
+            9 => Ok(GrpcStatusCode::FailedPrecondition),
+            10 => Ok(GrpcStatusCode::Aborted),
+            11 => Ok(GrpcStatusCode::OutOfRange),
+            12 => Ok(GrpcStatusCode::Unimplemented),
+            13 => Ok(GrpcStatusCode::Internal),
+            14 => Ok(GrpcStatusCode::Unavailable),
+            15 => Ok(GrpcStatusCode::DataLoss),
+            16 => Ok(GrpcStatusCode::Unauthenticated),
+            code => Err(code),
+        }
+    }
+}
+
 pub type Bytes = Vec<u8>;
+
+/// What happens when an HTTP context panics while handling a callback.
+///
+/// With `Isolate`, the panic is logged with the context id, the configured
+/// local response is sent to the client, and no further callbacks are routed
+/// to the poisoned context, while other contexts keep running. Unwinding is
+/// only possible when the plugin is built with `panic = "unwind"`; with
+/// `panic = "abort"` the local response is sent right before the VM traps.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum PanicPolicy {
+    #[default]
+    Abort,
+    Isolate {
+        status_code: u32,
+        headers: Vec<(String, String)>,
+        body: Option<Bytes>,
+    },
+}
+
+/// How to handle values that are not valid UTF-8 when they are returned as
+/// `String` (e.g. by `get_http_request_headers`). The `_bytes` variants and
+/// `HeaderMap` always return values as they are.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Utf8Policy {
+    /// Return `Error::InvalidUtf8` from `try_*` helpers (and panic otherwise).
+    Error,
+    /// Replace invalid sequences with U+FFFD.
+    #[default]
+    Lossy,
+    /// Ignore the value, i.e. skip map entries and return `None` otherwise.
+    Skip,
+}
+
+/// Pseudo-headers are validated when the request is dispatched: `:method`,
+/// `:path` and `:authority` are required, and each one can be set only once,
+/// either with the dedicated setter or with `header()`. The timeout defaults to
+/// 5 seconds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpCallRequest {
+    upstream: String,
+    method: Option<String>,
+    path: Option<String>,
+    authority: Option<String>,
+    scheme: Option<String>,
+    headers: Vec<(String, String)>,
+    body: Option<Bytes>,
+    trailers: Vec<(String, String)>,
+    timeout: Duration,
+}
+
+impl HttpCallRequest {
+    pub fn new(upstream: &str) -> HttpCallRequest {
+        HttpCallRequest {
+            upstream: upstream.to_string(),
+            method: None,
+            path: None,
+            authority: None,
+            scheme: None,
+            headers: Vec::new(),
+            body: None,
+            trailers: Vec::new(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    pub fn method(mut self, method: &str) -> HttpCallRequest {
+        self.method = Some(method.to_string());
+        self
+    }
+
+    pub fn path(mut self, path: &str) -> HttpCallRequest {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn authority(mut self, authority: &str) -> HttpCallRequest {
+        self.authority = Some(authority.to_string());
+        self
+    }
+
+    pub fn scheme(mut self, scheme: &str) -> HttpCallRequest {
+        self.scheme = Some(scheme.to_string());
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> HttpCallRequest {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: &[u8]) -> HttpCallRequest {
+        self.body = Some(body.to_vec());
+        self
+    }
+
+    pub fn trailer(mut self, name: &str, value: &str) -> HttpCallRequest {
+        self.trailers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> HttpCallRequest {
+        self.timeout = timeout;
+        self
+    }
+
+    pub(crate) fn get_upstream(&self) -> &str {
+        &self.upstream
+    }
+
+    pub(crate) fn get_headers(&self) -> Result<Vec<(&str, &str)>, Error> {
+        let mut headers = Vec::with_capacity(self.headers.len() + 4);
+        for (name, value) in [
+            (":method", &self.method),
+            (":path", &self.path),
+            (":authority", &self.authority),
+            (":scheme", &self.scheme),
+        ] {
+            if let Some(value) = value {
+                headers.push((name, value.as_str()));
+            }
+        }
+        headers.extend(
+            self.headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        );
+        for name in [":method", ":path", ":authority", ":scheme"] {
+            match headers.iter().filter(|(key, _)| *key == name).count() {
+                0 if name != ":scheme" => {
+                    return Err(Error::InvalidRequest {
+                        reason: format!("missing {} header", name),
+                    })
+                }
+                0 | 1 => {}
+                _ => {
+                    return Err(Error::InvalidRequest {
+                        reason: format!("duplicate {} header", name),
+                    })
+                }
+            }
+        }
+        Ok(headers)
+    }
+
+    pub(crate) fn get_body(&self) -> Option<&[u8]> {
+        self.body.as_deref()
+    }
+
+    pub(crate) fn get_trailers(&self) -> Vec<(&str, &str)> {
+        self.trailers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect()
+    }
+
+    pub(crate) fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+/// Failed calls (e.g. timeouts or connection failures) are reported without any
+/// headers, and therefore without a status code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpCallResponse {
+    status_code: Option<u32>,
+    headers: Vec<(String, Bytes)>,
+    body: Option<Bytes>,
+    trailers: Vec<(String, Bytes)>,
+}
+
+impl HttpCallResponse {
+    pub(crate) fn new(
+        headers: Vec<(String, Bytes)>,
+        body: Option<Bytes>,
+        trailers: Vec<(String, Bytes)>,
+    ) -> HttpCallResponse {
+        let status_code = find_value(&headers, ":status")
+            .and_then(|value| std::str::from_utf8(value).ok())
+            .and_then(|value| value.parse().ok());
+        HttpCallResponse {
+            status_code,
+            headers,
+            body,
+            trailers,
+        }
+    }
+
+    pub fn status_code(&self) -> Option<u32> {
+        self.status_code
+    }
+
+    pub fn headers(&self) -> &[(String, Bytes)] {
+        &self.headers
+    }
+
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        find_value(&self.headers, name)
+    }
+
+    pub fn body(&self) -> Option<&[u8]> {
+        self.body.as_deref()
+    }
+
+    pub fn trailers(&self) -> &[(String, Bytes)] {
+        &self.trailers
+    }
+
+    pub fn trailer(&self, name: &str) -> Option<&[u8]> {
+        find_value(&self.trailers, name)
+    }
+}
+
+fn find_value<'a>(map: &'a [(String, Bytes)], name: &str) -> Option<&'a [u8]> {
+    map.iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_slice())
+}
+
+/// Header map backed by the buffer returned by the host, in the Proxy-Wasm
+/// serialization format. The buffer is validated once, when the map is created,
+/// and entries are decoded on demand, without copying names or values.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    bytes: Bytes,
+}
+
+impl HeaderMap {
+    pub(crate) fn from_bytes(bytes: Bytes) -> Result<HeaderMap, Error> {
+        let map = HeaderMap { bytes };
+        let mut entries = map.entries();
+        while let Some(entry) = entries.next_raw() {
+            let (name, _) = entry?;
+            if std::str::from_utf8(name).is_err() {
+                return Err(Error::InvalidHeaderMap {
+                    reason: "header name is not valid UTF-8",
+                });
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn len(&self) -> usize {
+        match self.bytes.get(0..4) {
+            Some(size) => u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> HeaderMapIter<'_> {
+        HeaderMapIter {
+            entries: self.entries(),
+        }
+    }
+
+    /// Names are compared case-insensitively.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.get(name)
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    fn entries(&self) -> RawEntries<'_> {
+        let len = self.len();
+        // The count comes from the host, so the size table might not fit in
+        // the buffer, or even in a `usize`.
+        let offset = len
+            .checked_mul(8)
+            .and_then(|size| size.checked_add(4))
+            .filter(|offset| *offset <= self.bytes.len());
+        RawEntries {
+            bytes: &self.bytes,
+            index: 0,
+            len,
+            offset,
+        }
+    }
+}
+
+impl fmt::Debug for HeaderMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.iter()
+                    .map(|(name, value)| (name, String::from_utf8_lossy(value))),
+            )
+            .finish()
+    }
+}
+
+impl<'a> IntoIterator for &'a HeaderMap {
+    type Item = (&'a str, &'a [u8]);
+    type IntoIter = HeaderMapIter<'a>;
+
+    fn into_iter(self) -> HeaderMapIter<'a> {
+        self.iter()
+    }
+}
+
+pub struct HeaderMapIter<'a> {
+    entries: RawEntries<'a>,
+}
+
+impl<'a> Iterator for HeaderMapIter<'a> {
+    type Item = (&'a str, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // The map was validated when it was created.
+        let (name, value) = self.entries.next_raw()?.ok()?;
+        Some((unsafe { std::str::from_utf8_unchecked(name) }, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.entries.len - self.entries.index;
+        (remaining, Some(remaining))
+    }
+}
+
+type RawEntry<'a> = (&'a [u8], &'a [u8]);
+
+struct RawEntries<'a> {
+    bytes: &'a [u8],
+    index: usize,
+    len: usize,
+    // Start of the next name, or `None` if the size table is truncated.
+    offset: Option<usize>,
+}
+
+impl<'a> RawEntries<'a> {
+    fn next_raw(&mut self) -> Option<Result<RawEntry<'a>, Error>> {
+        if self.index >= self.len {
+            return None;
+        }
+        let sizes = self
+            .index
+            .checked_mul(8)
+            .and_then(|size| size.checked_add(4));
+        self.index += 1;
+        let entry = sizes
+            .and_then(|sizes| self.bytes.get(sizes..sizes.checked_add(8)?))
+            .and_then(|sizes| {
+                let name_size = u32::from_le_bytes([sizes[0], sizes[1], sizes[2], sizes[3]]);
+                let value_size = u32::from_le_bytes([sizes[4], sizes[5], sizes[6], sizes[7]]);
+                let name_start = self.offset?;
+                let name_end = name_start.checked_add(name_size as usize)?;
+                let value_start = name_end.checked_add(1)?;
+                let value_end = value_start.checked_add(value_size as usize)?;
+                let name = self.bytes.get(name_start..name_end)?;
+                let value = self.bytes.get(value_start..value_end)?;
+                self.offset = Some(value_end.checked_add(1)?);
+                Some((name, value))
+            })
+            .ok_or(Error::InvalidHeaderMap {
+                reason: "truncated map",
+            });
+        if entry.is_err() {
+            self.index = self.len;
+        }
+        Some(entry)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[non_exhaustive]
+pub enum CancelReason {
+    Cancelled,
+    ContextDeleted,
+    TimedOut,
+}
+
+impl fmt::Display for CancelReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CancelReason::Cancelled => write!(f, "cancelled"),
+            CancelReason::ContextDeleted => write!(f, "context deleted"),
+            CancelReason::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    Hostcall {
+        name: &'static str,
+        status: Status,
+        args: String,
+    },
+    Grpc {
+        status_code: u32,
+        message: Option<String>,
+    },
+    InvalidRequest {
+        reason: String,
+    },
+    InvalidHeaderMap {
+        reason: &'static str,
+    },
+    InvalidUtf8 {
+        key: String,
+    },
+    InvalidProperty {
+        path: String,
+        reason: &'static str,
+    },
+    Codec {
+        reason: String,
+    },
+    JsonPatch {
+        reason: String,
+    },
+    Cancelled {
+        token_id: u32,
+        reason: CancelReason,
+    },
+}
+
+impl Error {
+    pub(crate) fn hostcall(name: &'static str, status: Status, args: String) -> Error {
+        Error::Hostcall { name, status, args }
+    }
+
+    pub fn status(&self) -> Option<Status> {
+        match self {
+            Error::Hostcall { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Returns the status of a failed gRPC call. Codes that aren't known to
+    /// this version of the SDK are returned as `GrpcStatusCode::Unknown`.
+    pub fn grpc_status_code(&self) -> Option<GrpcStatusCode> {
+        match self {
+            Error::Grpc { status_code, .. } => Some(GrpcStatusCode::from_code(*status_code)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Hostcall { name, status, args } => {
+                write!(f, "{}({}) failed with status {:?}", name, args, status)
+            }
+            Error::Grpc {
+                status_code,
+                message: Some(message),
+            } => write!(
+                f,
+                "gRPC call failed with status {}: {}",
+                status_code, message
+            ),
+            Error::Grpc { status_code, .. } => {
+                write!(f, "gRPC call failed with status {}", status_code)
+            }
+            Error::InvalidRequest { reason } => write!(f, "invalid request: {}", reason),
+            Error::InvalidHeaderMap { reason } => write!(f, "invalid header map: {}", reason),
+            Error::InvalidUtf8 { key } => write!(f, "invalid UTF-8 in {}", key),
+            Error::InvalidProperty { path, reason } => {
+                write!(f, "invalid property {}: {}", path, reason)
+            }
+            Error::Codec { reason } => write!(f, "codec error: {}", reason),
+            Error::JsonPatch { reason } => write!(f, "JSON patch failed: {}", reason),
+            Error::Cancelled { token_id, reason } => {
+                write!(f, "callout {} {}", token_id, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(map: &[(&str, &str)]) -> Bytes {
+        let mut bytes = (map.len() as u32).to_le_bytes().to_vec();
+        for (name, value) in map {
+            bytes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        }
+        for (name, value) in map {
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.push(0);
+            bytes.extend_from_slice(value.as_bytes());
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    #[test]
+    fn header_map() {
+        let map = HeaderMap::from_bytes(serialize(&[(":status", "200"), ("a", "1")])).unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get_str(":STATUS"), Some("200"));
+        assert_eq!(map.iter().count(), 2);
+    }
+
+    #[test]
+    fn header_map_with_invalid_count() {
+        let mut bytes = serialize(&[("a", "1")]);
+        bytes[0..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(HeaderMap::from_bytes(bytes).is_err());
+
+        let mut bytes = serialize(&[("a", "1")]);
+        bytes[0..4].copy_from_slice(&2u32.to_le_bytes());
+        assert!(HeaderMap::from_bytes(bytes).is_err());
+
+        assert!(HeaderMap::from_bytes(vec![1, 0, 0, 0]).is_err());
+    }
+}